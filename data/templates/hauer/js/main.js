@@ -118,10 +118,9 @@
 		let showingVideo = false;
 
 		function showPicture(collection, index, event) {
-			const isVideo = !!collection.pictures[index].video;
+			const isVideo = collection.pictures[index].kind === "video";
 			showingVideo = isVideo;
-			const path = collection.pictures[index].path;
-			const picture = isVideo ? "v/" + path : "p/" + path;
+			const picture = "p/" + collection.pictures[index].path;
 			curPictureIndex = index;
 			showing = true;
 
@@ -138,6 +137,7 @@
 			if (isVideo) {
 				img.style.display = "none";
 				vid.style.display = "block";
+				vid.poster = picture + ".disp." + config.extension;
 				vid.src = picture + "." + config.videoExtension;
 				vid.play();
 
@@ -240,7 +240,7 @@
 			clear(contentContainer);
 			collection.pictures.forEach((pic, index) => {
 				const title = pic.title;
-				const path = pic.video ? "v/" + pic.path : "p/" + pic.path;
+				const path = "p/" + pic.path;
 
 
 				const thumb = document.createElement("div");
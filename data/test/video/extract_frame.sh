@@ -0,0 +1,3 @@
+#!/bin/sh
+# Stands in for ffmpeg in the tests, "extracts" a fixed frame: extract_frame.sh <input> <output>
+cp "$(dirname "$0")/../orientation/orientation_1.jpg" "$2"
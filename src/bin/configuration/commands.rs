@@ -0,0 +1,206 @@
+use std::path::PathBuf;
+use structopt::StructOpt;
+use super::{Command, Configuration, InputOptions, RenderOptions};
+
+/// Generates static picture galleries using a template.
+///
+/// Exit codes are the same for all commands, see "static_gallery --help".
+#[derive(StructOpt, Debug)]
+#[structopt(name = "miGallery")]
+pub enum Cli {
+	/// Creates a new gallery without pictures
+	Init(Init),
+	/// Creates a new gallery from the given collections
+	Build(Build),
+	/// Adds collections, pictures and backgrounds to an existing gallery. Pictures already part of a collection are
+	/// not added again.
+	Add(Add),
+	/// Removes collections, pictures and backgrounds from an existing gallery and deletes their files
+	Remove(Remove),
+	/// Lists the collections of an existing gallery
+	List(List),
+	/// Shows the settings, collections and file sizes of an existing gallery
+	Status(Status),
+	/// Checks that all files of an existing gallery exist and can be read. Fails with exit code 2 otherwise.
+	Verify(Verify),
+	/// Lists the files of an existing gallery no picture or background references (orphans) and the missing files
+	Gc(Gc),
+	/// Prints the JSON Schema of the gallery data embedded in index.html, which templates can validate against
+	Schema,
+}
+
+/// Options of all commands
+#[derive(StructOpt, Debug)]
+pub struct GalleryOptions {
+	/// The output directory of the gallery, required unless given by the project file
+	#[structopt(short = "o", long = "output")]
+	pub output_dir: Option<PathBuf>,
+
+	/// Project file (gallery.toml) declaring the options and collections of the gallery. Options given on the
+	/// command line take precedence, collections given on the command line replace those of the project file.
+	#[structopt(short = "f", long = "project")]
+	pub project: Option<PathBuf>,
+
+	/// Increases the log level. By default only errors are shown.
+	/// Levels: Error, Warning, Info, Debug
+	#[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+	pub verbose: u8,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct Init {
+	#[structopt(flatten)]
+	gallery: GalleryOptions,
+
+	/// The directory of the template to be used for the gallery, required unless given by the project file
+	#[structopt(short = "p", long = "template")]
+	template_dir: Option<PathBuf>,
+
+	/// Whether to clear the output irectory
+	#[structopt(short = "r", long = "remove-output")]
+	clean_output: bool,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct Build {
+	#[structopt(flatten)]
+	gallery: GalleryOptions,
+
+	/// The directory of the template to be used for the gallery, required unless given by the project file
+	#[structopt(short = "p", long = "template")]
+	template_dir: Option<PathBuf>,
+
+	/// Whether to clear the output irectory
+	#[structopt(short = "r", long = "remove-output")]
+	clean_output: bool,
+
+	#[structopt(flatten)]
+	input: InputOptions,
+
+	#[structopt(flatten)]
+	render: RenderOptions,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct Add {
+	#[structopt(flatten)]
+	gallery: GalleryOptions,
+
+	#[structopt(flatten)]
+	input: InputOptions,
+
+	#[structopt(flatten)]
+	render: RenderOptions,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct Remove {
+	#[structopt(flatten)]
+	gallery: GalleryOptions,
+
+	/// Remove the collection with the given key (its sanitized title). Can be given multiple times.
+	#[structopt(long = "collection", number_of_values = 1)]
+	collections: Vec<String>,
+
+	/// Remove the pictures and backgrounds with the given basename (e.g. "3f2a9c01d4e5b6a7").
+	/// Can be given multiple times.
+	#[structopt(long = "picture", number_of_values = 1)]
+	pictures: Vec<String>,
+
+	/// Remove the pictures and backgrounds created from the given source file. Can be given multiple times.
+	#[structopt(long = "source", number_of_values = 1)]
+	sources: Vec<PathBuf>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct List {
+	#[structopt(flatten)]
+	gallery: GalleryOptions,
+
+	/// List the pictures and backgrounds of each collection as well
+	#[structopt(long = "pictures")]
+	pictures: bool,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct Status {
+	#[structopt(flatten)]
+	gallery: GalleryOptions,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct Verify {
+	#[structopt(flatten)]
+	gallery: GalleryOptions,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct Gc {
+	#[structopt(flatten)]
+	gallery: GalleryOptions,
+
+	/// Delete the orphans found
+	#[structopt(long = "delete")]
+	delete: bool,
+
+	/// Create the missing files, from their recorded sources if these still exist.
+	/// Files rendered with other options than the given ones are created again, as when adding pictures.
+	#[structopt(long = "regenerate")]
+	regenerate: bool,
+
+	#[structopt(flatten)]
+	render: RenderOptions,
+}
+
+impl Cli {
+	/// Returns the configuration of the command, options the command does not have are set to their defaults
+	pub fn into_configuration(self) -> Configuration {
+		match self {
+			Cli::Init(c) => {
+				let mut config = configuration(Command::Init, c.gallery);
+				config.template_dir = c.template_dir;
+				config.clean_output = c.clean_output;
+				config
+			},
+			Cli::Build(c) => {
+				let mut config = configuration(Command::Build, c.gallery);
+				config.template_dir = c.template_dir;
+				config.clean_output = c.clean_output;
+				config.input = c.input;
+				config.render = c.render;
+				config
+			},
+			Cli::Add(c) => {
+				let mut config = configuration(Command::Add, c.gallery);
+				config.input = c.input;
+				config.render = c.render;
+				config
+			},
+			Cli::Remove(c) => {
+				let mut config = configuration(Command::Remove, c.gallery);
+				config.remove_collections = c.collections;
+				config.remove_pictures = c.pictures;
+				config.remove_sources = c.sources;
+				config
+			},
+			Cli::List(c) => configuration(Command::List{ pictures: c.pictures }, c.gallery),
+			Cli::Status(c) => configuration(Command::Status, c.gallery),
+			Cli::Verify(c) => configuration(Command::Verify, c.gallery),
+			Cli::Gc(c) => {
+				let mut config = configuration(Command::Gc, c.gallery);
+				config.gc_delete = c.delete;
+				config.gc_regenerate = c.regenerate;
+				config.render = c.render;
+				config
+			},
+			Cli::Schema => Configuration::with_defaults(Command::Schema, PathBuf::new(), 0),
+		}
+	}
+}
+
+/// Returns the configuration of a command with the options of all commands set, others are set to their defaults
+fn configuration(command: Command, gallery: GalleryOptions) -> Configuration {
+	let mut config = Configuration::with_defaults(command, gallery.output_dir.unwrap_or_default(), gallery.verbose);
+	config.project = gallery.project;
+	config
+}
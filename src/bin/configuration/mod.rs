@@ -1,17 +1,341 @@
+mod commands;
+mod project;
+mod scan;
 
-use mi;
-use mi::logger::{infoln};
+use mi::error::Error;
+use mi::logger::infoln;
 
+use std::ffi::OsString;
 use std::path::PathBuf;
 use structopt::StructOpt;
-use mi::img::Resolution;
+use mi::caption::{CaptionSource, CaptionSources};
+use mi::img::{Color, ExifFields, Format, Resolution};
+use crate::gallery::CollectionDetails;
 use crate::gallery::CollectionInput;
+use crate::gallery::DataLayout;
+use crate::gallery::Manifest;
+use crate::gallery::RenderOverrides;
+use crate::gallery::SortOrder;
+use crate::gallery::TemplateManifest;
+use crate::gallery::Variant;
+use commands::Cli;
+use project::Project;
+
+
+/// Names of the subcommands, other first arguments are parsed as the flags of the previous command line interface
+pub const COMMANDS: [&str; 10] = ["init", "build", "add", "remove", "list", "status", "verify", "gc", "schema", "help"];
+
+/// What to do with the gallery
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Command {
+	/// Create a new gallery without pictures
+	Init,
+	/// Create a new gallery from the given collections
+	#[default]
+	Build,
+	/// Add collections, pictures and backgrounds to an existing gallery
+	Add,
+	/// Remove collections, pictures and backgrounds from an existing gallery
+	Remove,
+	/// List the collections (and pictures) of an existing gallery
+	List { pictures: bool },
+	/// Show the settings, collections and file sizes of an existing gallery
+	Status,
+	/// Check that all files of an existing gallery exist and can be read
+	Verify,
+	/// List or delete orphaned files and create missing ones
+	Gc,
+	/// Print the JSON Schema of the gallery data
+	Schema,
+}
+
+impl Command {
+	/// Returns whether the command works on an existing gallery
+	pub fn existing(&self) -> bool {
+		!matches!(self, Command::Init | Command::Build | Command::Schema)
+	}
+}
+
+/// Options of the pictures and backgrounds added to a gallery
+#[derive(StructOpt, Debug, Default)]
+pub struct InputOptions {
+	/// Collection input as "[input directory];[background directory],[collection title]". Examples: "in/;bg/;Col 1", "in/;-;Col 2"
+	#[structopt(short = "c", long = "collection")]
+	pub collections: Vec<CollectionInput>,
+
+	/// Whether to create an archive (downloadable zip-file) with the original pictures
+	#[structopt(short = "a", long = "archive")]
+	pub create_full_archive: bool,
+
+	/// When set to true the image names (without extensions) are used as picture titles
+	#[structopt(long = "image-name-titles")]
+	pub image_name_titles: bool,
+
+	/// Search pictures and backgrounds that look alike (e.g. the same shot exported with a different compression)
+	/// and list them. Byte identical files are always merged.
+	#[structopt(long = "near-duplicates")]
+	pub near_duplicates: bool,
+
+	/// Replace near duplicates by the picture or background found first, implies --near-duplicates
+	#[structopt(long = "merge-near-duplicates")]
+	pub merge_near_duplicates: bool,
+
+	/// Remove the pictures of parent collections that one of their child collections shows as well, so each
+	/// picture is only shown once in the tree
+	#[structopt(long = "remove-nested-duplicates")]
+	pub remove_nested_duplicates: bool,
+
+	/// Maximum number of differing bits (0-64) of the perceptual hashes of near duplicates
+	#[structopt(long = "near-duplicate-distance", default_value = "5")]
+	pub near_duplicate_distance: u32,
+
+	/// Render settings of a collection that differ from the ones of the gallery, as "[collection key]:[settings]".
+	/// Valid settings: thumb-size, display-size, background-size, jpeg-quality, resize-method.
+	/// Example: "panoramas:display-size=5120x1440,jpeg-quality=90". Can be given multiple times.
+	#[structopt(long = "collection-settings", number_of_values = 1)]
+	pub collection_settings: Vec<CollectionSettings>,
+
+	/// Exif fields published in the gallery data, as comma separated list, "all" or "none" [default: none].
+	/// Valid fields: "date", "camera", "lens", "exposure", "aperture", "iso", "focal-length".
+	/// When updating, the fields stored in the gallery are used unless given.
+	#[structopt(long = "exif-fields")]
+	pub exif_fields: Option<ExifFields>,
+
+	/// Order of the pictures, as "[order]" for all given collections or "[collection key]:[order]".
+	/// Valid orders: "name", "exif-date", "mtime", "random-seeded[=seed]", "manual" (order of the file names
+	/// listed in "order.txt" of the input directory). When updating, the order stored in the collection is used
+	/// unless given. Can be given multiple times.
+	#[structopt(long = "sort", number_of_values = 1)]
+	pub sort: Vec<CollectionSort>,
+
+	/// Comma separated list of the sources of picture titles and descriptions, in the order they are used, or "none".
+	/// Valid sources: "txt" (IMG_1.jpg.txt, first line is the title), "json" (IMG_1.json), "xmp" (IMG_1.xmp),
+	/// "embedded-xmp", "iptc", "exif" (stored in the picture) [default: txt,json,xmp].
+	/// Titles found replace the image name titles. Only pictures added in this run are read.
+	#[structopt(long = "caption-sources")]
+	pub caption_sources: Option<CaptionSources>,
+
+	/// Detail of a collection shown on overview pages, as "[collection key]:[detail]=[value]". Valid details:
+	/// "description" (Markdown), "dates" ("2023-07-01..2023-07-14", "2023-07" or "2023"), "location", "cover"
+	/// (file name or basename of a picture of the collection). Details not given are read from "collection.toml"
+	/// of the input directory. Can be given multiple times.
+	#[structopt(long = "collection-info", number_of_values = 1)]
+	pub collection_info: Vec<CollectionInfo>,
+
+	/// Collection shown below another one, as "[collection key]:[parent key]", or "[collection key]:" to show it at
+	/// the top level. Parents do not need pictures of their own. Can be given multiple times.
+	#[structopt(long = "collection-parent", number_of_values = 1)]
+	pub collection_parent: Vec<CollectionParent>,
+
+	/// Directory tree whose directories with pictures are added as collections, titled by their directory names.
+	/// Backgrounds are taken from the "_backgrounds" subdirectory of a directory or of its nearest parent, or
+	/// from --scan-backgrounds.
+	#[structopt(long = "scan")]
+	pub scan: Option<PathBuf>,
+
+	/// Background directory of the scanned directories that have no "_backgrounds" directory
+	#[structopt(long = "scan-backgrounds")]
+	pub scan_backgrounds: Option<PathBuf>,
+
+	/// Add the directories of the scanned tree as nested collections, directories with subdirectories become their
+	/// parents. By default only directories without subdirectories are added.
+	#[structopt(long = "scan-nested")]
+	pub scan_nested: bool,
+}
+
+impl InputOptions {
+	pub fn caption_sources(&self) -> Vec<CaptionSource> {
+		self.caption_sources.clone().unwrap_or_default().0
+	}
+}
+
+/// Render settings of one collection given on the command line
+#[derive(Debug, Clone)]
+pub struct CollectionSettings {
+	/// Key (sanitized title) of the collection
+	pub key: String,
+	pub overrides: RenderOverrides,
+}
+
+impl std::str::FromStr for CollectionSettings {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.split_once(':') {
+			Some((key, settings)) => Ok(CollectionSettings{
+				key: crate::mi::fs::sanitize(key),
+				overrides: RenderOverrides::from_str(settings)?,
+			}),
+			None => Err(String::from("Invalid collection settings, must be \"[collection key]:[settings]\"")),
+		}
+	}
+}
+
+/// Order of the pictures of one or all collections given on the command line
+#[derive(Debug, Clone)]
+pub struct CollectionSort {
+	/// Key (sanitized title) of the collection, none for all given collections
+	pub key: Option<String>,
+	pub order: SortOrder,
+}
+
+impl std::str::FromStr for CollectionSort {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.split_once(':') {
+			Some((key, order)) => Ok(CollectionSort{ key: Some(crate::mi::fs::sanitize(key)), order: SortOrder::from_str(order)? }),
+			None => Ok(CollectionSort{ key: None, order: SortOrder::from_str(s)? }),
+		}
+	}
+}
+
+/// Detail of one collection given on the command line
+#[derive(Debug, Clone)]
+pub struct CollectionInfo {
+	/// Key (sanitized title) of the collection
+	pub key: String,
+	pub details: CollectionDetails,
+}
+
+impl std::str::FromStr for CollectionInfo {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (key, detail) = s.split_once(':')
+			.ok_or_else(|| String::from("Invalid collection info, must be \"[collection key]:[detail]=[value]\""))?;
+		let (field, value) = detail.split_once('=')
+			.ok_or_else(|| format!("Invalid collection detail \"{}\", must be \"[detail]=[value]\"", detail))?;
+
+		let mut details = CollectionDetails::default();
+		details.set(field.trim(), value)?;
+
+		Ok(CollectionInfo{ key: crate::mi::fs::sanitize(key), details })
+	}
+}
+
+/// Parent of one collection given on the command line
+#[derive(Debug, Clone)]
+pub struct CollectionParent {
+	/// Key (sanitized title) of the collection
+	pub key: String,
+	/// Key of the parent, none for top level collections
+	pub parent: Option<String>,
+}
+
+impl std::str::FromStr for CollectionParent {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.split_once(':') {
+			Some((key, parent)) => Ok(CollectionParent{
+				key: crate::mi::fs::sanitize(key),
+				parent: Some(parent.trim()).filter(|p| !p.is_empty()).map(crate::mi::fs::sanitize),
+			}),
+			None => Err(String::from("Invalid collection parent, must be \"[collection key]:[parent key]\"")),
+		}
+	}
+}
+
+/// Options of the created files
+#[derive(StructOpt, Debug, Default)]
+pub struct RenderOptions {
+	/// The size of the small picture versions (thumbnails) [default: 960x540].
+	/// When updating, the size stored in the gallery is used unless given.
+	#[structopt(long = "thumb-size")]
+	pub tumb_size: Option<Resolution>,
+
+	/// The size of the display picture versions [default: 2560x1440].
+	/// When updating, the size stored in the gallery is used unless given.
+	#[structopt(long = "display-size")]
+	pub display_size: Option<Resolution>,
+
+	/// The size of the backgroun picture versions [default: 2560x1440].
+	/// When updating, the size stored in the gallery is used unless given.
+	#[structopt(long = "background-size")]
+	pub background_size: Option<Resolution>,
+
+	/// The size of the cover versions of the cover pictures of the collections [default: 1920x1080].
+	/// When updating, the size stored in the gallery is used unless given.
+	#[structopt(long = "cover-size")]
+	pub cover_size: Option<Resolution>,
+
+	/// Comma separated list of formats the thumbnail, display and background versions are created in.
+	/// Valid formats: "jpeg", "webp", "avif". JPEG is always created as fallback [default: jpeg].
+	/// When updating, the formats stored in the gallery are used unless given.
+	#[structopt(long = "formats", use_delimiter = true)]
+	pub formats: Option<Vec<Format>>,
+
+	/// Where the gallery data is stored. Valid layouts: "inline" (embedded in the index.html), "file" (data.json),
+	/// "collections" (data.json and one file per collection in "c/") [default: inline]. Templates load data files
+	/// with requests, which needs a web server. When updating, the layout stored in the gallery is used unless given.
+	#[structopt(long = "data-layout")]
+	pub data_layout: Option<DataLayout>,
+
+	/// Image resize method. Valid methods: "lanczos3", "gaussian", "nearest", "cubic", "linear" [default: lanczos3].
+	/// When updating, the method stored in the gallery is used unless given.
+	#[structopt(long = "resize-method")]
+	pub resize_method: Option<String>,
+
+	/// Color used to fill transparent parts of the pictures, as the created images do not keep transparency
+	/// [default: #ffffff]. When updating, the color stored in the gallery is used unless given.
+	#[structopt(long = "matte-color")]
+	pub matte_color: Option<Color>,
+
+	/// Command that extracts a still image from a video, used as poster and for the thumbnails.
+	/// "{input}" is replaced by the video and "{output}" by the image file to be written.
+	/// [default: ffmpeg -loglevel error -y -i {input} -vf thumbnail -frames:v 1 {output}]
+	#[structopt(long = "video-frame-command")]
+	pub video_frame_command: Option<String>,
+
+	/// Quality of the output images 1-100 [default: 75]. When updating, the quality stored in the gallery is used
+	/// unless given.
+	#[structopt(long = "jpeg-quality")]
+	pub jpeg_quality: Option<u8>,
+
+	/// Number of concurrent threads to use for image resizing.
+	/// If not set or set to 0 it uses the number of available logical cores.
+	#[structopt(long = "threads")]
+	pub threads: Option<usize>,
+
+	/// Stop with an error if any picture or background fails. By default failed ones are left out of the gallery
+	/// and the exit code is 7.
+	#[structopt(long = "strict")]
+	pub strict: bool,
+
+	/// Write the processed pictures and failures as JSON to the given file
+	#[structopt(long = "failure-report")]
+	pub failure_report: Option<PathBuf>,
+}
+
+/// Options that are not set on the command line can be set by a project file, the defaults are used otherwise
+impl RenderOptions {
+	pub fn resize_method(&self) -> String {
+		self.resize_method.clone().unwrap_or_else(|| String::from(crate::gallery::DEFAULT_RESIZE_METHOD))
+	}
+
+	pub fn video_frame_command(&self) -> String {
+		self.video_frame_command.clone().unwrap_or_else(|| String::from(crate::gallery::DEFAULT_VIDEO_FRAME_COMMAND))
+	}
+
+	pub fn jpeg_quality(&self) -> u8 {
+		self.jpeg_quality.unwrap_or(crate::gallery::DEFAULT_JPEG_QUALITY)
+	}
+
+	pub fn threads(&self) -> usize {
+		self.threads.unwrap_or(0)
+	}
+}
 
 /// Generate a static picture gallery using the given template
 #[derive(StructOpt, Debug)]
 #[structopt(name = "miGallery")]
 /// Generates a static gallery from the given inputs.
 ///
+/// The gallery is managed with the commands init, build, add, remove, list, status, verify and gc.
+/// Run "static_gallery help <command>" for the options of each command. The flags below are the previous
+/// command line interface, which is deprecated but still works.
+///
 /// For each collection the options --input --background and --title should be set.
 ///
 /// The number of directories for --input and --background and the --title options should match.
@@ -22,86 +346,104 @@ use crate::gallery::CollectionInput;
 /// Create new gallery:
 ///
 ///  - Create a new gallery with one collection
-///    static_gallery -o out/ -c "in1/;bg1/;Pictures"
+///    static_gallery build -o out/ -p template/ -c "in1/;bg1/;Pictures"
 ///
 ///  - Create a new gallery with two collections (the second collection without backgrounds)
-///    static_gallery -o out/ -c "in1/;bg1/;Collection 01" -c "in2/;-;Collection 02"
+///    static_gallery build -o out/ -p template/ -c "in1/;bg1/;Collection 01" -c "in2/;-;Collection 02"
 ///
 /// Update existing gallery:
 ///  - Add a new collection
-///    static_gallery -u -o out/ -c "in1/;bg1/;New Collection"
+///    static_gallery add -o out/ -c "in1/;bg1/;New Collection"
 ///
 ///  - Add pictures to existing collection (collection with same title must already exist, else it will be created as new)
-///    static_gallery -u -o out/ -c "in2/;-;Collection 01"
+///    static_gallery add -o out/ -c "in2/;-;Collection 01"
 ///
 ///  - Add backgrounds to existing collection (collection with same title must already exist, else it will be created as new)
-///    static_gallery -u -o out/ -c "-;bg2/;Collection 01"
+///    static_gallery add -o out/ -c "-;bg2/;Collection 01"
+///
+///  - Remove a collection
+///    static_gallery remove -o out/ --collection collection_01
+///
+/// Exit codes:
+///
+///  0  Success
+///  1  A file or directory could not be read or written
+///  2  The data of the existing gallery is invalid or its files are damaged (verify)
+///  3  Invalid options
+///  4  Invalid template
+///  5  An image or video could not be decoded or encoded
+///  6  Invalid exif data
+///  7  Pictures or backgrounds failed and were left out of the gallery
+///  70 Internal error
 ///
 pub struct Configuration {
 
-	/// Collection input as "[input directory];[background directory],[collection title]". Examples: "in/;bg/;Col 1", "in/;-;Col 2"
-	#[structopt(short = "c", long = "collection")]
-	pub collections: Vec<CollectionInput>,
+	#[structopt(flatten)]
+	pub input: InputOptions,
 
 	/// The output directory for the generated gallery
 	#[structopt(short = "o", long = "output")]
 	pub output_dir: PathBuf,
 
-	/// The directory of the template to be used for the gallery
+	/// The directory of the template to be used for the gallery, required for new galleries
 	#[structopt(short = "p", long = "template")]
-	pub template_dir: PathBuf,
+	pub template_dir: Option<PathBuf>,
 
 	/// Whether to clear the output irectory
 	#[structopt(short = "r", long = "remove-output")]
 	pub clean_output: bool,
 
-	/// Whether to create an archive (downloadable zip-file) with the original pictures
-	#[structopt(short = "a", long = "archive")]
-	pub create_full_archive: bool,
-
-	/// The size of the small picture versions (thumbnails)
-	#[structopt(long = "thumb-size", default_value = "960x540")]
-	pub tumb_size: Resolution,
-
-	/// The size of the display picture versions
-	#[structopt(long = "display-size", default_value = "2560x1440")]
-	pub display_size: Resolution,
-
-	/// The size of the backgroun picture versions
-	#[structopt(long = "background-size", default_value = "2560x1440")]
-	pub background_size: Resolution,
-
-	/// Image resize method. Valid methods: "lanczos3", "gaussian", "nearest", "cubic", "linear"
-	#[structopt(long = "resize-method", default_value = "lanczos3")]
-	pub resize_method: String,
-
-	/// Quality of the output images 1-100
-	#[structopt(long = "jpeg-quality", default_value = "75")]
-	pub jpeg_quality: u8,
-
-	/// Number of concurrent threads to use for image resizing.
-	/// If set to 0 it uses the number of available logical cores.
-	#[structopt(long = "threads", default_value = "0")]
-	pub threads: usize,
+	#[structopt(flatten)]
+	pub render: RenderOptions,
 
 	/// Increases the log level. By default only errors are shown.
 	/// Levels: Error, Warning, Info, Debug
 	#[structopt(short = "v", long = "verbose", parse(from_occurrences))]
     pub verbose: u8,
 
-	/// When set to true the image names (without extensions) are used as picture titles
-	#[structopt(long = "image-name-titles")]
-	pub image_name_titles: bool,
-
 	/// Update gallery (add new pictures to existing gallery) overwrites pictures with the same file name in the same collection
 	#[structopt(short = "u", long = "update")]
 	pub update: bool,
 
+	/// Remove the collection with the given key (its sanitized title) from the gallery when updating.
+	/// Can be given multiple times.
+	#[structopt(long = "remove-collection", number_of_values = 1)]
+	pub remove_collections: Vec<String>,
+
+	/// Remove the pictures and backgrounds with the given basename (e.g. "3f2a9c01d4e5b6a7") from the gallery
+	/// when updating. Can be given multiple times.
+	#[structopt(long = "remove-picture", number_of_values = 1)]
+	pub remove_pictures: Vec<String>,
+
+	/// Remove the pictures and backgrounds created from the given source file from the gallery when updating.
+	/// Can be given multiple times.
+	#[structopt(long = "remove-source", number_of_values = 1)]
+	pub remove_sources: Vec<PathBuf>,
+
+	/// Compare the existing gallery with the files in its output directory instead of creating it. Lists the
+	/// files no picture or background references (orphans) and the missing files.
+	#[structopt(long = "gc")]
+	pub gc: bool,
+
+	/// Delete the orphans found, implies --gc
+	#[structopt(long = "gc-delete")]
+	pub gc_delete: bool,
+
+	/// Create the missing files, from their recorded sources if these still exist, implies --gc.
+	/// Files rendered with other options than the given ones are created again, as when updating.
+	#[structopt(long = "gc-regenerate")]
+	pub gc_regenerate: bool,
+
 	// /// Disables any output (including errors)
 	// #[structopt(short = "s", long = "silent")]
     // silent: bool,
 
 
+	#[structopt(skip)]
+	pub command: Command,
+	/// Project file the options not given on the command line are read from
+	#[structopt(skip)]
+	pub project: Option<PathBuf>,
 	#[structopt(skip)]
 	delete_output_dir: bool,
 	#[structopt(skip)]
@@ -111,138 +453,331 @@ pub struct Configuration {
 
 
 impl Configuration {
-	pub fn from_cli() -> Result<Configuration, String> {
-		let mut config = Configuration::from_args();
+	pub fn from_cli() -> Result<Configuration, Error> {
+		Configuration::from_args(std::env::args_os().collect())
+	}
+
+	/// Parses the command line arguments (including the program name). Arguments starting with a command are
+	/// parsed as subcommand, others as the flags of the previous command line interface.
+	pub fn from_args(args: Vec<OsString>) -> Result<Configuration, Error> {
+		// Errors in the options have to be shown before the log level is known
+		mi::logger::set_level(mi::logger::LOGLEVEL_ERROR);
+
+		// Without any arguments the usage of the commands is shown
+		let is_command = args.len() < 2 || args.get(1).and_then(|a| a.to_str()).is_some_and(|a| COMMANDS.contains(&a));
+		let parsed = match is_command {
+			true => Cli::from_iter_safe(&args).map(Cli::into_configuration),
+			false => Configuration::from_iter_safe(&args).map(Configuration::with_legacy_command),
+		};
+
+		let mut config = match parsed {
+			Ok(c) => c,
+			// Help and version are shown by the error as well
+			Err(e) if e.use_stderr() => return Err(Error::Config(e.message)),
+			Err(e) => e.exit(),
+		};
 
 		// Verbose is 0 by default, which as log level would be silent, but we wand errors to be shown by default
-		config.verbose = config.verbose + 1;
+		config.verbose += 1;
 		mi::logger::set_level(config.verbose);
 		// println!("Set log_level to {}", config.verbose);
 
-		config.validate()?;
+		if !is_command {
+			eprintln!("Calling static_gallery without command is deprecated, use \"static_gallery build\", \"add\", \"remove\" or \"gc\" instead.");
+		}
+
+		// The schema does not depend on a gallery
+		if config.command == Command::Schema {
+			return Ok(config);
+		}
+
+		config.load_project()?;
+		config.load_template()?;
+		config.scan()?;
+		config.validate().map_err(Error::Config)?;
+		config.validate_template()?;
 		config.init()?;
 
 		Ok(config)
 	}
 
+	/// Returns the configuration of a command with all other options set to their defaults
+	fn with_defaults(command: Command, output_dir: PathBuf, verbose: u8) -> Configuration {
+		Configuration{
+			input: InputOptions::default(),
+			output_dir,
+			template_dir: None,
+			clean_output: false,
+			render: RenderOptions::default(),
+			verbose,
+			update: command.existing(),
+			remove_collections: Vec::new(),
+			remove_pictures: Vec::new(),
+			remove_sources: Vec::new(),
+			gc: command == Command::Gc,
+			gc_delete: false,
+			gc_regenerate: false,
+			command,
+			project: None,
+			delete_output_dir: false,
+			create_output_dir: false,
+		}
+	}
 
-	fn init(&mut self) -> Result<u8, String> {
-		// Run initialization tasks if any
+	/// Sets the command the flags of the previous command line interface stand for
+	fn with_legacy_command(mut self) -> Configuration {
+		self.command = if self.gc || self.gc_delete || self.gc_regenerate {
+			Command::Gc
+		} else if self.update && self.input.collections.is_empty() && self.removes() {
+			Command::Remove
+		} else if self.update {
+			// Pictures can be removed and added in one run
+			Command::Add
+		} else {
+			Command::Build
+		};
 
-		let mut errors: Vec<String> = Vec::new();
+		self
+	}
+
+	/// Sets the options not given on the command line from the project file, if any
+	fn load_project(&mut self) -> Result<(), Error> {
+		let path = match self.project.clone() {
+			Some(p) => p,
+			None => return Ok(()),
+		};
+
+		let project_dir = path.parent().map(PathBuf::from).unwrap_or_default();
+		Project::read(&path)?.apply(self, &project_dir)?;
+		infoln(format!("Using project file {}", path.to_string_lossy()));
 
+		Ok(())
+	}
+
+	/// Adds the collections of the scanned directory tree
+	fn scan(&mut self) -> Result<(), Error> {
+		let input = &mut self.input;
+		let root = match &input.scan {
+			Some(r) => r,
+			None => return Ok(()),
+		};
+
+		let mut collections = scan::scan(root, input.scan_backgrounds.as_ref(), input.scan_nested)?;
+		infoln(format!("Found {} collections in {}", collections.len(), root.to_string_lossy()));
+		input.collections.append(&mut collections);
+
+		Ok(())
+	}
+
+	fn init(&mut self) -> Result<(), Error> {
+		// Run initialization tasks if any
 
 		// Remove output files recursively
-		if self.clean_output {
-			let removed = match std::fs::remove_dir_all(&self.output_dir) {
-				Ok(_) => {
-					infoln(format!("Output directory removed"));
-					true
-				},
-				Err(e) => {
-					errors.push(format!("Could not remove output directory: {}", e));
-					false
-				}
-			};
-
-			if removed {
-				match std::fs::create_dir_all(std::path::Path::new(&self.output_dir)) {
-					Ok(_) => {
-						infoln(format!("Output directory created"));
-					},
-					Err(e) => {
-						errors.push(format!("Output directory could not be created: {}", e));
-					}
-				};
-			}
+		if self.delete_output_dir {
+			std::fs::remove_dir_all(&self.output_dir).map_err(|e| Error::io(&self.output_dir, e))?;
+			infoln(String::from("Output directory removed"));
 		}
 
+		if self.create_output_dir {
+			std::fs::create_dir_all(&self.output_dir).map_err(|e| Error::io(&self.output_dir, e))?;
+			infoln(String::from("Output directory created"));
+		}
 
+		Ok(())
+	}
 
+	/// Uses the defaults of the template manifest for the options given neither on the command line nor by the
+	/// project file. Existing galleries keep their settings, so only new galleries use them.
+	fn load_template(&mut self) -> Result<(), Error> {
+		let template_dir = match &self.template_dir {
+			Some(dir) if !self.command.existing() && dir.is_dir() => dir,
+			_ => return Ok(()),
+		};
 
+		let manifest = TemplateManifest::read(template_dir)?;
+		let render = &mut self.render;
+		render.tumb_size = render.tumb_size.or(manifest.size(Variant::Thumb));
+		render.display_size = render.display_size.or(manifest.size(Variant::Display));
+		render.background_size = render.background_size.or(manifest.size(Variant::Background));
+		render.cover_size = render.cover_size.or(manifest.size(Variant::Cover));
+		render.formats = render.formats.take().or(manifest.formats());
+
+		let defaults = manifest.defaults;
+		render.jpeg_quality = render.jpeg_quality.or(defaults.quality);
+		render.resize_method = render.resize_method.take().or(defaults.resize_method);
+		render.matte_color = render.matte_color.or(defaults.matte_color);
+		render.data_layout = render.data_layout.or(defaults.data_layout);
+		self.input.exif_fields = self.input.exif_fields.take().or(defaults.exif_fields);
+		self.input.caption_sources = self.input.caption_sources.take().or(defaults.caption_sources);
+
+		Ok(())
+	}
 
-		if errors.len() > 0 {
-			Err(errors.join("\n"))
-		} else {
-			Ok(0)
+	/// Checks the template against its manifest, templates without manifest need the data marker in their index.html
+	fn validate_template(&self) -> Result<(), Error> {
+		let template_dir = match &self.template_dir {
+			Some(dir) => dir,
+			None => return Ok(()),
+		};
+
+		// Validate template folder exists
+		if !crate::mi::fs::dir_exists(template_dir) {
+			return Err(Error::Template{ path: template_dir.clone(), message: String::from("Not a directory") });
 		}
+
+		let manifest = TemplateManifest::read(template_dir)?;
+		manifest.check(template_dir)?;
+
+		// The formats the template loads have to be created
+		if let Some(formats) = &self.render.formats {
+			for v in &manifest.variants {
+				if let Some(f) = v.formats.iter().find(|f| **f != Format::Jpeg && !formats.contains(f)) {
+					return Err(Error::Template{
+						path: template_dir.join(crate::gallery::TEMPLATE_MANIFEST_FILE_NAME),
+						message: format!("The template needs the {} variants as {}, which is not in the given formats", v.name.name(), f),
+					});
+				}
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Returns whether any pictures or collections are to be removed
+	pub fn removes(&self) -> bool {
+		!self.remove_collections.is_empty() || !self.remove_pictures.is_empty() || !self.remove_sources.is_empty()
 	}
 
 	fn validate(&mut self) -> Result<u8, String> {
 		let mut errors: Vec<String> = Vec::new();
 
-		if self.threads == 0 {
-			self.threads = num_cpus::get();
-			// self.threads = num_cpus::get_physical();
-			infoln(format!("Using {} threads for image resizing", self.threads));
+		if self.render.threads() == 0 {
+			self.render.threads = Some(num_cpus::get());
+			// self.render.threads = Some(num_cpus::get_physical());
+			infoln(format!("Using {} threads for image resizing", self.render.threads()));
 		}
 
 
-		if self.jpeg_quality < 1 || self.jpeg_quality > 100 {
+		if self.render.jpeg_quality() < 1 || self.render.jpeg_quality() > 100 {
 			errors.push(String::from("Jpeg quality must be between 1 and 100"));
 		}
 
-		if self.collections.len() == 0 {
+		if self.input.near_duplicate_distance > 64 {
+			errors.push(String::from("Near duplicate distance must be between 0 and 64"));
+		}
+
+		// Commands working on an existing gallery update it
+		self.update = self.command.existing();
+
+		if self.gc_delete || self.gc_regenerate {
+			self.gc = true;
+		}
+
+		if self.command == Command::Gc && (!self.input.collections.is_empty() || self.removes()) {
+			errors.push(String::from("Collections cannot be added or removed together with --gc"));
+		}
+
+		if !self.update && self.removes() {
+			errors.push(String::from("Pictures and collections can only be removed when updating"));
+		}
+
+		if self.command == Command::Remove && !self.removes() {
+			errors.push(String::from("Nothing to remove specified"));
+		}
+
+		let adds = matches!(self.command, Command::Build | Command::Add);
+		let changes_settings = self.command == Command::Add
+			&& (!self.input.collection_settings.is_empty()
+				|| self.input.sort.iter().any(|s| s.key.is_some())
+				|| !self.input.collection_info.is_empty()
+				|| !self.input.collection_parent.is_empty()
+				|| self.render.data_layout.is_some());
+		if adds && self.input.collections.is_empty() && !self.removes() && !changes_settings {
 			errors.push(String::from("No collections specified"));
 		}
 
-		// Validate collections
-		for col in &self.collections {
-			if col.title == "" || col.title == "-" {
-				errors.push(format!("Collections must have valid titles"));
+		if self.output_dir.as_os_str().is_empty() {
+			errors.push(String::from("No output directory specified (--output or in the project file)"));
+			return Err(errors.join("\n"));
+		}
+
+		if !self.update && self.template_dir.is_none() {
+			errors.push(String::from("No template specified, new galleries require a template (--template)"));
+		}
+
+		// Validate collections, parents do not need pictures
+		let parents: Vec<&String> = self.input.collections.iter().filter_map(|c| c.parent.as_ref())
+			.chain(self.input.collection_parent.iter().filter_map(|c| c.parent.as_ref()))
+			.collect();
+		for col in &self.input.collections {
+			if col.title.is_empty() || col.title == "-" {
+				errors.push(String::from("Collections must have valid titles"));
 			}
 
-			if !self.update && col.input_dir.is_none() {
+			let parent = parents.contains(&&col.name);
+			if !self.update && !col.has_pictures() && !parent {
 				errors.push(format!("New collection \"{}\" does not have an input directory", col.title));
 			}
 
-			if col.input_dir.is_none() && col.background_dir.is_none() {
+			if !col.has_pictures() && !col.has_backgrounds() && !parent {
 				errors.push(format!("New collection \"{}\" has neither an input nor background directory", col.title));
 			}
 
-			if col.input_dir.is_some() && !crate::gallery::contains_images(&col.input_dir.as_ref().unwrap()) {
-				errors.push(format!("Input directory for collection \"{}\" does not contain images: {}", col.title, col.input_dir.as_ref().unwrap().to_string_lossy()));
+			if !col.input_files.is_empty() && !col.input_files.iter().any(crate::gallery::valid_file) {
+				errors.push(format!("Input files for collection \"{}\" do not contain images", col.title));
 			}
 
-			if col.background_dir.is_some() && !crate::gallery::contains_images(&col.background_dir.as_ref().unwrap()) {
-				errors.push(format!("Background directory for collection \"{}\" does not contain images: {}", col.title, col.background_dir.as_ref().unwrap().to_string_lossy()));
+			if let Some(dir) = &col.input_dir {
+				if !crate::gallery::contains_images(dir) {
+					errors.push(format!("Input directory for collection \"{}\" does not contain images: {}", col.title, dir.to_string_lossy()));
+				}
 			}
-		}
 
+			if let Some(dir) = &col.background_dir {
+				if !crate::gallery::contains_images(dir) {
+					errors.push(format!("Background directory for collection \"{}\" does not contain images: {}", col.title, dir.to_string_lossy()));
+				}
+			}
+		}
 
 
-		match self.resize_method.as_str() {
-			"lanczos3" => {},
-			"gaussian" => {},
-			"nearest" => {},
-			"cubic" => {},
-			"linear" => {},
-			_ => {
-				errors.push(format!("Invalid resize method \"{}\". Valid options: \"lanczos3\", \"gaussian\" and \"nearest\"", self.resize_method));
-			},
-		};
 
-		// Validate template folder exists
-		if !crate::mi::fs::dir_exists(&self.template_dir) {
-			errors.push(format!("Template directory is not a directory: {}", self.template_dir.to_str().unwrap()));
-		}
-		// Validate template is valid
-		if !crate::mi::fs::file_exists(&self.template_dir.join("index.html")) {
-			errors.push(format!("Template directory is not a directory: {}", self.template_dir.to_str().unwrap()));
+		let overrides = self.input.collections.iter().map(|c| (&c.title, &c.render))
+			.chain(self.input.collection_settings.iter().map(|c| (&c.key, &c.overrides)));
+		for (collection, o) in overrides {
+			if o.quality.is_some_and(|q| !(1..=100).contains(&q)) {
+				errors.push(format!("Jpeg quality of collection \"{}\" must be between 1 and 100", collection));
+			}
+			if let Some(error) = o.method.as_deref().and_then(validate_resize_method) {
+				errors.push(format!("Collection \"{}\": {}", collection, error));
+			}
 		}
 
+		if let Some(error) = validate_resize_method(&self.render.resize_method()) {
+			errors.push(error);
+		}
 
 		if self.update && self.clean_output {
 			errors.push(String::from("Options --clean und --update are mutually exclusive. Choose only one of them."));
 		}
 
-		if self.update && !crate::mi::fs::file_exists(&self.output_dir.join("index.html")) {
-			errors.push(format!("No index.html found in the output folder ({}), cannot update.", self.output_dir.to_str().unwrap()));
+		// The gallery data is in the first file the template fills with it
+		if self.update {
+			let data_file = Manifest::read(&self.output_dir).ok()
+				.and_then(|m| m.data_files.into_iter().next())
+				.unwrap_or_else(|| PathBuf::from("index.html"));
+			if !crate::mi::fs::file_exists(&self.output_dir.join(&data_file)) {
+				errors.push(format!(
+					"No {} found in the output folder ({}), cannot update.",
+					data_file.to_string_lossy(),
+					self.output_dir.to_str().unwrap(),
+				));
+			}
 		}
 
 		let output_dir_exists = crate::mi::fs::dir_exists(&self.output_dir);
 		let output_dir_empty = if output_dir_exists {
-			crate::mi::fs::list_dir_as_strings(&self.output_dir).len() == 0
+			crate::mi::fs::list_dir_as_strings(&self.output_dir).is_empty()
 		} else {
 			true
 		};
@@ -263,10 +798,18 @@ impl Configuration {
 		}
 
 
-		if errors.len() > 0 {
+		if !errors.is_empty() {
 			Err(errors.join("\n"))
 		} else {
 			Ok(0)
 		}
 	}
 }
+
+/// Returns an error message if the resize method is not valid
+fn validate_resize_method(method: &str) -> Option<String> {
+	match method {
+		"lanczos3" | "gaussian" | "nearest" | "cubic" | "linear" => None,
+		_ => Some(format!("Invalid resize method \"{}\". Valid options: \"lanczos3\", \"gaussian\", \"nearest\", \"cubic\" and \"linear\"", method)),
+	}
+}
@@ -0,0 +1,253 @@
+use mi::error::Error;
+use serde::Deserialize;
+use std::path::PathBuf;
+use mi::caption::CaptionSources;
+use mi::img::{Color, ExifFields, Format, Resolution};
+use crate::gallery::CollectionDetails;
+use crate::gallery::CollectionInput;
+use crate::gallery::DataLayout;
+use crate::gallery::DateRange;
+use crate::gallery::RenderOverrides;
+use crate::gallery::SortOrder;
+use super::Configuration;
+
+/// Project file (gallery.toml) describing the whole gallery. Relative paths are relative to the directory of the
+/// project file. Details of a collection that are not given are read from the collection files (collection.toml)
+/// of its input directories.
+///
+/// Example:
+///
+/// ```toml
+/// output = "out/"
+/// template = "templates/hauer/"
+/// display_size = "2560x1440"
+/// quality = 85
+/// exif_fields = "date,camera,lens"
+/// scan = "photos/event/"
+///
+/// [[collections]]
+/// title = "Day 1"
+/// inputs = ["photos/day-1/", "photos/extra/day1_*.jpg"]
+/// backgrounds = ["backgrounds/"]
+/// description = "Arrival and *opening*"
+/// dates = "2023-07-01"
+/// location = "Berlin"
+/// cover = "IMG_0042.jpg"
+/// sort = "exif-date"
+///
+/// [[collections]]
+/// title = "Panoramas"
+/// parent = "Day 1"
+/// inputs = ["photos/panoramas/"]
+/// display_size = "5120x1440"
+/// quality = 90
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Project {
+	pub output: Option<PathBuf>,
+	pub template: Option<PathBuf>,
+
+	#[serde(default, deserialize_with = "mi::de::deserialize_from_str")]
+	pub thumb_size: Option<Resolution>,
+	#[serde(default, deserialize_with = "mi::de::deserialize_from_str")]
+	pub display_size: Option<Resolution>,
+	#[serde(default, deserialize_with = "mi::de::deserialize_from_str")]
+	pub background_size: Option<Resolution>,
+	#[serde(default, deserialize_with = "mi::de::deserialize_from_str")]
+	pub cover_size: Option<Resolution>,
+	#[serde(default, deserialize_with = "mi::de::deserialize_list_from_str")]
+	pub formats: Option<Vec<Format>>,
+	#[serde(default, deserialize_with = "mi::de::deserialize_from_str")]
+	pub data_layout: Option<DataLayout>,
+
+	pub quality: Option<u8>,
+	pub resize_method: Option<String>,
+	#[serde(default, deserialize_with = "mi::de::deserialize_from_str")]
+	pub matte_color: Option<Color>,
+	pub video_frame_command: Option<String>,
+	pub threads: Option<usize>,
+
+	#[serde(default)]
+	pub archive: bool,
+	#[serde(default)]
+	pub image_name_titles: bool,
+	#[serde(default)]
+	pub remove_nested_duplicates: bool,
+	#[serde(default, deserialize_with = "mi::de::deserialize_from_str")]
+	pub exif_fields: Option<ExifFields>,
+	#[serde(default, deserialize_with = "mi::de::deserialize_from_str")]
+	pub caption_sources: Option<CaptionSources>,
+	/// Order of the pictures of the collections that do not have their own
+	pub sort: Option<SortOrder>,
+
+	/// Directory tree whose directories with pictures are added as collections
+	pub scan: Option<PathBuf>,
+	pub scan_backgrounds: Option<PathBuf>,
+	#[serde(default)]
+	pub scan_nested: bool,
+
+	#[serde(default)]
+	pub collections: Vec<ProjectCollection>,
+}
+
+/// A collection of the project file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectCollection {
+	pub title: String,
+
+	/// Directories whose pictures are added, or glob patterns of picture files
+	#[serde(default)]
+	pub inputs: Vec<String>,
+
+	/// Directories whose pictures are used as backgrounds, or glob patterns of background files
+	#[serde(default)]
+	pub backgrounds: Vec<String>,
+
+	/// Markdown
+	pub description: Option<String>,
+	#[serde(default, deserialize_with = "mi::de::deserialize_from_str")]
+	pub dates: Option<DateRange>,
+	pub location: Option<String>,
+	/// File name of a picture of the collection
+	pub cover: Option<String>,
+	pub sort: Option<SortOrder>,
+	/// Title of the collection this one is shown below
+	pub parent: Option<String>,
+
+	/// Render settings of the collection that differ from the ones of the gallery
+	#[serde(default, deserialize_with = "mi::de::deserialize_from_str")]
+	pub thumb_size: Option<Resolution>,
+	#[serde(default, deserialize_with = "mi::de::deserialize_from_str")]
+	pub display_size: Option<Resolution>,
+	#[serde(default, deserialize_with = "mi::de::deserialize_from_str")]
+	pub background_size: Option<Resolution>,
+	pub quality: Option<u8>,
+	pub resize_method: Option<String>,
+}
+
+impl Project {
+	pub fn read(path: &PathBuf) -> Result<Project, Error> {
+		let data = std::fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+		toml::from_str(&data).map_err(|e| Error::Config(format!("{}: Invalid project file: {}", path.to_string_lossy(), e)))
+	}
+
+	/// Sets the options of the configuration that were not given on the command line. The collections of the
+	/// project (given or scanned) are only used if no collections were given on the command line.
+	pub fn apply(self, config: &mut Configuration, project_dir: &PathBuf) -> Result<(), Error> {
+		if config.output_dir.as_os_str().is_empty() {
+			if let Some(output) = self.output {
+				config.output_dir = project_dir.join(output);
+			}
+		}
+		if config.template_dir.is_none() && !config.command.existing() {
+			config.template_dir = self.template.map(|t| project_dir.join(t));
+		}
+
+		let render = &mut config.render;
+		render.tumb_size = render.tumb_size.or(self.thumb_size);
+		render.display_size = render.display_size.or(self.display_size);
+		render.background_size = render.background_size.or(self.background_size);
+		render.cover_size = render.cover_size.or(self.cover_size);
+		render.formats = render.formats.take().or(self.formats);
+		render.data_layout = render.data_layout.or(self.data_layout);
+		render.jpeg_quality = render.jpeg_quality.or(self.quality);
+		render.resize_method = render.resize_method.take().or(self.resize_method);
+		render.matte_color = render.matte_color.or(self.matte_color);
+		render.video_frame_command = render.video_frame_command.take().or(self.video_frame_command);
+		render.threads = render.threads.or(self.threads);
+
+		let input = &mut config.input;
+		let collections_given = !input.collections.is_empty() || input.scan.is_some();
+		input.create_full_archive |= self.archive;
+		input.image_name_titles |= self.image_name_titles;
+		input.remove_nested_duplicates |= self.remove_nested_duplicates;
+		input.exif_fields = input.exif_fields.take().or(self.exif_fields);
+		input.caption_sources = input.caption_sources.take().or(self.caption_sources);
+
+		// Only commands adding pictures use the collections
+		if !collections_given && matches!(config.command, super::Command::Build | super::Command::Add) {
+			for c in self.collections {
+				input.collections.push(c.into_input(project_dir, &self.sort)?);
+			}
+			if let Some(root) = self.scan {
+				let backgrounds = input.scan_backgrounds.clone().or(self.scan_backgrounds.map(|b| project_dir.join(b)));
+				for mut c in super::scan::scan(&project_dir.join(root), backgrounds.as_ref(), input.scan_nested || self.scan_nested)? {
+					c.sort = self.sort.clone();
+					input.collections.push(c);
+				}
+			}
+		}
+
+		Ok(())
+	}
+}
+
+impl ProjectCollection {
+	fn into_input(self, project_dir: &PathBuf, sort: &Option<SortOrder>) -> Result<CollectionInput, Error> {
+		if self.title.is_empty() || self.title == "-" {
+			return Err(Error::Config(String::from("Collections must have valid titles")));
+		}
+
+		// Details given in the project file take precedence over those of the collection files
+		let mut details = CollectionDetails::default();
+		for entry in self.inputs.iter().map(|i| project_dir.join(i)).filter(|p| p.is_dir()) {
+			details.update(CollectionDetails::read(&entry)?);
+		}
+		details.update(CollectionDetails{
+			description: self.description,
+			dates: self.dates,
+			location: self.location,
+			cover: self.cover,
+		});
+
+		Ok(CollectionInput{
+			name: crate::mi::fs::sanitize(&self.title),
+			title: self.title,
+			input_dir: None,
+			background_dir: None,
+			input_files: resolve(&self.inputs, project_dir)?,
+			background_files: resolve(&self.backgrounds, project_dir)?,
+			details,
+			render: RenderOverrides{
+				res_thumb: self.thumb_size,
+				res_display: self.display_size,
+				res_background: self.background_size,
+				quality: self.quality,
+				method: self.resize_method,
+			},
+			sort: self.sort.or_else(|| sort.clone()),
+			parent: self.parent.map(|p| crate::mi::fs::sanitize(&p)),
+			exists: false,
+		})
+	}
+}
+
+/// Returns the images and videos of the given directories and glob patterns, sorted per entry. Other files, like
+/// collection files, order files and caption sidecars, are left out.
+fn resolve(entries: &[String], project_dir: &PathBuf) -> Result<Vec<PathBuf>, Error> {
+	let mut files = Vec::new();
+
+	for entry in entries {
+		let path = project_dir.join(entry);
+		let mut matched: Vec<PathBuf> = match path.is_dir() {
+			true => crate::mi::fs::list_dir(&path),
+			false => {
+				let pattern = path.to_string_lossy();
+				let matches = glob::glob(&pattern).map_err(|e| Error::Config(format!("Invalid pattern \"{}\": {}", entry, e)))?;
+				let mut matched: Vec<PathBuf> = matches.filter_map(Result::ok).filter(|p| p.is_file()).collect();
+				matched.sort();
+				matched
+			},
+		};
+
+		matched.retain(crate::gallery::valid_file);
+		if matched.is_empty() {
+			return Err(Error::Config(format!("No images or videos found for \"{}\"", entry)));
+		}
+		files.append(&mut matched);
+	}
+
+	Ok(files)
+}
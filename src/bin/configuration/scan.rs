@@ -0,0 +1,139 @@
+use mi::error::Error;
+use mi::logger::{debugln, warnln};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use crate::gallery::CollectionDetails;
+use crate::gallery::CollectionInput;
+use crate::gallery::RenderOverrides;
+
+/// Name of the subdirectory holding the backgrounds of a scanned directory and its subdirectories
+pub const BACKGROUNDS_DIR_NAME: &str = "_backgrounds";
+
+/// Returns a collection for each directory of the tree that has pictures. Only leaf directories are used, unless
+/// `nested` is set. Then the directories below the root are nested collections, directories with pictures and
+/// subdirectories are collections as well and those without pictures parents without own pictures.
+///
+/// The backgrounds of a directory are those of its `_backgrounds` subdirectory, or of the nearest parent
+/// directory below the root that has one, or the shared background directory.
+pub fn scan(root: &PathBuf, shared_backgrounds: Option<&PathBuf>, nested: bool) -> Result<Vec<CollectionInput>, Error> {
+	if !root.is_dir() {
+		return Err(Error::Config(format!("Scan directory {} does not exist", root.to_string_lossy())));
+	}
+
+	let mut found = Vec::new();
+	walk(root, Vec::new(), shared_backgrounds.cloned(), nested, &mut HashSet::new(), &mut found);
+	let parent_key = |path: &Vec<String>| match (nested, path.len()) {
+		(true, len) if len > 1 => Some(crate::mi::fs::sanitize(&path[..len - 1].join(" / "))),
+		_ => None,
+	};
+
+	if found.is_empty() {
+		return Err(Error::Config(format!("No directories with pictures found in {}", root.to_string_lossy())));
+	}
+
+	// Directories of the same name in different parents are told apart by their path
+	let root_name = root.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+	let title = |path: &Vec<String>, full: bool| match (path.last(), full) {
+		(None, _) => root_name.clone(),
+		(Some(name), false) => name.clone(),
+		(Some(_), true) => path.join(" / "),
+	};
+	let mut names: HashMap<String, usize> = HashMap::new();
+	for d in &found {
+		*names.entry(crate::mi::fs::sanitize(&title(&d.path, false))).or_default() += 1;
+	}
+
+	// Nested collections are titled by their directory name, their keys keep the path
+	let mut collections = Vec::new();
+	for d in found {
+		let full = nested || names[&crate::mi::fs::sanitize(&title(&d.path, false))] > 1;
+		let name = crate::mi::fs::sanitize(&title(&d.path, full));
+		let title = title(&d.path, full && !nested);
+		debugln(format!("Scanned collection \"{}\": {}", title, d.dir.to_string_lossy()));
+
+		collections.push(CollectionInput{
+			name,
+			title,
+			details: CollectionDetails::read(&d.dir)?,
+			input_dir: match d.pictures {
+				true => Some(d.dir),
+				false => None,
+			},
+			background_dir: d.backgrounds,
+			input_files: Vec::new(),
+			background_files: Vec::new(),
+			render: RenderOverrides::default(),
+			sort: None,
+			parent: parent_key(&d.path),
+			exists: false,
+		});
+	}
+
+	Ok(collections)
+}
+
+/// A directory with pictures
+struct ScannedDir {
+	dir: PathBuf,
+	/// Names of the directories below the root
+	path: Vec<String>,
+	backgrounds: Option<PathBuf>,
+	/// Whether the directory has pictures itself, nested directories without are parents of those below
+	pictures: bool,
+}
+
+/// Adds the directory and those below it, returns whether any of them was added. Directories already visited
+/// through a symbolic link are skipped, so links to parent directories do not lead to endless recursion.
+fn walk(
+	dir: &PathBuf,
+	path: Vec<String>,
+	backgrounds: Option<PathBuf>,
+	nested: bool,
+	visited: &mut HashSet<PathBuf>,
+	found: &mut Vec<ScannedDir>,
+) -> bool {
+	let canonical = |d: &PathBuf| d.canonicalize().unwrap_or_else(|_| d.clone());
+	if !visited.insert(canonical(dir)) {
+		warnln(format!("{} was already scanned", dir.to_string_lossy()));
+		return false;
+	}
+
+	let own_backgrounds = dir.join(BACKGROUNDS_DIR_NAME);
+	let backgrounds = match crate::gallery::contains_images(&own_backgrounds) {
+		true => Some(own_backgrounds),
+		false => backgrounds,
+	};
+
+	let subdirs: Vec<PathBuf> = crate::mi::fs::list_dir(dir)
+		.into_iter()
+		.filter(|d| d.is_dir())
+		.filter(|d| d.file_name().is_some_and(|n| !n.to_string_lossy().starts_with(['.', '_'])))
+		.filter(|d| !visited.contains(&canonical(d)))
+		.collect();
+
+	let pictures = crate::gallery::contains_images(dir);
+	let added = pictures && (subdirs.is_empty() || nested);
+	if pictures && !added {
+		warnln(format!("Pictures of {} are not added, it has subdirectories (see --scan-nested)", dir.to_string_lossy()));
+	}
+
+	// Parents are added before their children, the root is never a parent
+	let parent = nested && !path.is_empty();
+	let index = found.len();
+	if added || parent {
+		found.push(ScannedDir{ dir: dir.clone(), path: path.clone(), backgrounds: backgrounds.clone(), pictures });
+	}
+
+	let mut children = false;
+	for d in subdirs {
+		let mut sub_path = path.clone();
+		sub_path.push(d.file_name().unwrap().to_string_lossy().to_string());
+		children |= walk(&d, sub_path, backgrounds.clone(), nested, visited, found);
+	}
+
+	if parent && !pictures && !children {
+		found.remove(index);
+	}
+
+	added || children
+}
@@ -1,11 +1,28 @@
-use mi::logger::debugln;
+use mi::error::Error;
+use mi::logger::{debugln, warnln};
+use crate::gallery::Decoded;
+use crate::gallery::Failure;
+use crate::gallery::FileNames;
 use crate::gallery::GalleryImages;
 use crate::gallery::Image;
+use crate::gallery::Kind;
+use crate::gallery::Manifest;
+use crate::gallery::ManifestEntry;
+use crate::gallery::Render;
 use crate::gallery::Picture;
+use crate::gallery::SortOrder;
+use crate::gallery::Variant;
 // use crate::mi::fs::clean_basename;
 use crate::mi::img::Resolution;
+use crate::mi::img::Color;
+use crate::mi::img::Format;
+use crate::mi::img::SourceInfo;
+use crate::mi::img::Variant as ImageVariant;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::path::PathBuf;
+use std::sync::mpsc::Sender;
 use threadpool::ThreadPool;
 
 #[derive(Debug)]
@@ -14,9 +31,33 @@ pub struct CollectionInput {
 	pub name: String,
 	pub input_dir: Option<PathBuf>,
 	pub background_dir: Option<PathBuf>,
+	/// Pictures given individually (e.g. matched by the globs of a project file), added after those of input_dir
+	pub input_files: Vec<PathBuf>,
+	/// Backgrounds given individually, added after those of background_dir
+	pub background_files: Vec<PathBuf>,
+	/// Description, dates, location and cover of the collection
+	pub details: CollectionDetails,
+	/// Render settings of the collection that differ from the ones of the gallery
+	pub render: RenderOverrides,
+	/// Order of the pictures, the order stored in the gallery is used for existing collections if not given
+	pub sort: Option<SortOrder>,
+	/// Key of the collection this one is shown below, existing collections keep their place if not given
+	pub parent: Option<String>,
 	pub exists: bool,
 }
 
+impl CollectionInput {
+	/// Returns whether pictures are given, as directory or as files
+	pub fn has_pictures(&self) -> bool {
+		self.input_dir.is_some() || !self.input_files.is_empty()
+	}
+
+	/// Returns whether backgrounds are given, as directory or as files
+	pub fn has_backgrounds(&self) -> bool {
+		self.background_dir.is_some() || !self.background_files.is_empty()
+	}
+}
+
 impl std::str::FromStr for CollectionInput {
 	type Err = String;
 
@@ -53,18 +94,28 @@ impl std::str::FromStr for CollectionInput {
 		};
 		let title = String::from(parts[2]);
 		let name = crate::mi::fs::sanitize(title.as_str());
+		let details = match &input_dir {
+			Some(dir) => CollectionDetails::read(dir).map_err(|e| e.to_string())?,
+			None => CollectionDetails::default(),
+		};
 
 		Ok(CollectionInput {
 			title,
 			name,
 			input_dir,
 			background_dir,
+			input_files: Vec::new(),
+			background_files: Vec::new(),
+			details,
+			render: RenderOverrides::default(),
+			sort: None,
+			parent: None,
 			exists: false,
 		})
 	}
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct Collection {
 	/// Collection title as shown to the user
 	pub title: String,
@@ -72,6 +123,47 @@ pub struct Collection {
 	/// Collection basename (directory name) derived from title
 	pub name: String,
 
+	/// Text introducing the collection (Markdown)
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub description: Option<String>,
+
+	/// The description rendered to HTML
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub description_html: Option<String>,
+
+	/// Dates the pictures of the collection were taken
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub dates: Option<DateRange>,
+
+	/// Where the pictures of the collection were taken
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub location: Option<String>,
+
+	/// Basename of the picture representing the collection on overview pages, it has an additional cover variant
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub cover: Option<String>,
+
+	/// Render settings that differ from the ones of the gallery, the gallery settings are used for all others
+	#[serde(default, skip_serializing_if = "RenderOverrides::is_empty")]
+	pub render: RenderOverrides,
+
+	/// Order of the pictures, pictures of collections without order are shown in the order they were added
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub sort: Option<SortOrder>,
+
+	/// Key of the collection this one is shown below, none for top level collections
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub parent: Option<String>,
+
+	/// Keys of the collections shown below this one, in the order they are shown
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub children: Vec<String>,
+
+	/// File the pictures and backgrounds are stored in, relative to the gallery. Only set in the gallery data of
+	/// galleries stored by collection, the collection files contain the full collections.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub data_file: Option<String>,
+
 	pub pictures: Vec<Picture>,
 	pub backgrounds: Vec<Image>,
 }
@@ -85,7 +177,10 @@ impl Collection {
 		use_filenames_as_titles: bool,
 	) -> Collection {
 		let mut pictures: Vec<Picture> = GalleryImages::from(&picture_paths);
-		let backgrounds = GalleryImages::from(&background_paths);
+		let mut backgrounds: Vec<Image> = GalleryImages::from(&background_paths);
+
+		pictures.filter_valid();
+		backgrounds.filter_valid();
 
 		if !use_filenames_as_titles {
 			pictures.clear_titles();
@@ -94,146 +189,769 @@ impl Collection {
 		Collection {
 			title,
 			name,
+			description: None,
+			description_html: None,
+			dates: None,
+			location: None,
+			cover: None,
+			render: RenderOverrides::default(),
+			sort: None,
+			parent: None,
+			children: Vec::new(),
+			data_file: None,
 			pictures,
 			backgrounds,
 		}
 	}
 
-	pub fn append(&mut self, mut other: Collection) {
-		self.pictures.append(&mut other.pictures);
-		self.backgrounds.append(&mut other.backgrounds);
+	/// Adds the pictures and backgrounds of the other collection. Those already part of this collection (same
+	/// content) are not added again, the existing ones remember the new source instead.
+	pub fn append(&mut self, other: Collection) {
+		for pic in other.pictures {
+			if let Some(existing) = self.pictures.iter_mut().find(|p| p.image.original_hash == pic.image.original_hash) {
+				adopt_source(&mut existing.image, pic.image);
+			} else if let Some(existing) = find_legacy(self.pictures.iter_mut().map(|p| &mut p.image), &pic.image) {
+				replace_legacy_hash(existing, pic.image);
+			} else {
+				self.pictures.push(pic);
+			}
+		}
+
+		for bg in other.backgrounds {
+			if let Some(existing) = self.backgrounds.iter_mut().find(|b| b.original_hash == bg.original_hash) {
+				adopt_source(existing, bg);
+			} else if let Some(existing) = find_legacy(self.backgrounds.iter_mut(), &bg) {
+				replace_legacy_hash(existing, bg);
+			} else {
+				self.backgrounds.push(bg);
+			}
+		}
+
+		self.sort_pictures();
 	}
 
+	/// Sorts the pictures in the order of the collection, if it has one
+	pub fn sort_pictures(&mut self) {
+		if let Some(order) = &self.sort {
+			crate::gallery::sort::sort_pictures(&mut self.pictures, order, &self.title);
+		}
+	}
+
+	/// Sets the details given, the others keep their previous value. The cover is given as file name of the source
+	/// or as basename of a picture of the collection. The sources of existing pictures are not stored, they can only
+	/// be found by file name if added again or sorted by name.
+	pub fn set_details(&mut self, details: CollectionDetails) -> Result<(), Error> {
+		if let Some(cover) = details.cover {
+			let picture = self.pictures.iter().find(|p| {
+				p.image.basename == cover
+					|| p.image.source_path.file_name().is_some_and(|n| n.to_string_lossy() == cover)
+					|| p.sort_keys.name.as_ref() == Some(&cover)
+			});
+			match picture {
+				Some(p) => self.cover = Some(p.image.basename.clone()),
+				None => return Err(Error::Config(format!(
+					"Cover \"{}\" is not a picture of collection \"{}\", pictures added before are given by their basename",
+					cover,
+					self.title,
+				))),
+			}
+		}
+
+		if let Some(description) = details.description {
+			self.description_html = Some(markdown_to_html(&description));
+			self.description = Some(description);
+		}
+		self.dates = details.dates.or(self.dates.take());
+		self.location = details.location.or(self.location.take());
+
+		Ok(())
+	}
+
+	/// Drops the cover if it is no longer a picture of the collection
+	pub fn check_cover(&mut self) {
+		if let Some(cover) = &self.cover {
+			if !self.pictures.iter().any(|p| p.image.basename == *cover) {
+				warnln(format!("Cover {} of collection \"{}\" was removed", cover, self.title));
+				self.cover = None;
+			}
+		}
+	}
+
+	/// Returns whether the picture is the cover of the collection
+	fn is_cover(&self, picture: &Picture) -> bool {
+		self.cover.as_ref() == Some(&picture.image.basename)
+	}
+
+	/// Queues the creation of the missing and outdated files of all pictures and backgrounds. Files the manifest
+	/// records as created from the same source with the same settings are skipped. Images whose basename is
+	/// already in `planned` (duplicates) are skipped as well. Returns the files queued for each picture and
+	/// background, failures of the queued jobs are sent to `failures` and the properties of the decoded images to
+	/// `decoded`.
 	pub fn create_images(
 		&mut self,
 		pool: &ThreadPool,
+		failures: &Sender<Failure>,
+		decoded: &Sender<Decoded>,
 		output_dir: &PathBuf,
-		quality: u8,
-		method: &String,
-		res_thumb: Resolution,
-		res_display: Resolution,
-		res_background: Resolution,
-	) -> Result<(), Box<dyn std::error::Error>> {
+		settings: &RenderSettings,
+		manifest: &Manifest,
+		planned: &mut HashSet<(String, bool)>,
+	) -> Result<Vec<QueuedImage>, Error> {
 		// Create picture directory if not already existing
-		let pictures_dir = output_dir.join(crate::gallery::PICTURES_DIR_NAME);
-		std::fs::create_dir_all(&pictures_dir)?;
+		let names = &settings.file_names;
+		let pictures_dir = output_dir.join(&names.pictures_dir);
+		std::fs::create_dir_all(&pictures_dir).map_err(|e| Error::io(&pictures_dir, e))?;
+
+		let mut queued = Vec::new();
 
-		// TODO: Do not overwrite pictures with the same name but different content
 		for p in self.pictures.iter_mut() {
-			if !p.image.update {
+			let cover = self.cover.as_ref() == Some(&p.image.basename);
+			if p.image.update {
+				let extensions = [
+					format!("{}.{}", names.suffix(Variant::Thumb), crate::gallery::PICTURE_EXTENSION),
+					format!("{}.{}", names.suffix(Variant::Display), crate::gallery::PICTURE_EXTENSION),
+					format!("{}.{}", names.suffix(Variant::Cover), crate::gallery::PICTURE_EXTENSION),
+					String::from(crate::gallery::PICTURE_EXTENSION),
+					String::from(crate::gallery::VIDEO_EXTENSION),
+				];
+				p.image.basename = free_basename(output_dir, &p.image, manifest, names, &extensions);
+				if cover {
+					self.cover = Some(p.image.basename.clone());
+				}
+			}
+
+			// The cover variant of a picture already planned by another collection is queued on its own
+			let mut outputs = Vec::new();
+			if planned.insert((p.image.basename.clone(), false)) {
+				outputs = picture_outputs(p.kind, &p.image.basename, settings);
+			}
+			if cover && planned.insert((format!("{}.{}", p.image.basename, names.suffix(Variant::Cover)), false)) {
+				outputs.append(&mut cover_outputs(&p.image.basename, settings));
+			}
+			if outputs.is_empty() {
+				continue;
+			}
+
+			let reporter = Reporter::new(failures, decoded, &self.name, &p.image.basename, false);
+			if let Some(q) = queue_outputs(pool, reporter, output_dir, &mut p.image, p.kind, outputs, settings, manifest) {
+				queued.push(q);
+			}
+		}
+
+		for b in self.backgrounds.iter_mut() {
+			if b.update {
+				let extensions = [format!("{}.{}", names.suffix(Variant::Background), crate::gallery::PICTURE_EXTENSION)];
+				b.basename = free_basename(output_dir, b, manifest, names, &extensions);
+			}
+			if !planned.insert((b.basename.clone(), true)) {
 				continue;
 			}
 
-			let source = p.image.source_path.clone();
+			let target = settings.file_names.pictures_dir.join(&b.basename);
+			let outputs = variant_outputs(&target, Variant::Background, settings.res_background, settings);
+			let reporter = Reporter::new(failures, decoded, &self.name, &b.basename, true);
+			if let Some(q) = queue_outputs(pool, reporter, output_dir, b, Kind::Image, outputs, settings, manifest) {
+				queued.push(q);
+			}
+		}
 
-			let mut target = pictures_dir.clone();
-			let file_stem = p.image.basename.clone();
-			target.push(&file_stem);
+		Ok(queued)
+	}
 
-			let ext = crate::gallery::PICTURE_EXTENSION;
+	/// Returns the files all pictures and backgrounds have when created with the given settings
+	pub fn files(&self, settings: &RenderSettings) -> Vec<ImageFiles<'_>> {
+		let pictures = self.pictures.iter().map(|p| {
+			let mut outputs = picture_outputs(p.kind, &p.image.basename, settings);
+			if self.is_cover(p) {
+				outputs.append(&mut cover_outputs(&p.image.basename, settings));
+			}
+			ImageFiles{
+				image: &p.image,
+				background: false,
+				files: outputs.into_iter().map(|o| o.file).collect(),
+			}
+		});
+
+		let backgrounds = self.backgrounds.iter().map(|b| {
+			let target = settings.file_names.pictures_dir.join(&b.basename);
+			ImageFiles{
+				image: b,
+				background: true,
+				files: variant_outputs(&target, Variant::Background, settings.res_background, settings).into_iter().map(|o| o.file).collect(),
+			}
+		});
 
-			let thumb = target.with_extension(format!("thumb.{}", ext));
-			let display = target.with_extension(format!("disp.{}", ext));
+		pictures.chain(backgrounds).collect()
+	}
+}
 
-			let source_thumb = source.clone();
-			let res_thumb = res_thumb.clone();
-			let method_thumb = String::from(method);
+/// The files of a picture or background, relative to the output directory
+#[derive(Debug)]
+pub struct ImageFiles<'a> {
+	pub image: &'a Image,
+	pub background: bool,
+	pub files: Vec<PathBuf>,
+}
+
+/// Description, dates, location and cover of a collection, given on the command line, in the project file or in
+/// the collection file of the input directory
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CollectionDetails {
+	/// Markdown, rendered to HTML when building the gallery
+	pub description: Option<String>,
+	pub dates: Option<DateRange>,
+	pub location: Option<String>,
+	/// File name of the source of the cover picture, or its basename
+	pub cover: Option<String>,
+}
+
+/// Collection file (collection.toml) in an input directory
+///
+/// Example:
+///
+/// ```toml
+/// description = """
+/// Two weeks on *Maui*, mostly at the coast.
+/// """
+/// dates = "2023-07-01..2023-07-14"
+/// location = "Maui, Hawaii"
+/// cover = "IMG_1209.jpg"
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CollectionFile {
+	description: Option<String>,
+	dates: Option<String>,
+	location: Option<String>,
+	cover: Option<String>,
+}
 
-			let source_display = source.clone();
-			let res_display = res_display.clone();
-			let method_display = String::from(method);
+impl CollectionDetails {
+	/// Reads the collection file of the directory, the details are empty if it has none
+	pub fn read(dir: &PathBuf) -> Result<CollectionDetails, Error> {
+		let path = dir.join(crate::gallery::COLLECTION_FILE_NAME);
+		if !path.is_file() {
+			return Ok(CollectionDetails::default());
+		}
+
+		let data = std::fs::read_to_string(&path).map_err(|e| Error::io(&path, e))?;
+		let invalid = |e: String| Error::Config(format!("{}: Invalid collection file: {}", path.to_string_lossy(), e));
+		let file: CollectionFile = toml::from_str(&data).map_err(|e| invalid(e.to_string()))?;
 
-			let source_full = source.clone();
+		Ok(CollectionDetails{
+			description: file.description,
+			dates: file.dates.map(|d| d.parse()).transpose().map_err(invalid)?,
+			location: file.location,
+			cover: file.cover,
+		})
+	}
 
-			let mut target_thumb = thumb.clone();
-			let mut target_display = display.clone();
-			let mut target_full = target.with_extension(ext);
+	/// Sets the details given in the other ones, keeps the others
+	pub fn update(&mut self, other: CollectionDetails) {
+		self.description = other.description.or(self.description.take());
+		self.dates = other.dates.or(self.dates.take());
+		self.location = other.location.or(self.location.take());
+		self.cover = other.cover.or(self.cover.take());
+	}
 
-			let mut i = 0;
-			while target_thumb.exists() || target_display.exists() || target_full.exists() {
-				i += 1;
-				target_thumb.set_file_name(format!("{}-{}.thumb.{}", &file_stem, i, ext));
-				target_display.set_file_name(format!("{}-{}.disp.{}", &file_stem, i, ext));
-				target_full.set_file_name(format!("{}-{}.{}", &file_stem, i, ext));
+	/// Sets one detail by the name used on the command line and in the collection file
+	pub fn set(&mut self, field: &str, value: &str) -> Result<(), String> {
+		match field {
+			"description" => self.description = Some(String::from(value)),
+			"dates" => self.dates = Some(value.parse()?),
+			"location" => self.location = Some(String::from(value)),
+			"cover" => self.cover = Some(String::from(value)),
+			_ => return Err(format!("Unknown collection detail \"{}\". Valid details: description, dates, location, cover", field)),
+		}
+
+		Ok(())
+	}
+}
+
+/// Dates as "YYYY", "YYYY-MM" or "YYYY-MM-DD", a single date has the same start and end
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct DateRange {
+	pub start: String,
+	pub end: String,
+}
+
+impl std::str::FromStr for DateRange {
+	type Err = String;
+
+	/// Parses "[start]..[end]" or a single date
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (start, end) = s.split_once("..").unwrap_or((s, s));
+		let (start, end) = (start.trim(), end.trim());
+
+		for date in [start, end].iter() {
+			if !is_date(date) {
+				return Err(format!("Invalid date \"{}\", must be \"YYYY\", \"YYYY-MM\" or \"YYYY-MM-DD\"", date));
 			}
-			p.image.basename = String::from(target_full.file_stem().unwrap().to_string_lossy());
+		}
+		if start > end {
+			return Err(format!("Invalid dates \"{}\", the start is after the end", s));
+		}
 
-			pool.execute(move || {
-				debugln(format!(
-					"Resize {} \t=> {} ({:?})",
-					source_thumb.to_str().unwrap(),
-					target_thumb.to_str().unwrap(),
-					res_thumb
-				));
-				crate::mi::img::resize(
-					&source_thumb,
-					&target_thumb,
-					res_thumb,
-					quality,
-					&method_thumb,
-				);
-			});
+		Ok(DateRange{ start: String::from(start), end: String::from(end) })
+	}
+}
 
-			pool.execute(move || {
-				debugln(format!(
-					"Resize {} \t=> {} ({:?})",
-					source_display.to_str().unwrap(),
-					target_display.to_str().unwrap(),
-					res_display
-				));
-				crate::mi::img::resize(
-					&source_display,
-					&target_display,
-					res_display,
-					quality,
-					&method_display,
-				);
-			});
+impl std::fmt::Display for DateRange {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self.start == self.end {
+			true => write!(f, "{}", self.start),
+			false => write!(f, "{}..{}", self.start, self.end),
+		}
+	}
+}
 
-			pool.execute(move || {
-				debugln(format!(
-					"Recode {} \t=> {}",
-					source_full.to_str().unwrap(),
-					target_full.to_str().unwrap()
-				));
-				crate::mi::img::recode(&source_full, &target_full, quality);
-			});
+fn is_date(date: &str) -> bool {
+	let parts: Vec<&str> = date.split('-').collect();
+	let number = |i: usize, len: usize, max: u32| {
+		parts[i].len() == len && parts[i].bytes().all(|b| b.is_ascii_digit()) && (1..=max).contains(&parts[i].parse().unwrap_or(0))
+	};
+
+	match parts.len() {
+		1 => number(0, 4, 9999),
+		2 => number(0, 4, 9999) && number(1, 2, 12),
+		3 => number(0, 4, 9999) && number(1, 2, 12) && number(2, 2, 31),
+		_ => false,
+	}
+}
+
+fn markdown_to_html(text: &str) -> String {
+	let mut html = String::new();
+	pulldown_cmark::html::push_html(&mut html, pulldown_cmark::Parser::new(text));
+	html
+}
+
+/// Render settings of a collection that differ from the ones of the gallery
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RenderOverrides {
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub res_thumb: Option<Resolution>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub res_display: Option<Resolution>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub res_background: Option<Resolution>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub quality: Option<u8>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub method: Option<String>,
+}
+
+impl RenderOverrides {
+	pub fn is_empty(&self) -> bool {
+		*self == RenderOverrides::default()
+	}
+
+	/// Sets the overrides given in the other ones, keeps the others
+	pub fn update(&mut self, other: RenderOverrides) {
+		self.res_thumb = other.res_thumb.or(self.res_thumb);
+		self.res_display = other.res_display.or(self.res_display);
+		self.res_background = other.res_background.or(self.res_background);
+		self.quality = other.quality.or(self.quality);
+		self.method = other.method.or(self.method.take());
+	}
+}
+
+impl std::str::FromStr for RenderOverrides {
+	type Err = String;
+
+	/// Parses comma separated settings with the names of the command line options, e.g.
+	/// "display-size=4000x2000,jpeg-quality=90,resize-method=cubic"
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let mut overrides = RenderOverrides::default();
+
+		for setting in s.split(',').filter(|s| !s.is_empty()) {
+			let (name, value) = match setting.split_once('=') {
+				Some((n, v)) => (n.trim(), v.trim()),
+				None => return Err(format!("Invalid setting \"{}\", must be \"name=value\"", setting)),
+			};
+
+			match name {
+				"thumb-size" => overrides.res_thumb = Some(Resolution::from_str(value)?),
+				"display-size" => overrides.res_display = Some(Resolution::from_str(value)?),
+				"background-size" => overrides.res_background = Some(Resolution::from_str(value)?),
+				"jpeg-quality" => overrides.quality = Some(value.parse().map_err(|_| format!("Invalid jpeg quality \"{}\"", value))?),
+				"resize-method" => overrides.method = Some(String::from(value)),
+				_ => return Err(format!(
+					"Unknown setting \"{}\". Valid settings: thumb-size, display-size, background-size, jpeg-quality, resize-method",
+					name,
+				)),
+			}
 		}
 
-		// TODO: Do not overwrite pictures with the same name but different content
-		for p in self.backgrounds.iter_mut() {
-			if !p.update {
+		Ok(overrides)
+	}
+}
+
+/// Settings the files of the pictures and backgrounds are created with
+#[derive(Debug, Clone)]
+pub struct RenderSettings {
+	pub quality: u8,
+	pub method: String,
+	pub matte: Color,
+	pub formats: Vec<Format>,
+	pub res_thumb: Resolution,
+	pub res_display: Resolution,
+	pub res_background: Resolution,
+	pub res_cover: Resolution,
+	pub video_frame_command: String,
+	pub file_names: FileNames,
+}
+
+impl RenderSettings {
+	/// Returns the settings of a collection with the given overrides
+	pub fn with_overrides(&self, overrides: &RenderOverrides) -> RenderSettings {
+		RenderSettings{
+			quality: overrides.quality.unwrap_or(self.quality),
+			method: overrides.method.clone().unwrap_or_else(|| self.method.clone()),
+			res_thumb: overrides.res_thumb.unwrap_or(self.res_thumb),
+			res_display: overrides.res_display.unwrap_or(self.res_display),
+			res_background: overrides.res_background.unwrap_or(self.res_background),
+			..self.clone()
+		}
+	}
+
+	fn render(&self, resolution: Option<Resolution>, format: Format) -> Render {
+		Render{
+			resolution,
+			format,
+			quality: self.quality,
+			method: self.method.clone(),
+			matte: self.matte.to_string(),
+		}
+	}
+}
+
+/// The files queued for one picture or background
+#[derive(Debug)]
+pub struct QueuedImage {
+	pub basename: String,
+	pub background: bool,
+	/// Manifest entries of the queued files
+	pub outputs: Vec<(PathBuf, ManifestEntry)>,
+}
+
+/// A file of a picture or background
+struct Output {
+	/// Path relative to the output directory
+	file: PathBuf,
+	/// Resized variant, none for the full size version and the video
+	variant: Option<Variant>,
+	/// How the file is rendered, none for videos which are copied unchanged
+	render: Option<Render>,
+}
+
+/// Keeps the existing image but remembers the source of the newly added identical one, so its files can be
+/// created from the original instead of the full size version
+fn adopt_source(existing: &mut Image, new: Image) {
+	if existing.update {
+		return;
+	}
+
+	debugln(format!("{} is already part of the collection", new.source_path.to_string_lossy()));
+	existing.source_path = new.source_path;
+}
+
+/// Returns the image of a gallery of version 1 created from the source of the new image. Version 1 named the
+/// files of the images by the checksum of their source, so the file name of the image is the checksum.
+fn find_legacy<'a>(mut images: impl Iterator<Item = &'a mut Image>, new: &Image) -> Option<&'a mut Image> {
+	let mut checksum = None;
+	images.find(|img| {
+		if !img.legacy_hash {
+			return false;
+		}
+		let checksum = checksum.get_or_insert_with(|| crate::mi::fs::file_checksum_legacy(&new.source_path).ok());
+		checksum.is_some_and(|c| img.basename == c.to_string())
+	})
+}
+
+/// Replaces the checksum of an image of a gallery of version 1 by the content hash of its source. The files
+/// keep their names.
+fn replace_legacy_hash(existing: &mut Image, new: Image) {
+	debugln(format!("{} was added before content hashes were used, replacing its checksum", new.source_path.to_string_lossy()));
+	existing.original_hash = new.original_hash;
+	existing.legacy_hash = false;
+	existing.source_path = new.source_path;
+}
+
+/// Returns the basename for a new image. The basename derived from the hash is used, unless files with that name
+/// exist that were not created from the same source. In that case a number is appended.
+fn free_basename(output_dir: &PathBuf, image: &Image, manifest: &Manifest, names: &FileNames, extensions: &[String]) -> String {
+	let mut basename = image.basename.clone();
+
+	let mut i = 0;
+	loop {
+		let taken = extensions.iter().any(|ext| {
+			let file = names.file(&basename, ext);
+			output_dir.join(&file).exists() && !manifest.created_from(&file, &image.original_hash)
+		});
+
+		if !taken {
+			return basename;
+		}
+
+		i += 1;
+		basename = format!("{}-{}", image.basename, i);
+	}
+}
+
+/// Returns all files of a picture
+fn picture_outputs(kind: Kind, basename: &str, settings: &RenderSettings) -> Vec<Output> {
+	let target = settings.file_names.pictures_dir.join(basename);
+
+	let mut outputs = Vec::new();
+	if kind == Kind::Video {
+		outputs.push(Output{
+			file: target.with_extension(crate::gallery::VIDEO_EXTENSION),
+			variant: None,
+			render: None,
+		});
+	}
+
+	// The full size version of videos is the extracted frame
+	outputs.push(Output{
+		file: target.with_extension(crate::gallery::PICTURE_EXTENSION),
+		variant: None,
+		render: Some(settings.render(None, Format::Jpeg)),
+	});
+	outputs.append(&mut variant_outputs(&target, Variant::Display, settings.res_display, settings));
+	outputs.append(&mut variant_outputs(&target, Variant::Thumb, settings.res_thumb, settings));
+
+	outputs
+}
+
+/// Returns the files of the cover variant of a picture
+fn cover_outputs(basename: &str, settings: &RenderSettings) -> Vec<Output> {
+	let target = settings.file_names.pictures_dir.join(basename);
+	variant_outputs(&target, Variant::Cover, settings.res_cover, settings)
+}
+
+/// Returns the files of one variant of the image in all formats
+fn variant_outputs(target: &PathBuf, variant: Variant, resolution: Resolution, settings: &RenderSettings) -> Vec<Output> {
+	settings.formats
+		.iter()
+		.map(|format| Output{
+			file: target.with_extension(format!("{}.{}", settings.file_names.suffix(variant), format.extension())),
+			variant: Some(variant),
+			render: Some(settings.render(Some(resolution), *format)),
+		})
+		.collect()
+}
+
+/// Returns whether the file has to be created (again)
+fn is_pending(output: &Output, image: &Image, manifest: &Manifest, output_dir: &PathBuf) -> bool {
+	let exists = output_dir.join(&output.file).is_file();
+
+	match manifest.outputs.get(&output.file) {
+		Some(entry) => !exists || entry.hash != image.original_hash || entry.render != output.render,
+		// Files of existing images that are not in the manifest were created before manifests were written
+		None => image.update || !exists || output.variant.is_some_and(|v| image.outdated.contains(&v)),
+	}
+}
+
+/// Queues one job that creates the pending files of a picture or background. New files are created from the
+/// original source if it is known. Otherwise (images of an existing gallery) the full size version is used, or
+/// without one the previous (jpeg) version of each variant.
+fn queue_outputs(
+	pool: &ThreadPool,
+	reporter: Reporter,
+	output_dir: &PathBuf,
+	image: &mut Image,
+	kind: Kind,
+	outputs: Vec<Output>,
+	settings: &RenderSettings,
+	manifest: &Manifest,
+) -> Option<QueuedImage> {
+	let pending: Vec<Output> = outputs.into_iter().filter(|o| is_pending(o, image, manifest, output_dir)).collect();
+	image.outdated.clear();
+	image.formats = settings.formats.clone();
+
+	if pending.is_empty() {
+		return None;
+	}
+
+	let source = match image.source_path.is_file() {
+		true => Some(image.source_path.clone()),
+		false => None,
+	};
+	let full = output_dir.join(settings.file_names.file(&image.basename, crate::gallery::PICTURE_EXTENSION));
+
+	let mut video = None;
+	let mut frame = None;
+	let mut variants = Vec::new();
+	let mut files = Vec::new();
+	for o in pending {
+		let target = output_dir.join(&o.file);
+		match &o.render {
+			None => video = Some(target),
+			Some(_) if kind == Kind::Video && o.variant.is_none() => frame = Some(target),
+			Some(r) => {
+				if r.resolution.is_none() && source.is_none() {
+					warnln(format!("Cannot create {}, the source of the image is not known", target.to_string_lossy()));
+					continue;
+				}
+				variants.push(ImageVariant{ target, resolution: r.resolution, format: r.format });
+			},
+		}
+		files.push(o);
+	}
+
+	if kind == Kind::Video {
+		if (video.is_some() || frame.is_some()) && source.is_none() {
+			warnln(format!("Cannot update video {}, its source is not known", image.basename));
+			return None;
+		}
+		queue_video(pool, reporter.clone(), source.clone().unwrap_or_else(|| full.clone()), video, frame, full, variants, settings);
+	} else if let Some(source) = &source {
+		queue_variants(pool, reporter.clone(), source.clone(), variants, settings);
+	} else if full.is_file() {
+		queue_variants(pool, reporter.clone(), full, variants, settings);
+	} else {
+		// Without full size version each variant can only be created from its previous (jpeg) version
+		let mut created = Vec::new();
+		for variant in variants {
+			let previous = variant.target.with_extension(crate::gallery::PICTURE_EXTENSION);
+			if !previous.exists() {
+				warnln(format!("Cannot update {}, no full size version found", variant.target.to_string_lossy()));
 				continue;
 			}
+			warnln(format!("No full size version of {} found, resizing previous version", variant.target.to_string_lossy()));
+			created.push(variant.target.clone());
+			queue_variants(pool, reporter.clone(), previous, vec![variant], settings);
+		}
+		files.retain(|o| created.contains(&output_dir.join(&o.file)));
+	}
 
-			let source = p.source_path.clone();
-			let method = String::from(method);
-			let ext = crate::gallery::PICTURE_EXTENSION;
-			let mut target = pictures_dir.clone();
-			let file_stem = p.basename.clone();
-			target.push(&file_stem);
-			let mut target_full = target.with_extension(ext);
-			target.set_extension(format!("bg.{}", ext));
-
-			let mut i = 0;
-			while target.exists() {
-				i += 1;
-				target.set_file_name(format!("{}-{}.bg.{}", &file_stem, i, ext));
-				target_full.set_file_name(format!("{}-{}.{}", &file_stem, i, ext));
-			}
-			p.basename = String::from(target_full.file_stem().unwrap().to_string_lossy());
-
-			pool.execute(move || {
-				debugln(format!(
-					"Resize {} \t=> {} ({:?})",
-					source.to_str().unwrap(),
-					target.to_str().unwrap(),
-					res_background
-				));
-				crate::mi::img::resize(&source, &target, res_background, quality, &method);
-			});
+	if files.is_empty() {
+		return None;
+	}
+
+	let outputs = files
+		.into_iter()
+		.map(|o| (o.file, ManifestEntry{
+			source: source.clone(),
+			hash: image.original_hash.clone(),
+			render: o.render,
+		}))
+		.collect();
+
+	Some(QueuedImage{
+		basename: reporter.basename,
+		background: reporter.background,
+		outputs,
+	})
+}
+
+/// Runs the queued jobs of one picture or background and reports their failures and the decoded images
+#[derive(Clone)]
+struct Reporter {
+	failures: Sender<Failure>,
+	decoded: Sender<Decoded>,
+	collection: String,
+	basename: String,
+	background: bool,
+}
+
+impl Reporter {
+	fn new(failures: &Sender<Failure>, decoded: &Sender<Decoded>, collection: &str, basename: &str, background: bool) -> Reporter {
+		Reporter{
+			failures: failures.clone(),
+			decoded: decoded.clone(),
+			collection: String::from(collection),
+			basename: String::from(basename),
+			background,
 		}
+	}
 
-		Ok(())
+	/// Runs the job, errors and panics are reported as failure of the given source
+	fn run<T, F: FnOnce() -> Result<T, Error>>(&self, source: &PathBuf, job: F) -> Option<T> {
+		let error = match std::panic::catch_unwind(std::panic::AssertUnwindSafe(job)) {
+			Ok(Ok(result)) => return Some(result),
+			Ok(Err(e)) => e,
+			Err(_) => Error::Internal(format!("Processing {} crashed", source.to_string_lossy())),
+		};
+
+		self.failures.send(Failure{
+			collection: self.collection.clone(),
+			basename: self.basename.clone(),
+			background: self.background,
+			source: source.clone(),
+			error,
+		}).ok();
+		None
+	}
+
+	/// Reports the properties of the decoded image
+	fn decoded(&self, info: SourceInfo) {
+		self.decoded.send(Decoded{
+			basename: self.basename.clone(),
+			background: self.background,
+			info,
+		}).ok();
+	}
+}
+
+/// Queues one job that creates all variants of the source image, so it only has to be read and decoded once
+fn queue_variants(pool: &ThreadPool, reporter: Reporter, source: PathBuf, variants: Vec<ImageVariant>, settings: &RenderSettings) {
+	let settings = settings.clone();
+	pool.execute(move || {
+		for v in &variants {
+			debugln(format!(
+				"Resize {} \t=> {} ({:?})",
+				source.to_str().unwrap(),
+				v.target.to_str().unwrap(),
+				v.resolution
+			));
+		}
+		let info = reporter.run(&source, || crate::mi::img::create_variants(&source, &variants, settings.quality, &settings.method, settings.matte));
+		if let Some(info) = info {
+			reporter.decoded(info);
+		}
+	});
+}
+
+/// Queues one job that copies the video, extracts a frame from it and creates the image variants from that frame.
+/// Files that are up to date (video or frame) are not created again.
+fn queue_video(
+	pool: &ThreadPool,
+	reporter: Reporter,
+	source: PathBuf,
+	target_video: Option<PathBuf>,
+	target_frame: Option<PathBuf>,
+	frame: PathBuf,
+	variants: Vec<ImageVariant>,
+	settings: &RenderSettings,
+) {
+	let settings = settings.clone();
+	pool.execute(move || {
+		reporter.run(&source, || create_video(&source, target_video, target_frame, &frame, &variants, &settings));
+	});
+}
+
+fn create_video(
+	source: &PathBuf,
+	target_video: Option<PathBuf>,
+	target_frame: Option<PathBuf>,
+	frame: &PathBuf,
+	variants: &[ImageVariant],
+	settings: &RenderSettings,
+) -> Result<(), Error> {
+	if let Some(target) = target_video {
+		debugln(format!("Copy {} \t=> {}", source.to_str().unwrap(), target.to_str().unwrap()));
+		std::fs::copy(source, &target).map_err(|e| Error::io(source, e))?;
+	}
+
+	if let Some(target) = target_frame {
+		debugln(format!("Extract frame {} \t=> {}", source.to_str().unwrap(), target.to_str().unwrap()));
+		crate::mi::video::extract_frame(&settings.video_frame_command, source, &target)?;
 	}
+
+	if variants.is_empty() {
+		return Ok(());
+	}
+
+	crate::mi::img::create_variants(frame, variants, settings.quality, &settings.method, settings.matte).map(|_| ())
 }
@@ -0,0 +1,168 @@
+use mi::error::Error;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use crate::mi::bin::Replace;
+
+/// Where the gallery data is stored
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum DataLayout {
+	/// Embedded in the index.html
+	#[default]
+	Inline,
+	/// In data.json, the index.html only contains the version and the file name as `data_file`
+	File,
+	/// In data.json without pictures and backgrounds, the collections are stored in one file each and reference it
+	/// as `data_file`
+	Collections,
+}
+
+impl std::fmt::Display for DataLayout {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			DataLayout::Inline => write!(f, "inline"),
+			DataLayout::File => write!(f, "file"),
+			DataLayout::Collections => write!(f, "collections"),
+		}
+	}
+}
+
+impl std::str::FromStr for DataLayout {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<DataLayout, String> {
+		match s.trim() {
+			"inline" => Ok(DataLayout::Inline),
+			"file" => Ok(DataLayout::File),
+			"collections" => Ok(DataLayout::Collections),
+			_ => Err(format!("Invalid data layout \"{}\". Valid layouts: \"inline\", \"file\", \"collections\"", s)),
+		}
+	}
+}
+
+/// Reads the gallery data of the gallery in `gallery_dir` in any layout, from the first of the files the template
+/// fills with it. Returns the data with the collections read from their files and the path of the file it was read
+/// from.
+pub fn read(gallery_dir: &PathBuf, data_files: &[PathBuf]) -> Result<(Value, PathBuf), Error> {
+	let index_path = match data_files.first() {
+		Some(file) => gallery_dir.join(file),
+		None => return Err(no_data_files(gallery_dir)),
+	};
+	let html = std::fs::read(&index_path).map_err(|e| Error::io(&index_path, e))?;
+	let json = html.between(crate::gallery::PATTERM_DATA_START, crate::gallery::PATTERM_DATA_END);
+
+	// Data stored in a file is referenced by its file name. The version is embedded as well, so binaries that do
+	// not know the data files refuse the gallery as newer.
+	let data = parse(&json, &index_path)?;
+	let (mut data, path) = match data.get("data_file").and_then(Value::as_str) {
+		Some(file) => {
+			let path = gallery_dir.join(file);
+			(read_json(&path)?, path)
+		},
+		None => (data, index_path),
+	};
+
+	if let Some(collections) = data.get_mut("collections").and_then(Value::as_object_mut) {
+		for c in collections.values_mut() {
+			if let Some(file) = c.get("data_file").and_then(Value::as_str) {
+				*c = read_json(&gallery_dir.join(file))?;
+			}
+		}
+	}
+
+	Ok((data, path))
+}
+
+/// Writes the gallery data in the given layout into the files the template fills with it. Data files of other
+/// layouts and of removed collections are deleted.
+pub fn write(gallery_dir: &PathBuf, data_files: &[PathBuf], mut data: Value, layout: DataLayout) -> Result<(), Error> {
+	if data_files.is_empty() {
+		return Err(no_data_files(gallery_dir));
+	}
+
+	let mut files = Vec::new();
+	for file in data_files {
+		let path = gallery_dir.join(file);
+		let content = std::fs::read(&path).map_err(|e| Error::io(&path, e))?;
+
+		if content.index_of(crate::gallery::PATTERM_DATA_START, 0).is_err() || content.index_of(crate::gallery::PATTERM_DATA_END, 0).is_err() {
+			return Err(Error::Template{ path, message: String::from("Gallery data placeholder not found") });
+		}
+		files.push((path, content));
+	}
+
+	// The collections keep their metadata, so templates can show them before loading the pictures
+	let mut collection_files = HashSet::new();
+	if layout == DataLayout::Collections {
+		let dir = gallery_dir.join(crate::gallery::COLLECTIONS_DIR_NAME);
+		std::fs::create_dir_all(&dir).map_err(|e| Error::io(&dir, e))?;
+
+		for (key, c) in data["collections"].as_object_mut().into_iter().flatten() {
+			let file = format!("{}/{}.json", crate::gallery::COLLECTIONS_DIR_NAME, key);
+			let path = gallery_dir.join(&file);
+			write_json(&path, c)?;
+			collection_files.insert(path);
+
+			c["pictures"] = Value::Array(Vec::new());
+			c["backgrounds"] = Value::Array(Vec::new());
+			c["data_file"] = Value::from(file);
+		}
+	}
+
+	let data_path = gallery_dir.join(crate::gallery::DATA_FILE_NAME);
+	let embedded = match layout {
+		DataLayout::Inline => to_json(&data)?,
+		DataLayout::File | DataLayout::Collections => {
+			write_json(&data_path, &data)?;
+			to_json(&serde_json::json!({
+				"version": data["version"],
+				"data_file": crate::gallery::DATA_FILE_NAME,
+			}))?
+		},
+	};
+
+	for (path, content) in files {
+		let replaced = content.replace_between(crate::gallery::PATTERM_DATA_START, crate::gallery::PATTERM_DATA_END, embedded.as_bytes());
+		std::fs::write(&path, replaced).map_err(|e| Error::io(&path, e))?;
+	}
+
+	// Remove the files that are not used anymore
+	if layout == DataLayout::Inline && data_path.is_file() {
+		std::fs::remove_file(&data_path).map_err(|e| Error::io(&data_path, e))?;
+	}
+	let dir = gallery_dir.join(crate::gallery::COLLECTIONS_DIR_NAME);
+	for file in crate::mi::fs::list_dir(&dir) {
+		if file.extension().is_some_and(|e| e == "json") && !collection_files.contains(&file) {
+			std::fs::remove_file(&file).map_err(|e| Error::io(&file, e))?;
+		}
+	}
+	if dir.is_dir() && collection_files.is_empty() {
+		std::fs::remove_dir(&dir).ok();
+	}
+
+	Ok(())
+}
+
+fn no_data_files(gallery_dir: &PathBuf) -> Error {
+	Error::Data{ path: gallery_dir.clone(), message: String::from("No file of the template stores the gallery data") }
+}
+
+fn parse(json: &[u8], path: &PathBuf) -> Result<Value, Error> {
+	serde_json::from_slice(json).map_err(|e| Error::Data{ path: path.clone(), message: e.to_string() })
+}
+
+fn read_json(path: &PathBuf) -> Result<Value, Error> {
+	let json = std::fs::read(path).map_err(|e| Error::io(path, e))?;
+	parse(&json, path)
+}
+
+fn to_json(data: &Value) -> Result<String, Error> {
+	serde_json::to_string_pretty(data).map_err(|e| Error::Internal(e.to_string()))
+}
+
+fn write_json(path: &PathBuf, data: &Value) -> Result<(), Error> {
+	std::fs::write(path, to_json(data)?).map_err(|e| Error::io(path, e))
+}
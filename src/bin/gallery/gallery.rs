@@ -1,29 +1,195 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::io::Write;
 use std::path::PathBuf;
 use threadpool::ThreadPool;
 use zip::write::FileOptions;
-use mi::logger::{info, infoln, errorln};
-use crate::mi::bin::Replace;
+use mi::error::Error;
+use mi::logger::{debugln, info, infoln, warnln};
+use crate::mi::caption::CaptionSource;
+use crate::mi::img::Color;
+use crate::mi::img::ExifData;
+use crate::mi::img::ExifField;
+use crate::mi::img::ExifFields;
+use crate::mi::img::Format;
 use crate::mi::img::Resolution;
+use crate::mi::img::SourceInfo;
 use crate::gallery::Collection;
+use crate::gallery::CollectionDetails;
 use crate::gallery::CollectionInput;
+use crate::gallery::DataLayout;
+use crate::gallery::FileNames;
 use crate::gallery::Image;
+use crate::gallery::Kind;
+use crate::gallery::Manifest;
+use crate::gallery::RenderOverrides;
+use crate::gallery::RenderSettings;
+use crate::gallery::SortKeys;
+use crate::gallery::SortOrder;
+use crate::gallery::TemplateManifest;
+use crate::gallery::Variant;
 
 
 
-#[derive(Debug, Serialize, Deserialize)]
+/// A picture or background that looks like another one
+#[derive(Debug)]
+pub struct NearDuplicate {
+	pub path: PathBuf,
+	pub similar_to: PathBuf,
+	/// Number of differing bits of the perceptual hashes
+	pub distance: u32,
+}
+
+/// A picture or background whose files could not be created
+#[derive(Debug, Serialize)]
+pub struct Failure {
+	/// Name of the collection the failed job was queued for
+	pub collection: String,
+	pub basename: String,
+	pub background: bool,
+	pub source: PathBuf,
+	#[serde(serialize_with = "serialize_error")]
+	pub error: Error,
+}
+
+/// Properties of the image of a picture or background decoded while creating its files
+#[derive(Debug)]
+pub struct Decoded {
+	pub basename: String,
+	pub background: bool,
+	pub info: SourceInfo,
+}
+
+/// Result of creating the images of a gallery
+#[derive(Debug, Default, Serialize)]
+pub struct BuildReport {
+	/// Number of pictures and backgrounds that were processed
+	pub processed: usize,
+	pub failures: Vec<Failure>,
+}
+
+impl BuildReport {
+	/// Returns an error if any picture or background failed
+	pub fn check(&self) -> Result<(), Error> {
+		match self.failures.len() {
+			0 => Ok(()),
+			n => Err(Error::Incomplete(n)),
+		}
+	}
+
+	/// Writes the report as JSON file
+	pub fn write(&self, path: &PathBuf) -> Result<(), Error> {
+		let json = serde_json::to_string_pretty(self).map_err(|e| Error::Internal(e.to_string()))?;
+		std::fs::write(path, json).map_err(|e| Error::io(path, e))
+	}
+}
+
+fn serialize_error<S: serde::Serializer>(error: &Error, serializer: S) -> Result<S::Ok, S::Error> {
+	use serde::ser::SerializeMap;
+
+	let mut map = serializer.serialize_map(Some(3))?;
+	map.serialize_entry("class", error.class())?;
+	map.serialize_entry("exit_code", &error.exit_code())?;
+	map.serialize_entry("message", &error.to_string())?;
+	map.end()
+}
+
+/// Files of the output directory that do not match the gallery data
+#[derive(Debug, Default)]
+pub struct GarbageReport {
+	/// Files in the picture directory no picture or background references, relative to the output directory
+	pub orphans: Vec<PathBuf>,
+	pub missing: Vec<MissingFile>,
+}
+
+/// A file of a picture or background that does not exist
+#[derive(Debug)]
+pub struct MissingFile {
+	pub collection: String,
+	pub basename: String,
+	pub background: bool,
+	/// Path relative to the output directory
+	pub file: PathBuf,
+	/// Source the file was created from according to the manifest, if it still exists
+	pub source: Option<PathBuf>,
+}
+
+/// Result of checking the files of a gallery
+#[derive(Debug, Default)]
+pub struct IntegrityReport {
+	pub garbage: GarbageReport,
+	/// Errors of the files that exist but cannot be read
+	pub invalid: Vec<Error>,
+}
+
+/// Number of pictures and size of the files of a collection
+#[derive(Debug)]
+pub struct CollectionStatus {
+	pub key: String,
+	pub title: String,
+	pub pictures: usize,
+	pub videos: usize,
+	pub backgrounds: usize,
+	/// Number of existing files of the pictures and backgrounds
+	pub files: usize,
+	/// Size in bytes of these files
+	pub size: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct Gallery {
 	pub version: u16,
 	pub extension: String,
 	pub archives: HashMap<String, String>,
+	/// Collections that have pictures in the order of the tree (parents before their children), for templates
+	/// without nested collections
 	pub collection_keys: Vec<String>,
+	/// Top level collections in the order they are shown, the children of each collection are listed by it
+	#[serde(default)]
+	pub root_keys: Vec<String>,
 	pub collections: HashMap<String, Collection>,
 
 	pub res_background: Resolution,
 	pub res_display: Resolution,
 	pub res_thumb: Resolution,
+	/// Resolution of the cover variant of the cover pictures of the collections
+	#[serde(default = "crate::gallery::default_resolution_cover")]
+	pub res_cover: Resolution,
+
+	/// Quality the images are created with, 1-100
+	#[serde(default = "crate::gallery::default_quality")]
+	pub quality: u8,
+	/// Method the images are resized with
+	#[serde(default = "crate::gallery::default_resize_method")]
+	pub resize_method: String,
+	/// Color that fills transparent parts of the images
+	#[serde(default = "crate::gallery::default_matte_color")]
+	pub matte_color: Color,
+
+	/// Formats the image variants are created in
+	#[serde(default = "crate::gallery::default_formats")]
+	pub formats: Vec<Format>,
+
+	/// Exif fields published in the data of the pictures
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub exif_fields: Vec<ExifField>,
+
+	/// Where the gallery data is stored
+	#[serde(default)]
+	pub data_layout: DataLayout,
+
+	/// How the files in the output directory were created, stored separately from the gallery data
+	#[serde(skip)]
+	pub manifest: Manifest,
+
+	/// Basenames of the pictures and backgrounds removed from the gallery, whose files may have to be deleted
+	#[serde(skip)]
+	removed: HashSet<String>,
+
+	/// Whether exif fields were selected that the existing pictures do not have yet
+	#[serde(skip)]
+	exif_fields_added: bool,
 }
 
 impl Gallery {
@@ -33,97 +199,538 @@ impl Gallery {
 			extension: String::from(crate::gallery::PICTURE_EXTENSION),
 			archives: HashMap::new(),
 			collection_keys: Vec::new(),
+			root_keys: Vec::new(),
 			collections: HashMap::new(),
 			res_background: crate::gallery::DEFAULT_RESOLUTION_BACKGROUND,
 			res_display: crate::gallery::DEFAULT_RESOLUTION_DISPLAY,
-			res_thumb: crate::gallery::DEFAULT_RESOLUTION_THUMB
+			res_thumb: crate::gallery::DEFAULT_RESOLUTION_THUMB,
+			res_cover: crate::gallery::DEFAULT_RESOLUTION_COVER,
+			quality: crate::gallery::DEFAULT_JPEG_QUALITY,
+			resize_method: String::from(crate::gallery::DEFAULT_RESIZE_METHOD),
+			matte_color: Color::WHITE,
+			formats: crate::gallery::default_formats(),
+			exif_fields: Vec::new(),
+			data_layout: DataLayout::Inline,
+			manifest: Manifest::new(),
+			removed: HashSet::new(),
+			exif_fields_added: false,
 		}
 	}
 
-	pub fn from(gallery_dir: &PathBuf)  -> Gallery {
-		// TODO: You cannot override resolutions when updating
+	/// Returns the JSON Schema of the gallery data
+	pub fn schema() -> schemars::schema::RootSchema {
+		schemars::schema_for!(Gallery)
+	}
+
+	/// Reads the gallery data of an existing gallery from its index.html or the data files it references, data of
+	/// older versions is migrated
+	pub fn from(gallery_dir: &PathBuf) -> Result<Gallery, Error> {
+		let manifest = Manifest::read(gallery_dir)?;
+		let (mut data, data_path) = crate::gallery::data::read(gallery_dir, &manifest.data_files)?;
+		crate::gallery::migration::migrate(&mut data, &data_path)?;
 
-		// TODO: Get json from index.html and deserialize
-		let index_path = gallery_dir.join("index.html");
-		let data = match std::fs::read(index_path) {
-			Ok(d) => d,
+		let mut gallery: Gallery = match serde_json::from_value(data) {
+			Ok(g) => g,
 			Err(e) => {
-				errorln(format!("Cannot update existing gallery. Error: {}", e));
-				std::process::exit(1); // TODO: Consistent exit codes
+				return Err(Error::Data{ path: data_path, message: e.to_string() });
 			}
 		};
 
-		let json = data.between(crate::gallery::PATTERM_DATA_START, crate::gallery::PATTERM_DATA_END);
+		gallery.manifest = manifest;
 
-		let gallery = match serde_json::from_slice(&json) {
-			Ok(g) => g,
-			Err(e) => {
-				errorln(format!("Cannot update existing gallery. Invalid Data. Error: {}", e));
-				std::process::exit(2); // TODO: Consistent exit codes
+		for (key, c) in gallery.collections.iter_mut() {
+			let keys = match gallery.manifest.sort_keys.get(key) {
+				Some(k) => k,
+				None => continue,
+			};
+			for p in c.pictures.iter_mut() {
+				if let Some(k) = keys.get(&p.image.original_hash) {
+					p.sort_keys = k.clone();
+				}
+			}
+		}
+
+		Ok(gallery)
+	}
+
+	/// Sets the resolutions of the image variants. Variants of already existing images that were created
+	/// with a different resolution are marked as outdated so they are rendered again.
+	pub fn set_resolutions(&mut self, res_thumb: Option<Resolution>, res_display: Option<Resolution>, res_background: Option<Resolution>) {
+		let mut outdated = Vec::new();
+
+		if let Some(res) = res_thumb {
+			if res != self.res_thumb {
+				infoln(format!("Thumbnail resolution changed from {} to {}", self.res_thumb, res));
+				self.res_thumb = res;
+				outdated.push(Variant::Thumb);
 			}
+		}
+		if let Some(res) = res_display {
+			if res != self.res_display {
+				infoln(format!("Display resolution changed from {} to {}", self.res_display, res));
+				self.res_display = res;
+				outdated.push(Variant::Display);
+			}
+		}
+		if let Some(res) = res_background {
+			if res != self.res_background {
+				infoln(format!("Background resolution changed from {} to {}", self.res_background, res));
+				self.res_background = res;
+				outdated.push(Variant::Background);
+			}
+		}
+
+		if outdated.is_empty() {
+			return;
+		}
+
+		self.mark_outdated(&outdated, |_| true);
+	}
+
+	/// Sets the resolution of the cover variants. The manifest records the resolution each cover was created with,
+	/// so covers with a different one are rendered again.
+	pub fn set_cover_resolution(&mut self, res_cover: Option<Resolution>) {
+		if let Some(res) = res_cover {
+			if res != self.res_cover {
+				infoln(format!("Cover resolution changed from {} to {}", self.res_cover, res));
+				self.res_cover = res;
+			}
+		}
+	}
+
+	/// Sets the quality, resize method and matte color the images are created with. The manifest records the
+	/// settings each file was created with, so files created with other settings are rendered again.
+	pub fn set_render_settings(&mut self, quality: Option<u8>, method: Option<String>, matte: Option<Color>) {
+		if let Some(quality) = quality.filter(|q| *q != self.quality) {
+			infoln(format!("Jpeg quality changed from {} to {}", self.quality, quality));
+			self.quality = quality;
+		}
+		if let Some(method) = method.filter(|m| *m != self.resize_method) {
+			infoln(format!("Resize method changed from {} to {}", self.resize_method, method));
+			self.resize_method = method;
+		}
+		if let Some(matte) = matte.filter(|m| *m != self.matte_color) {
+			infoln(format!("Matte color changed from {} to {}", self.matte_color, matte));
+			self.matte_color = matte;
+		}
+	}
+
+	/// Sets the formats the image variants are created in. JPEG is always included as fallback. Variants of
+	/// already existing images that are missing one of the formats are marked as outdated so they are rendered again.
+	pub fn set_formats(&mut self, formats: Option<Vec<Format>>) {
+		let requested = match formats {
+			Some(f) => f,
+			None => return,
 		};
 
-		gallery
+		let mut formats = vec![Format::Jpeg];
+		for f in requested {
+			if !formats.contains(&f) {
+				formats.push(f);
+			}
+		}
+
+		if formats == self.formats {
+			return;
+		}
+
+		infoln(format!("Formats changed from {:?} to {:?}", self.formats, formats));
+		self.formats = formats.clone();
+
+		let all = [Variant::Thumb, Variant::Display, Variant::Background];
+		self.mark_outdated(&all, |image| formats.iter().any(|f| !image.formats.contains(f)));
+	}
+
+	/// Sets the exif fields published in the gallery data. Fields that are no longer published are removed from
+	/// the existing pictures, newly published ones are only read for pictures whose source is given.
+	pub fn set_exif_fields(&mut self, fields: Option<ExifFields>) {
+		let fields = match fields {
+			Some(f) => f.0,
+			None => return,
+		};
+		self.exif_fields_added = fields.iter().any(|f| !self.exif_fields.contains(f));
+
+		for c in self.collections.values_mut() {
+			for p in c.pictures.iter_mut() {
+				if let Some(exif) = &mut p.exif {
+					exif.retain(&fields);
+				}
+				p.exif = p.exif.take().filter(|e| !e.is_empty());
+			}
+		}
+
+		self.exif_fields = fields;
+	}
+
+	/// Reads the newly selected exif fields of the existing images whose source is known, the exif data of new
+	/// images is read while their files are created. Unreadable exif data is reported as warning, the previous
+	/// data of the picture is kept in that case.
+	pub fn read_exif(&mut self) {
+		let fields = &self.exif_fields;
+		if fields.is_empty() || !self.exif_fields_added {
+			return;
+		}
+
+		for c in self.collections.values_mut() {
+			for p in c.pictures.iter_mut() {
+				if p.kind != Kind::Image || p.image.update || p.image.source_path.as_os_str().is_empty() {
+					continue;
+				}
+
+				match crate::mi::img::get_exif_data(&p.image.source_path) {
+					Ok(exif) => {
+						p.exif = exif.map(|mut e| {
+							e.retain(fields);
+							e
+						}).filter(|e| !e.is_empty());
+					},
+					Err(e) => warnln(e.to_string()),
+				}
+			}
+		}
+	}
+
+	/// Reads titles and descriptions of the pictures added in this run from the given sources. The title and
+	/// description of a picture are kept if none is found.
+	pub fn read_captions(&mut self, sources: &[CaptionSource]) {
+		if sources.is_empty() {
+			return;
+		}
+		let sidecars: Vec<CaptionSource> = sources.iter().filter(|s| s.is_sidecar()).copied().collect();
+
+		for c in self.collections.values_mut() {
+			for p in c.pictures.iter_mut() {
+				if !p.image.update {
+					continue;
+				}
+
+				// Only images store captions in their metadata
+				let sources = match p.kind {
+					Kind::Image => sources,
+					Kind::Video => &sidecars,
+				};
+				let caption = crate::mi::caption::read_caption(&p.image.source_path, sources);
+
+				if let Some(title) = caption.title {
+					p.title = title;
+				}
+				if caption.description.is_some() {
+					p.description = caption.description;
+				}
+			}
+		}
+	}
+
+	/// Marks the given variants of all existing images matching the filter as outdated
+	fn mark_outdated<F: Fn(&Image) -> bool>(&mut self, variants: &[Variant], filter: F) {
+		let picture_variants: Vec<Variant> = variants.iter().filter(|v| **v != Variant::Background).copied().collect();
+		let background_variants: Vec<Variant> = variants.iter().filter(|v| **v == Variant::Background).copied().collect();
+
+		// Images used more than once (duplicates) share their files and must only be rendered once
+		let mut seen_pictures: HashSet<String> = HashSet::new();
+		let mut seen_backgrounds: HashSet<String> = HashSet::new();
+
+		for k in &self.keys() {
+			let c = self.collections.get_mut(k).unwrap();
+			for pic in c.pictures.iter_mut() {
+				if filter(&pic.image) && seen_pictures.insert(pic.image.basename.clone()) {
+					add_outdated(&mut pic.image, &picture_variants);
+				}
+			}
+			for bg in c.backgrounds.iter_mut() {
+				if filter(bg) && seen_backgrounds.insert(bg.basename.clone()) {
+					add_outdated(bg, &background_variants);
+				}
+			}
+		}
 	}
 
+	pub fn fill(&mut self, collection_inputs: Vec<CollectionInput>, use_filenames_as_titles: bool) -> Result<(), Error> {
+		// Parents are created before their children are moved below them
+		let parents: Vec<(String, String)> = collection_inputs.iter()
+			.filter_map(|c| c.parent.as_ref().map(|p| (c.name.clone(), p.clone())))
+			.collect();
 
-	pub fn fill(&mut self, collection_inputs: Vec<CollectionInput>, use_filenames_as_titles: bool) -> Result<(), String> {
 		for mut c in collection_inputs {
 			c.exists = self.collections.contains_key(&c.name);
+			let has_pictures = c.has_pictures();
 
-
-			let background_paths: Vec<PathBuf> = match &c.background_dir {
-				Some(d) => crate::mi::fs::list_dir(&d),
+			let mut background_paths: Vec<PathBuf> = match &c.background_dir {
+				Some(d) => crate::mi::fs::list_dir(d),
 				None => Vec::new(),
 			};
+			background_paths.append(&mut c.background_files);
 
-			let picture_paths: Vec<PathBuf> = match &c.input_dir {
-				Some(d) => crate::mi::fs::list_dir(&d),
+			let mut picture_paths: Vec<PathBuf> = match &c.input_dir {
+				Some(d) => crate::mi::fs::list_dir(d),
 				None => Vec::new(),
 			};
+			picture_paths.append(&mut c.input_files);
 
-			let collection = Collection::new(c.name.clone(), c.title.clone(), picture_paths, background_paths, use_filenames_as_titles);
+			let mut collection = Collection::new(c.name.clone(), c.title.clone(), picture_paths, background_paths, use_filenames_as_titles);
+			collection.render = c.render.clone();
+			collection.sort = c.sort.clone();
 
 			if c.exists {
-				self.collections.get_mut(&c.name).unwrap().append(collection);
+				let existing = self.collections.get_mut(&c.name).unwrap();
+				if c.sort.is_some() {
+					existing.sort = c.sort;
+				}
+				existing.render.update(c.render);
+				existing.append(collection);
+				existing.set_details(c.details)?;
 			} else {
-				if c.input_dir.is_none() {
-					return Err(format!("Cannot create new collection without input directory: {}", c.title));
+				if !has_pictures && !parents.iter().any(|(_, p)| *p == c.name) {
+					return Err(Error::Config(format!("Cannot create new collection without pictures: {}", c.title)));
 				}
-				self.collection_keys.push(c.name.clone());
+				collection.set_details(c.details)?;
+				collection.sort_pictures();
+				self.root_keys.push(c.name.clone());
 				self.collections.insert(c.name, collection);
 			}
 		}
 
+		for (key, parent) in parents {
+			self.set_parent(&key, Some(&parent))?;
+		}
+		self.update_keys();
+
+		Ok(())
+	}
+
+	/// Returns the keys of all collections in the order of the tree, parents before their children
+	pub fn keys(&self) -> Vec<String> {
+		let mut keys = Vec::new();
+		let mut pending: Vec<&String> = self.root_keys.iter().rev().collect();
+		while let Some(k) = pending.pop() {
+			keys.push(k.clone());
+			pending.extend(self.collections[k].children.iter().rev());
+		}
+
+		keys
+	}
+
+	/// Returns the number of parents of the collection
+	pub fn depth(&self, key: &str) -> usize {
+		let mut depth = 0;
+		let mut parent = &self.collections[key].parent;
+		while let Some(p) = parent {
+			depth += 1;
+			parent = &self.collections[p].parent;
+		}
+
+		depth
+	}
+
+	/// Updates the flat list of the collections with pictures after the tree or the pictures changed
+	fn update_keys(&mut self) {
+		self.collection_keys = self.keys().into_iter().filter(|k| !self.collections[k].pictures.is_empty()).collect();
+	}
+
+	/// Moves the collection below the parent, as its last child, or to the top level without parent
+	pub fn set_parent(&mut self, key: &str, parent: Option<&str>) -> Result<(), Error> {
+		if !self.collections.contains_key(key) {
+			return Err(Error::Config(format!("Cannot move collection \"{}\", it does not exist", key)));
+		}
+		if let Some(p) = parent {
+			if !self.collections.contains_key(p) {
+				return Err(Error::Config(format!("Cannot move collection \"{}\" below \"{}\", it does not exist", key, p)));
+			}
+			let mut ancestor = Some(p);
+			while let Some(a) = ancestor {
+				if a == key {
+					return Err(Error::Config(format!("Cannot move collection \"{}\" below itself", key)));
+				}
+				ancestor = self.collections[a].parent.as_deref();
+			}
+		}
+
+		self.detach(key);
+		match parent {
+			Some(p) => self.collections.get_mut(p).unwrap().children.push(String::from(key)),
+			None => self.root_keys.push(String::from(key)),
+		}
+		self.collections.get_mut(key).unwrap().parent = parent.map(String::from);
+		self.update_keys();
+
+		Ok(())
+	}
+
+	/// Removes the collection from the children of its parent or the top level collections
+	fn detach(&mut self, key: &str) {
+		match self.collections[key].parent.clone() {
+			Some(p) => self.collections.get_mut(&p).unwrap().children.retain(|k| k != key),
+			None => self.root_keys.retain(|k| k != key),
+		}
+	}
+
+	/// Sets the render settings of an existing collection that differ from the ones of the gallery. Settings not
+	/// given keep their previous value.
+	pub fn set_overrides(&mut self, key: &str, overrides: RenderOverrides) -> Result<(), Error> {
+		match self.collections.get_mut(key) {
+			Some(c) => {
+				c.render.update(overrides);
+				Ok(())
+			},
+			None => Err(Error::Config(format!("Cannot change settings of collection \"{}\", it does not exist", key))),
+		}
+	}
+
+	/// Sets the order of the pictures of an existing collection and sorts them
+	pub fn set_sort(&mut self, key: &str, order: SortOrder) -> Result<(), Error> {
+		match self.collections.get_mut(key) {
+			Some(c) => {
+				c.sort = Some(order);
+				c.sort_pictures();
+				Ok(())
+			},
+			None => Err(Error::Config(format!("Cannot sort collection \"{}\", it does not exist", key))),
+		}
+	}
+
+	/// Sets the description, dates, location or cover of an existing collection. Details not given keep their
+	/// previous value.
+	pub fn set_details(&mut self, key: &str, details: CollectionDetails) -> Result<(), Error> {
+		match self.collections.get_mut(key) {
+			Some(c) => c.set_details(details),
+			None => Err(Error::Config(format!("Cannot change details of collection \"{}\", it does not exist", key))),
+		}
+	}
+
+	/// Removes the collection with the given key (sanitized title) and its children
+	pub fn remove_collection(&mut self, key: &str) -> Result<(), Error> {
+		if !self.collections.contains_key(key) {
+			return Err(Error::Config(format!("Cannot remove collection \"{}\", it does not exist", key)));
+		}
+		self.detach(key);
+
+		let mut pending = vec![String::from(key)];
+		while let Some(k) = pending.pop() {
+			let c = self.collections.remove(&k).unwrap();
+			pending.extend(c.children);
+
+			self.removed.extend(c.pictures.into_iter().map(|p| p.image.basename));
+			self.removed.extend(c.backgrounds.into_iter().map(|b| b.basename));
+			infoln(format!("Removed collection {}", k));
+		}
+		self.update_keys();
+
 		Ok(())
 	}
 
+	/// Removes all pictures and backgrounds with the given basename from all collections and returns how many
+	/// were removed
+	pub fn remove_image(&mut self, basename: &str) -> Result<usize, Error> {
+		let removed = self.remove_images(|img| img.basename == basename);
+		if removed == 0 {
+			return Err(Error::Config(format!("Cannot remove picture \"{}\", it does not exist", basename)));
+		}
+
+		Ok(removed)
+	}
+
+	/// Removes all pictures and backgrounds created from the given source file from all collections and returns
+	/// how many were removed. The source is identified by its content hash if it still exists and by the paths
+	/// recorded in the manifest.
+	pub fn remove_source(&mut self, source: &PathBuf) -> Result<usize, Error> {
+		let mut hashes: HashSet<String> = self.manifest.outputs
+			.values()
+			.filter(|e| e.source.as_ref() == Some(source))
+			.map(|e| e.hash.clone())
+			.collect();
+		if source.is_file() {
+			hashes.insert(crate::mi::fs::file_hash(source)?);
+		}
+
+		let removed = self.remove_images(|img| hashes.contains(&img.original_hash));
+		if removed == 0 {
+			return Err(Error::Config(format!("Cannot remove {}, no picture of the gallery was created from it", source.to_string_lossy())));
+		}
+
+		Ok(removed)
+	}
+
+	/// Removes the pictures and backgrounds matching the filter from all collections
+	fn remove_images<F: Fn(&Image) -> bool>(&mut self, filter: F) -> usize {
+		let mut removed = 0;
+
+		for k in &self.keys() {
+			let c = self.collections.get_mut(k).unwrap();
+			let (pictures, backgrounds) = (c.pictures.len(), c.backgrounds.len());
+
+			for img in c.pictures.iter().map(|p| &p.image).chain(c.backgrounds.iter()).filter(|img| filter(img)) {
+				self.removed.insert(img.basename.clone());
+			}
+			c.pictures.retain(|p| !filter(&p.image));
+			c.backgrounds.retain(|b| !filter(b));
+			c.check_cover();
+
+			let count = pictures - c.pictures.len() + backgrounds - c.backgrounds.len();
+			if count > 0 {
+				infoln(format!("Removed {} pictures and backgrounds from collection {}", count, k));
+			}
+			removed += count;
+		}
+		self.update_keys();
+
+		removed
+	}
+
+	/// Deletes the files in the output directory of removed pictures and backgrounds that no other picture or
+	/// background of the gallery references. Returns the deleted files.
+	pub fn delete_removed(&mut self, output_dir: &PathBuf) -> Result<Vec<PathBuf>, Error> {
+		let mut referenced: HashSet<&str> = HashSet::new();
+		for c in self.collections.values() {
+			referenced.extend(c.pictures.iter().map(|p| p.image.basename.as_str()));
+			referenced.extend(c.backgrounds.iter().map(|b| b.basename.as_str()));
+		}
+
+		let picture_dir = output_dir.join(&self.manifest.file_names.pictures_dir);
+		let entries = match std::fs::read_dir(&picture_dir) {
+			Ok(entries) => entries,
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+			Err(e) => return Err(Error::io(&picture_dir, e)),
+		};
+
+		let mut deleted = Vec::new();
+		for entry in entries {
+			let path = entry.map_err(|e| Error::io(&picture_dir, e))?.path();
+			let basename = file_basename(&path);
+			if !path.is_file() || !self.removed.contains(&basename) || referenced.contains(basename.as_str()) {
+				continue;
+			}
+
+			std::fs::remove_file(&path).map_err(|e| Error::io(&path, e))?;
+			debugln(format!("Deleted {}", path.to_string_lossy()));
+
+			if let Ok(file) = path.strip_prefix(output_dir) {
+				self.manifest.outputs.remove(file);
+			}
+			deleted.push(path);
+		}
+
+		self.manifest.write(output_dir)?;
+		Ok(deleted)
+	}
+
 	pub fn remove_duplicates(&mut self) {
 		// Remove duplicate pictures across galleries
-		infoln(format!("Searching for duplicates... "));
+		infoln(String::from("Searching for duplicates... "));
 
-		let mut file_hashes: HashMap<u64, Vec<Image>> = HashMap::new();
+		let mut file_hashes: HashMap<String, Vec<Image>> = HashMap::new();
 
-		for k in &self.collection_keys {
+		for k in &self.keys() {
 
 			for bg in &self.collections[k].backgrounds {
-				let hash = bg.original_hash;
+				let hash = bg.original_hash.clone();
 
-				if !file_hashes.contains_key(&hash) {
-					file_hashes.insert(hash, vec![]);
-				}
-				let basenames = file_hashes.get_mut(&hash).unwrap();
-				basenames.push(bg.clone());
+				file_hashes.entry(hash).or_default().push(bg.clone());
 			}
 
 			for pic in &self.collections[k].pictures {
-				let hash = pic.image.original_hash;
+				let hash = pic.image.original_hash.clone();
 
-				if !file_hashes.contains_key(&hash) {
-					file_hashes.insert(hash, vec![]);
-				}
-				let basenames = file_hashes.get_mut(&hash).unwrap();
-				basenames.push(pic.image.clone());
+				file_hashes.entry(hash).or_default().push(pic.image.clone());
 			}
 		}
 
@@ -144,7 +751,186 @@ impl Gallery {
 			}
 
 		}
+	}
+
+	/// Removes the pictures of parent collections that are shown by one of their descendants as well. Returns the
+	/// keys of the parent collections and the basenames of the removed pictures.
+	pub fn remove_nested_duplicates(&mut self) -> Vec<(String, String)> {
+		let mut removed = Vec::new();
+		for k in self.keys() {
+			let mut descendants: HashSet<String> = HashSet::new();
+			let mut pending = self.collections[&k].children.clone();
+			while let Some(child) = pending.pop() {
+				let c = &self.collections[&child];
+				descendants.extend(c.pictures.iter().map(|p| p.image.original_hash.clone()));
+				pending.extend(c.children.iter().cloned());
+			}
+			if descendants.is_empty() {
+				continue;
+			}
+
+			let c = self.collections.get_mut(&k).unwrap();
+			let count = removed.len();
+			c.pictures.retain(|p| match descendants.contains(&p.image.original_hash) {
+				true => {
+					removed.push((k.clone(), p.image.basename.clone()));
+					false
+				},
+				false => true,
+			});
+			if removed.len() > count {
+				c.check_cover();
+			}
+		}
+		self.update_keys();
+
+		removed
+	}
+
+	/// Searches pictures and backgrounds that look alike (e.g. the same shot exported with a different compression)
+	/// by comparing their perceptual hashes. Pictures are compared with pictures and backgrounds with backgrounds
+	/// across all collections. If `merge` is set, the later of two near duplicates is replaced by the earlier one,
+	/// preferring images that are already part of the gallery. The hashes are computed when the files are created,
+	/// so the search runs after the images were created. The files of merged images are deleted with the removed ones.
+	pub fn find_near_duplicates(&mut self, output_dir: &PathBuf, max_distance: u32, merge: bool, num_threads: usize) -> Vec<NearDuplicate> {
+		infoln(String::from("Searching for near duplicates... "));
+		self.compute_perceptual_hashes(output_dir, num_threads);
+
+		let names = self.manifest.file_names.clone();
+		let mut found = Vec::new();
+
+		for backgrounds in [false, true] {
+			// Duplicates share their basename and only have to be compared once
+			let mut seen: HashSet<String> = HashSet::new();
+			let mut images: Vec<Image> = Vec::new();
+			for k in &self.keys() {
+				let c = &self.collections[k];
+				let candidates: Vec<&Image> = if backgrounds {
+					c.backgrounds.iter().collect()
+				} else {
+					c.pictures.iter().filter(|p| p.kind == Kind::Image).map(|p| &p.image).collect()
+				};
+
+				for img in candidates {
+					if img.perceptual_hash.is_some() && seen.insert(img.basename.clone()) {
+						images.push(img.clone());
+					}
+				}
+			}
+			// Images already in the gallery come first, so they are kept when merging
+			images.sort_by_key(|img| img.update);
+
+			let mut merged: HashSet<String> = HashSet::new();
+			for (i, a) in images.iter().enumerate() {
+				if merged.contains(&a.basename) {
+					continue;
+				}
+
+				for b in &images[i + 1..] {
+					if merged.contains(&b.basename) {
+						continue;
+					}
+
+					let distance = crate::mi::img::hamming_distance(a.perceptual_hash.unwrap(), b.perceptual_hash.unwrap());
+					if distance > max_distance {
+						continue;
+					}
+
+					found.push(NearDuplicate{
+						path: perceptual_source(b, output_dir, &names, backgrounds),
+						similar_to: perceptual_source(a, output_dir, &names, backgrounds),
+						distance,
+					});
+
+					if merge {
+						merged.insert(b.basename.clone());
+						self.merge_image(&b.basename, a, backgrounds);
+					}
+				}
+			}
+		}
+
+		found
+	}
+
+	/// Computes the perceptual hashes of images whose files were created before the hashes were stored, from their
+	/// full size (or background) version in the output directory
+	fn compute_perceptual_hashes(&mut self, output_dir: &PathBuf, num_threads: usize) {
+		let names = self.manifest.file_names.clone();
+
+		let mut sources: HashSet<PathBuf> = HashSet::new();
+		for c in self.collections.values() {
+			for pic in c.pictures.iter().filter(|p| p.kind == Kind::Image && p.image.perceptual_hash.is_none()) {
+				sources.insert(output_file(&pic.image, output_dir, &names, false));
+			}
+			for bg in c.backgrounds.iter().filter(|b| b.perceptual_hash.is_none()) {
+				sources.insert(output_file(bg, output_dir, &names, true));
+			}
+		}
+		if sources.is_empty() {
+			return;
+		}
+
+		let pool = ThreadPool::new(num_threads);
+		let (tx, rx) = std::sync::mpsc::channel();
+		for source in sources {
+			if !source.is_file() {
+				warnln(format!("Cannot compare {}, file not found", source.to_string_lossy()));
+				continue;
+			}
 
+			let tx = tx.clone();
+			pool.execute(move || {
+				match crate::mi::img::open(&source) {
+					Ok(image) => {
+						let hash = crate::mi::img::dhash(&image);
+						tx.send((source, hash)).ok();
+					},
+					Err(e) => warnln(format!("Cannot compare {}", e)),
+				}
+			});
+		}
+		drop(tx);
+
+		let hashes: HashMap<PathBuf, u64> = rx.iter().collect();
+		pool.join();
+
+		for c in self.collections.values_mut() {
+			for pic in c.pictures.iter_mut().filter(|p| p.kind == Kind::Image && p.image.perceptual_hash.is_none()) {
+				pic.image.perceptual_hash = hashes.get(&output_file(&pic.image, output_dir, &names, false)).copied();
+			}
+			for bg in c.backgrounds.iter_mut().filter(|b| b.perceptual_hash.is_none()) {
+				bg.perceptual_hash = hashes.get(&output_file(bg, output_dir, &names, true)).copied();
+			}
+		}
+	}
+
+	/// Replaces all pictures (or backgrounds) with the given basename by another image
+	fn merge_image(&mut self, basename: &str, with: &Image, backgrounds: bool) {
+		infoln(format!("Merging {} into {}", basename, with.basename));
+		self.removed.insert(String::from(basename));
+
+		for c in self.collections.values_mut() {
+			if !backgrounds && c.cover.as_deref() == Some(basename) {
+				c.cover = Some(with.basename.clone());
+			}
+
+			let images: Vec<&mut Image> = if backgrounds {
+				c.backgrounds.iter_mut().collect()
+			} else {
+				c.pictures.iter_mut().map(|p| &mut p.image).collect()
+			};
+
+			for img in images.into_iter().filter(|img| img.basename == basename) {
+				img.basename = with.basename.clone();
+				img.source_path = with.source_path.clone();
+				img.original_hash = with.original_hash.clone();
+				img.perceptual_hash = with.perceptual_hash;
+				img.formats = with.formats.clone();
+				img.update = false;
+				img.outdated.clear();
+			}
+		}
 	}
 
 	pub fn replace_image(&mut self, basename: String, with: &Image) {
@@ -163,7 +949,7 @@ impl Gallery {
 
 					img.basename = with.basename.clone();
 					img.source_path = with.source_path.clone();
-					img.original_hash = with.original_hash;
+					img.original_hash = with.original_hash.clone();
 					img.update = false;
 
 				}
@@ -178,7 +964,7 @@ impl Gallery {
 
 					pic.image.basename = with.basename.clone();
 					pic.image.source_path = with.source_path.clone();
-					pic.image.original_hash = with.original_hash;
+					pic.image.original_hash = with.original_hash.clone();
 					pic.image.update = false;
 				}
 			}
@@ -187,74 +973,372 @@ impl Gallery {
 	}
 
 
-	pub fn create_images(&mut self, output_dir: &PathBuf, quality: u8, method: &String, num_threads: usize) -> Result<(), Box<dyn std::error::Error>> {
+	/// Returns the settings the files of the gallery are created with
+	pub fn render_settings(&self, video_frame_command: &str) -> RenderSettings {
+		RenderSettings{
+			quality: self.quality,
+			method: self.resize_method.clone(),
+			matte: self.matte_color,
+			formats: self.formats.clone(),
+			res_thumb: self.res_thumb,
+			res_display: self.res_display,
+			res_background: self.res_background,
+			res_cover: self.res_cover,
+			video_frame_command: String::from(video_frame_command),
+			file_names: self.manifest.file_names.clone(),
+		}
+	}
+
+	/// Cross-references the gallery data with the files in the output directory. Lists the files no picture or
+	/// background references (orphans) and the files of pictures and backgrounds that do not exist. Orphans are
+	/// deleted if requested.
+	pub fn collect_garbage(&mut self, output_dir: &PathBuf, settings: &RenderSettings, delete: bool) -> Result<GarbageReport, Error> {
+		let mut report = GarbageReport::default();
+
+		let mut expected: HashSet<PathBuf> = HashSet::new();
+		for k in &self.keys() {
+			for f in self.collections[k].files(settings) {
+				for file in &f.files {
+					if !expected.insert(file.clone()) || output_dir.join(file).is_file() {
+						continue;
+					}
+
+					let source = self.manifest.outputs
+						.get(file)
+						.filter(|e| e.hash == f.image.original_hash)
+						.and_then(|e| e.source.clone())
+						.filter(|s| s.is_file());
+					report.missing.push(MissingFile{
+						collection: k.clone(),
+						basename: f.image.basename.clone(),
+						background: f.background,
+						file: file.clone(),
+						source,
+					});
+				}
+			}
+		}
+
+		let picture_dir = output_dir.join(&self.manifest.file_names.pictures_dir);
+		if picture_dir.is_dir() {
+			let entries = std::fs::read_dir(&picture_dir).map_err(|e| Error::io(&picture_dir, e))?;
+			for entry in entries {
+				let path = entry.map_err(|e| Error::io(&picture_dir, e))?.path();
+				let file = self.manifest.file_names.pictures_dir.join(path.file_name().unwrap_or_default());
+				if path.is_file() && !expected.contains(&file) {
+					report.orphans.push(file);
+				}
+			}
+		}
+		report.orphans.sort();
+
+		if delete {
+			for file in &report.orphans {
+				let path = output_dir.join(file);
+				std::fs::remove_file(&path).map_err(|e| Error::io(&path, e))?;
+				debugln(format!("Deleted {}", path.to_string_lossy()));
+			}
+
+			// Entries of files that are neither expected nor exist any more
+			self.manifest.outputs.retain(|file, _| expected.contains(file));
+			self.manifest.write(output_dir)?;
+		}
+
+		Ok(report)
+	}
+
+	/// Returns the number of pictures and the size of the files of each collection. Files shared by several
+	/// collections are counted for each of them.
+	pub fn status(&self, output_dir: &PathBuf, settings: &RenderSettings) -> Vec<CollectionStatus> {
+		let mut status = Vec::new();
+
+		for k in &self.keys() {
+			let c = &self.collections[k];
+			let mut s = CollectionStatus{
+				key: k.clone(),
+				title: c.title.clone(),
+				pictures: c.pictures.iter().filter(|p| p.kind == Kind::Image).count(),
+				videos: c.pictures.iter().filter(|p| p.kind == Kind::Video).count(),
+				backgrounds: c.backgrounds.len(),
+				files: 0,
+				size: 0,
+			};
+
+			for file in c.files(settings).into_iter().flat_map(|f| f.files) {
+				if let Ok(metadata) = std::fs::metadata(output_dir.join(file)) {
+					s.files += 1;
+					s.size += metadata.len();
+				}
+			}
+
+			status.push(s);
+		}
+
+		status
+	}
+
+	/// Checks that all files of the gallery exist and can be read. Images are decoded completely, so damaged
+	/// files are found as well.
+	pub fn verify(&mut self, output_dir: &PathBuf, settings: &RenderSettings) -> Result<IntegrityReport, Error> {
+		let mut report = IntegrityReport{
+			garbage: self.collect_garbage(output_dir, settings, false)?,
+			invalid: Vec::new(),
+		};
+
+		let mut checked: HashSet<PathBuf> = HashSet::new();
+		for c in self.collections.values() {
+			for file in c.files(settings).into_iter().flat_map(|f| f.files) {
+				let path = output_dir.join(&file);
+				if !checked.insert(file) || !path.is_file() {
+					continue;
+				}
+
+				if let Err(e) = verify_file(&path) {
+					report.invalid.push(e);
+				}
+			}
+		}
+		report.invalid.sort_by_key(|e| e.path().cloned());
+
+		Ok(report)
+	}
+
+	/// Sets the sources recorded for the missing files, so they are created from the original instead of the full
+	/// size version. Sources whose content changed since are not used.
+	pub fn restore_sources(&mut self, missing: &[MissingFile]) {
+		let mut sources: HashMap<(&str, bool), &PathBuf> = HashMap::new();
+		for m in missing {
+			if let Some(source) = &m.source {
+				sources.insert((m.basename.as_str(), m.background), source);
+			}
+		}
+
+		for c in self.collections.values_mut() {
+			let pictures = c.pictures.iter_mut().map(|p| (&mut p.image, false));
+			let backgrounds = c.backgrounds.iter_mut().map(|b| (b, true));
+			for (image, background) in pictures.chain(backgrounds) {
+				let source = match sources.get(&(image.basename.as_str(), background)) {
+					Some(source) => *source,
+					None => continue,
+				};
+
+				match crate::mi::fs::file_hash(source) {
+					Ok(hash) if hash == image.original_hash => image.source_path = source.clone(),
+					Ok(_) => warnln(format!("{} changed since {} was created from it", source.to_string_lossy(), image.basename)),
+					Err(e) => warnln(e.to_string()),
+				}
+			}
+		}
+	}
+
+	/// Creates the missing and outdated files of all pictures and backgrounds and records them in the manifest.
+	/// Pictures and backgrounds that fail do not stop the others, they are listed in the returned report.
+	pub fn create_images(&mut self, output_dir: &PathBuf, video_frame_command: &str, num_threads: usize) -> Result<BuildReport, Error> {
 		let pool = ThreadPool::new(num_threads);
+		let (failures, rx) = std::sync::mpsc::channel();
+		let (decoded, decoded_rx) = std::sync::mpsc::channel();
+		let mut report = BuildReport::default();
+
+		let settings = self.render_settings(video_frame_command);
 
-		for (_, c) in self.collections.iter_mut() {
-			c.create_images(&pool, output_dir, quality, method, self.res_thumb, self.res_display, self.res_background)?;
+		// Duplicates share their files, which only have to be created once. Pictures in several collections with
+		// different render settings are created with the settings of the first one.
+		let mut planned: HashSet<(String, bool)> = HashSet::new();
+		let mut queued = Vec::new();
+		for k in &self.keys() {
+			let c = self.collections.get_mut(k).unwrap();
+			let settings = settings.with_overrides(&c.render);
+			queued.append(&mut c.create_images(&pool, &failures, &decoded, output_dir, &settings, &self.manifest, &mut planned)?);
 		}
+		report.processed = queued.len();
 
 		info(format!("Working on pictures... {} left.\r", pool.queued_count() + pool.active_count()));
 		let mut i = 0;
 		while pool.queued_count() + pool.active_count() > 0 {
-			i = i + 1;
+			i += 1;
 			let n = i % 4;
 			info(format!("Working on pictures{}{} {} left. \r", ".".repeat(n), " ".repeat(3 - n), pool.queued_count() + pool.active_count()));
 			std::thread::sleep(std::time::Duration::from_millis(500));
 		}
-		infoln(format!("Working on pictures. Done         "));
+		infoln(String::from("Working on pictures. Done         "));
 		pool.join();
+		drop(failures);
+		drop(decoded);
 
-		Ok(())
+		report.failures = rx.iter().collect();
+
+		// The perceptual hashes are kept to find near duplicates without decoding the images again, the exif data
+		// and capture times of the pictures are taken from the decoded sources. Full size versions have none, their
+		// pictures keep theirs.
+		let infos: HashMap<(String, bool), SourceInfo> = decoded_rx.iter().map(|d| ((d.basename, d.background), d.info)).collect();
+		let fields = &self.exif_fields;
+		for c in self.collections.values_mut() {
+			let by_date = c.sort == Some(SortOrder::ExifDate);
+			for pic in c.pictures.iter_mut().filter(|p| p.kind == Kind::Image) {
+				if let Some(info) = infos.get(&(pic.image.basename.clone(), false)) {
+					pic.image.perceptual_hash = Some(info.dhash);
+					if let (true, Some(time)) = (by_date, info.exif.as_ref().and_then(ExifData::capture_time)) {
+						pic.sort_keys.date = Some(time);
+					}
+					if let Some(mut exif) = info.exif.clone().filter(|_| !fields.is_empty()) {
+						exif.retain(fields);
+						pic.exif = Some(exif).filter(|e| !e.is_empty());
+					}
+				}
+			}
+			for bg in c.backgrounds.iter_mut() {
+				if let Some(info) = infos.get(&(bg.basename.clone(), true)) {
+					bg.perceptual_hash = Some(info.dhash);
+				}
+			}
+			if by_date {
+				c.sort_pictures();
+			}
+		}
+
+		let failed: HashSet<(&str, bool)> = report.failures.iter().map(|f| (f.basename.as_str(), f.background)).collect();
+		for q in queued {
+			if failed.contains(&(q.basename.as_str(), q.background)) {
+				continue;
+			}
+			self.manifest.outputs.extend(q.outputs);
+		}
+		self.manifest.write(output_dir)?;
+
+		Ok(report)
+	}
+
+	/// Removes the failed pictures and backgrounds from all collections, so the gallery data does not reference
+	/// files that were not created
+	pub fn remove_failed(&mut self, failures: &[Failure]) {
+		let failed_pictures: HashSet<&str> = failures.iter().filter(|f| !f.background).map(|f| f.basename.as_str()).collect();
+		let failed_backgrounds: HashSet<&str> = failures.iter().filter(|f| f.background).map(|f| f.basename.as_str()).collect();
+
+		for c in self.collections.values_mut() {
+			c.pictures.retain(|p| !failed_pictures.contains(p.image.basename.as_str()));
+			c.backgrounds.retain(|b| !failed_backgrounds.contains(b.basename.as_str()));
+			c.check_cover();
+		}
+		self.update_keys();
 	}
 
 
-	pub fn create_archive_full(&mut self, output_dir: &PathBuf) {
+	pub fn create_archive_full(&mut self, output_dir: &PathBuf) -> Result<(), Error> {
 		let archive_path = std::path::PathBuf::from(output_dir).join(crate::gallery::FULL_ARCHIVE_PATH);
 		self.archives.insert(String::from("_full_"), String::from(crate::gallery::FULL_ARCHIVE_PATH));
 
-		let archive_file = std::fs::File::create(archive_path).unwrap();
+		let archive_file = std::fs::File::create(&archive_path).map_err(|e| Error::io(&archive_path, e))?;
 		let mut zip = zip::ZipWriter::new(archive_file);
 		let options = FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+		let zip_error = |e: zip::result::ZipError| Error::io(&archive_path, e.into());
 
-		for (_, c) in &self.collections {
-			let dir_name = crate::mi::fs::sanitize(c.title.as_str());
-			zip.add_directory(&dir_name, options.clone()).unwrap();
+		// Child collections are stored in the directories of their parents
+		for k in self.keys() {
+			let c = &self.collections[&k];
+			let mut dir_name = crate::mi::fs::sanitize(c.title.as_str());
+			let mut parent = &c.parent;
+			while let Some(p) = parent {
+				dir_name = format!("{}/{}", crate::mi::fs::sanitize(self.collections[p].title.as_str()), dir_name);
+				parent = &self.collections[p].parent;
+			}
+			zip.add_directory(&dir_name, options).map_err(zip_error)?;
 			// println!("Starting dir {}", dir_name);
 			for p in &c.pictures {
 				let path = p.image.source_path.clone();
-				let pic = path.file_name().unwrap().to_str().unwrap();
+				let pic = path.file_name().unwrap_or_default().to_string_lossy();
 				// println!("Starting file {}", pic);
 				let file_path = format!("{}/{}", &dir_name, pic);
-				zip.start_file(file_path, options).unwrap();
-				let mut buf = std::fs::read(p.image.source_path.clone()).unwrap();
-				zip.write_all(&mut buf).unwrap();
+				zip.start_file(file_path, options).map_err(zip_error)?;
+				let buf = std::fs::read(&path).map_err(|e| Error::io(&path, e))?;
+				zip.write_all(&buf).map_err(|e| Error::io(&archive_path, e))?;
 			}
 		}
-		zip.finish().unwrap();
+		zip.finish().map_err(zip_error)?;
 
+		Ok(())
 	}
 
-	pub fn include_json_data(&self, output_dir: &PathBuf) {
-		let json = serde_json::to_string_pretty(self).unwrap();
+	/// Sets where the gallery data is stored, the data files of the previous layout are removed when it is written
+	pub fn set_data_layout(&mut self, layout: Option<DataLayout>) {
+		if let Some(layout) = layout.filter(|l| *l != self.data_layout) {
+			infoln(format!("Data layout changed from {} to {}", self.data_layout, layout));
+			self.data_layout = layout;
+		}
+	}
 
-		// Insert JSON data in the index.html
-		let index_path = std::path::PathBuf::from(output_dir).join("index.html");
-		let html = match std::fs::read(&index_path) {
-			Ok(d) => d,
-			Err(e) => {
-				panic!("Could not read from {}: {}", &index_path.to_str().unwrap(), e);
+	/// Writes the gallery data into the index.html or, depending on the data layout, into the data files it references.
+	/// The sort keys of the pictures are written to the manifest.
+	pub fn include_json_data(&mut self, output_dir: &PathBuf) -> Result<(), Error> {
+		self.manifest.sort_keys.clear();
+		for (key, c) in &self.collections {
+			let keys: BTreeMap<String, SortKeys> = c.pictures.iter()
+				.filter(|p| !p.sort_keys.is_empty())
+				.map(|p| (p.image.original_hash.clone(), p.sort_keys.clone()))
+				.collect();
+			if !keys.is_empty() {
+				self.manifest.sort_keys.insert(key.clone(), keys);
 			}
-		};
+		}
+		self.manifest.write(output_dir)?;
 
-		let replaced_html = html.replace_between(crate::gallery::PATTERM_DATA_START, crate::gallery::PATTERM_DATA_END, json.as_bytes());
+		let data = serde_json::to_value(&*self).map_err(|e| Error::Internal(e.to_string()))?;
+		crate::gallery::data::write(output_dir, &self.manifest.data_files, data, self.data_layout)
+	}
 
-		match std::fs::write(&index_path, replaced_html) {
-			Ok(_) => {},
-			Err(e) => {
-				panic!("Could not write to {}: {}", &index_path.to_str().unwrap(), e);
-			}
-		}
+	/// Uses the files of the template for a new gallery: the gallery data is written to the files with the data
+	/// marker and the pictures are named as the template declares
+	pub fn set_template(&mut self, template: &TemplateManifest) {
+		self.manifest.data_files = template.data_files();
+		self.manifest.file_names = template.file_names();
+	}
+
+}
+
+/// Checks that the file is not empty and that images and videos can be read
+fn verify_file(path: &PathBuf) -> Result<(), Error> {
+	let metadata = std::fs::metadata(path).map_err(|e| Error::io(path, e))?;
+	if metadata.len() == 0 {
+		return Err(Error::Decode{ path: path.clone(), message: String::from("Empty file") });
+	}
+
+	let extension = path.extension().unwrap_or_default().to_string_lossy().to_lowercase();
+	if extension == crate::gallery::VIDEO_EXTENSION {
+		crate::mi::video::read_info(path).map(|_| ())
+	} else if extension == Format::Avif.extension() {
+		// Avif files cannot be decoded, only encoded
+		Ok(())
+	} else {
+		crate::mi::img::check(path)
 	}
+}
+
+/// Returns the basename of an output file, the part of the file name before the variant and extension
+fn file_basename(file: &PathBuf) -> String {
+	let name = file.file_name().unwrap_or_default().to_string_lossy();
+	String::from(name.split('.').next().unwrap_or_default())
+}
+
+/// Returns the source of new images and the full size (or background) version of the others
+fn perceptual_source(image: &Image, output_dir: &PathBuf, names: &FileNames, background: bool) -> PathBuf {
+	match image.update {
+		true => image.source_path.clone(),
+		false => output_file(image, output_dir, names, background),
+	}
+}
 
+/// Returns the full size (or background) version of the image in the output directory
+fn output_file(image: &Image, output_dir: &PathBuf, names: &FileNames, background: bool) -> PathBuf {
+	let ext = crate::gallery::PICTURE_EXTENSION;
+	match background {
+		true => output_dir.join(names.file(&image.basename, &format!("{}.{}", names.suffix(Variant::Background), ext))),
+		false => output_dir.join(names.file(&image.basename, ext)),
+	}
+}
+
+fn add_outdated(image: &mut Image, variants: &[Variant]) {
+	for v in variants {
+		if !image.outdated.contains(v) {
+			image.outdated.push(*v);
+		}
+	}
 }
@@ -0,0 +1,152 @@
+use mi::error::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use crate::mi::img::Format;
+use crate::mi::img::Resolution;
+use crate::gallery::SortKeys;
+use crate::gallery::Variant;
+
+/// Records which source and settings each output file was created from, so files that are up to date do not have
+/// to be created again when the gallery is updated
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+	pub version: u16,
+
+	/// Files of the template the gallery data is written to, relative to the output directory. Galleries created
+	/// before they were recorded have the data in their index.html.
+	#[serde(default = "default_data_files")]
+	pub data_files: Vec<PathBuf>,
+
+	/// Names of the picture files the template loads
+	#[serde(default)]
+	pub file_names: FileNames,
+
+	/// Output files by their path relative to the output directory
+	pub outputs: BTreeMap<PathBuf, ManifestEntry>,
+
+	/// Sort keys of the pictures by collection key and content hash of the source, they are not published with
+	/// the gallery data
+	#[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+	pub sort_keys: BTreeMap<String, BTreeMap<String, SortKeys>>,
+}
+
+/// How an output file was created
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+	/// Path of the original source, if it was known when the file was created
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub source: Option<PathBuf>,
+
+	/// Content hash of the original source
+	pub hash: String,
+
+	/// Settings the file was rendered with, none for files copied unchanged (videos)
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub render: Option<Render>,
+}
+
+/// Settings an image file was rendered with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Render {
+	/// Resolution the image was scaled to cover, none for full size versions
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub resolution: Option<Resolution>,
+	pub format: Format,
+	pub quality: u8,
+	pub method: String,
+	pub matte: String,
+}
+
+/// Where the files of the pictures and backgrounds are created and the suffixes of their variants. Templates can
+/// declare their own, they are kept for the lifetime of the gallery.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileNames {
+	/// Directory of the pictures and backgrounds, relative to the output directory
+	pub pictures_dir: PathBuf,
+	pub thumb: String,
+	pub display: String,
+	pub background: String,
+	pub cover: String,
+}
+
+impl Default for FileNames {
+	fn default() -> FileNames {
+		FileNames{
+			pictures_dir: PathBuf::from(crate::gallery::PICTURES_DIR_NAME),
+			thumb: String::from(Variant::Thumb.suffix()),
+			display: String::from(Variant::Display.suffix()),
+			background: String::from(Variant::Background.suffix()),
+			cover: String::from(Variant::Cover.suffix()),
+		}
+	}
+}
+
+impl FileNames {
+	/// The suffix added to the basename of the files of the variant
+	pub fn suffix(&self, variant: Variant) -> &str {
+		match variant {
+			Variant::Thumb => &self.thumb,
+			Variant::Display => &self.display,
+			Variant::Background => &self.background,
+			Variant::Cover => &self.cover,
+		}
+	}
+
+	/// Path of a file of the image relative to the output directory, `extension` includes the suffix of variants
+	pub fn file(&self, basename: &str, extension: &str) -> PathBuf {
+		self.pictures_dir.join(format!("{}.{}", basename, extension))
+	}
+}
+
+fn default_data_files() -> Vec<PathBuf> {
+	vec![PathBuf::from("index.html")]
+}
+
+impl Default for Manifest {
+	fn default() -> Manifest {
+		Manifest::new()
+	}
+}
+
+impl Manifest {
+	pub fn new() -> Manifest {
+		Manifest{
+			version: crate::gallery::MANIFEST_VERSION,
+			data_files: default_data_files(),
+			file_names: FileNames::default(),
+			outputs: BTreeMap::new(),
+			sort_keys: BTreeMap::new(),
+		}
+	}
+
+	/// Path of the manifest of the gallery, inside the gallery directory
+	fn path(gallery_dir: &PathBuf) -> PathBuf {
+		gallery_dir.join(crate::gallery::MANIFEST_PATH)
+	}
+
+	/// Reads the manifest of an existing gallery. Galleries created before manifests were written get an empty one.
+	pub fn read(gallery_dir: &PathBuf) -> Result<Manifest, Error> {
+		let path = Manifest::path(gallery_dir);
+		if !path.exists() {
+			return Ok(Manifest::new());
+		}
+
+		let data = std::fs::read(&path).map_err(|e| Error::io(&path, e))?;
+		serde_json::from_slice(&data).map_err(|e| Error::Data{ path, message: e.to_string() })
+	}
+
+	pub fn write(&self, gallery_dir: &PathBuf) -> Result<(), Error> {
+		let path = Manifest::path(gallery_dir);
+		let json = serde_json::to_string_pretty(self).map_err(|e| Error::Internal(e.to_string()))?;
+		std::fs::write(&path, json).map_err(|e| Error::io(&path, e))
+	}
+
+	/// Returns whether the file was created from a source with the given hash
+	pub fn created_from(&self, file: &PathBuf, hash: &str) -> bool {
+		match self.outputs.get(file) {
+			Some(entry) => entry.hash == hash,
+			None => false,
+		}
+	}
+}
@@ -0,0 +1,73 @@
+use mi::error::Error;
+use mi::logger::infoln;
+use serde_json::Value;
+use std::path::PathBuf;
+
+/// Migrations of the gallery data, the one at index i converts version i + 1 to version i + 2
+const MIGRATIONS: [fn(&mut Value); 3] = [
+	v1_to_v2,
+	v2_to_v3,
+	v3_to_v4,
+];
+
+/// Converts the gallery data read from `path` step by step to the current version and returns the version it was
+/// stored with. Data without version was stored by version 1. Data of newer versions cannot be read, as it may
+/// contain information that would get lost.
+pub fn migrate(data: &mut Value, path: &PathBuf) -> Result<u16, Error> {
+	let current = crate::gallery::GALLERY_CONFIGURATION_VERSION;
+	let version = match data.get("version") {
+		None => 1,
+		Some(v) => match v.as_u64() {
+			Some(v) if v >= 1 && v <= u16::MAX as u64 => v as u16,
+			_ => return Err(Error::Data{ path: path.clone(), message: format!("Invalid version {}", v) }),
+		},
+	};
+
+	if version > current {
+		return Err(Error::Data{
+			path: path.clone(),
+			message: format!(
+				"The gallery was created with data version {}, this static_gallery only supports up to version {}. Please update static_gallery.",
+				version,
+				current,
+			),
+		});
+	}
+
+	for v in version..current {
+		infoln(format!("Migrating gallery data from version {} to {}", v, v + 1));
+		MIGRATIONS[v as usize - 1](data);
+		data["version"] = Value::from(v + 1);
+	}
+
+	Ok(version)
+}
+
+/// Version 1 stored numeric checksums instead of content hashes. They are kept as strings and marked, so they
+/// are replaced when the sources are added again.
+fn v1_to_v2(data: &mut Value) {
+	for c in collections(data) {
+		for list in ["pictures", "backgrounds"].iter() {
+			for image in c.get_mut(*list).and_then(Value::as_array_mut).into_iter().flatten() {
+				if let Some(n) = image.get("original_hash").and_then(Value::as_u64) {
+					image["original_hash"] = Value::from(n.to_string());
+					image["legacy_hash"] = Value::from(true);
+				}
+			}
+		}
+	}
+}
+
+/// Version 3 added nested collections, all collections before are top level collections
+fn v2_to_v3(data: &mut Value) {
+	data["root_keys"] = data.get("collection_keys").cloned().unwrap_or_else(|| Value::Array(Vec::new()));
+}
+
+/// Version 4 added storing the data in files referenced by the index.html, older galleries have it embedded
+fn v3_to_v4(data: &mut Value) {
+	data["data_layout"] = Value::from("inline");
+}
+
+fn collections(data: &mut Value) -> impl Iterator<Item = &mut Value> {
+	data.get_mut("collections").and_then(Value::as_object_mut).into_iter().flat_map(|c| c.values_mut())
+}
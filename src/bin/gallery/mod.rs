@@ -1,26 +1,75 @@
 mod gallery;
 mod collection;
+mod data;
 mod picture;
+mod manifest;
+mod migration;
+mod sort;
+mod template;
 
 // use crate::mi::img::Resolution;
+use mi::logger::warnln;
 
+pub use gallery::BuildReport;
+pub use gallery::Decoded;
+pub use gallery::Failure;
 pub use gallery::Gallery;
 pub use collection::Collection;
+pub use collection::CollectionDetails;
 pub use collection::CollectionInput;
+pub use collection::DateRange;
+pub use collection::RenderOverrides;
+pub use collection::RenderSettings;
+pub use data::DataLayout;
+pub use manifest::FileNames;
+pub use manifest::Manifest;
+pub use manifest::ManifestEntry;
+pub use manifest::Render;
 pub use picture::Picture;
 pub use picture::Image;
+pub use picture::Kind;
+pub use picture::Variant;
+pub use sort::SortKeys;
+pub use sort::SortOrder;
+pub use template::TemplateManifest;
 
 
-pub const GALLERY_CONFIGURATION_VERSION: u16 = 1;
+/// Version of the gallery data, data of older versions is migrated when read
+pub const GALLERY_CONFIGURATION_VERSION: u16 = 4;
 pub const FULL_ARCHIVE_PATH: &str = "Gallery.zip";
 
-// TODO: Support more modern picture formats
+/// Extension of the full size pictures and the fallback format for all variants
 pub const PICTURE_EXTENSION: &str = "jpg";
 
 // pub const BACKGROUNDS_DIR_NAME: &str  = "b";
-// pub const COLLECTIONS_DIR_NAME: &str  = "c";
+/// Directory of the pictures and backgrounds, unless the template declares another one
 pub const PICTURES_DIR_NAME: &str  = "p";
 
+/// Extension of the copied videos
+pub const VIDEO_EXTENSION: &str = "mp4";
+
+/// Default command to extract the poster frame of videos
+pub const DEFAULT_VIDEO_FRAME_COMMAND: &str = "ffmpeg -loglevel error -y -i {input} -vf thumbnail -frames:v 1 {output}";
+
+/// Gallery data referenced by the index.html, unless it is embedded
+pub const DATA_FILE_NAME: &str = "data.json";
+/// Directory of the collection data files, when the gallery data is stored by collection
+pub const COLLECTIONS_DIR_NAME: &str = "c";
+
+/// Build manifest in the output directory, records how each output file was created
+pub const MANIFEST_PATH: &str = ".manifest.json";
+pub const MANIFEST_VERSION: u16 = 1;
+
+/// File in an input directory listing the file names of the pictures in the order they are shown (sort order
+/// "manual"), one per line
+pub const ORDER_FILE_NAME: &str = "order.txt";
+
+/// File in an input directory with the description, dates, location and cover of the collection
+pub const COLLECTION_FILE_NAME: &str = "collection.toml";
+
+/// Manifest of a template, declares the files with markers, the image variants and default options
+pub const TEMPLATE_MANIFEST_FILE_NAME: &str = "template.toml";
+
 pub const PATTERM_DATA_START: &[u8] = b"/*{{BEGIN:data*/";
 pub const PATTERM_DATA_END: &[u8] = b"/*END:data}}*/";
 
@@ -28,6 +77,38 @@ use crate::mi::img::Resolution;
 pub const DEFAULT_RESOLUTION_THUMB: Resolution = Resolution{ width: 960, height: 540 };
 pub const DEFAULT_RESOLUTION_DISPLAY: Resolution = Resolution{ width: 2560, height: 1440 };
 pub const DEFAULT_RESOLUTION_BACKGROUND: Resolution = Resolution{ width: 2560, height: 1440 };
+pub const DEFAULT_RESOLUTION_COVER: Resolution = Resolution{ width: 1920, height: 1080 };
+
+/// Cover resolution of galleries created before collections had covers
+pub fn default_resolution_cover() -> Resolution {
+	DEFAULT_RESOLUTION_COVER
+}
+
+/// Quality and resize method of new galleries and of galleries created before they were stored
+pub const DEFAULT_JPEG_QUALITY: u8 = 75;
+pub const DEFAULT_RESIZE_METHOD: &str = "lanczos3";
+
+pub fn default_quality() -> u8 {
+	DEFAULT_JPEG_QUALITY
+}
+
+pub fn default_resize_method() -> String {
+	String::from(DEFAULT_RESIZE_METHOD)
+}
+
+pub fn default_matte_color() -> crate::mi::img::Color {
+	crate::mi::img::Color::WHITE
+}
+
+use crate::mi::img::Format;
+/// Formats used for galleries and images that were created before other formats were supported
+pub fn default_formats() -> Vec<Format> {
+	vec![Format::Jpeg]
+}
+
+/// Number of hash characters used for the file names of the images
+pub const BASENAME_HASH_LENGTH: usize = 16;
+
 
 
 pub fn contains_images(dir: &std::path::PathBuf) -> bool {
@@ -35,8 +116,8 @@ pub fn contains_images(dir: &std::path::PathBuf) -> bool {
 		return false;
 	}
 
-	for file in &crate::mi::fs::list_dir(&dir) {
-		if valid_extension(file) {
+	for file in &crate::mi::fs::list_dir(dir) {
+		if valid_file(file) {
 			return true;
 		}
 	}
@@ -57,19 +138,44 @@ impl GalleryImages for Vec<Picture> {
 		let mut new = Vec::with_capacity(paths.len());
 
 		for path in paths {
-			let original_hash = crate::mi::fs::file_hash_quick(&path);
+			let original_hash = match hash_file(path) {
+				Some(h) => h,
+				None => continue,
+			};
 			// let basename = clean_basename(&source_path);
-			let basename = format!("{}", original_hash);
+			let basename = String::from(&original_hash[..BASENAME_HASH_LENGTH]);
 			let source_path = path.to_path_buf();
 			let title = String::from(source_path.file_stem().unwrap_or_default().to_string_lossy());
 
+			let (kind, video) = if crate::mi::video::is_video(path) {
+				let info = match crate::mi::video::read_info(path) {
+					Ok(info) => Some(info),
+					Err(e) => {
+						warnln(format!("Could not read video metadata: {}", e));
+						None
+					},
+				};
+				(Kind::Video, info)
+			} else {
+				(Kind::Image, None)
+			};
+
 			new.push(Picture{
 				title,
+				description: None,
+				kind,
+				video,
+				exif: None,
+				sort_keys: SortKeys::default(),
 				image: Image{
 					basename,
 					source_path,
 					original_hash,
+					legacy_hash: false,
+					perceptual_hash: None,
 					update: true,
+					outdated: Vec::new(),
+					formats: default_formats(),
 				}
 			});
 		}
@@ -81,11 +187,11 @@ impl GalleryImages for Vec<Picture> {
 		let mut i = 0;
 		let mut len = self.len();
 		while i < len {
-			if !valid_extension(&self[i].image.source_path) {
+			if !valid_file(&self[i].image.source_path) {
 				self.remove(i);
-				len = len - 1;
+				len -= 1;
 			} else {
-				i = i + 1;
+				i += 1;
 			}
 		}
 	}
@@ -103,16 +209,23 @@ impl GalleryImages for Vec<Image> {
 		let mut new = Vec::with_capacity(paths.len());
 
 		for path in paths {
-			let original_hash = crate::mi::fs::file_hash_quick(&path);
+			let original_hash = match hash_file(path) {
+				Some(h) => h,
+				None => continue,
+			};
 			// let basename = clean_basename(&source_path);
-			let basename = format!("{}", original_hash);
+			let basename = String::from(&original_hash[..BASENAME_HASH_LENGTH]);
 			let source_path = path.to_path_buf();
 
 			new.push(Image{
 				basename,
 				source_path,
 				original_hash,
+				legacy_hash: false,
+				perceptual_hash: None,
 				update: true,
+				outdated: Vec::new(),
+				formats: default_formats(),
 			});
 		}
 
@@ -124,11 +237,12 @@ impl GalleryImages for Vec<Image> {
 		let mut i = 0;
 		let mut len = self.len();
 		while i < len {
-			if !valid_extension(&self[i].source_path) {
+			// Videos cannot be used as backgrounds
+			if !crate::mi::img::is_image(&self[i].source_path) {
 				self.remove(i);
-				len = len - 1;
+				len -= 1;
 			} else {
-				i = i + 1;
+				i += 1;
 			}
 		}
 	}
@@ -138,16 +252,22 @@ impl GalleryImages for Vec<Image> {
 	}
 }
 
-fn valid_extension(path: &std::path::PathBuf) -> bool {
-	let ext = match path.extension() {
-		None => String::from(""),
-		Some(e) => e.to_str().unwrap().to_lowercase(),
-	};
-
-	match ext.as_str() {
-		"jpg" => true,
-		"jpeg" => true,
-		"mp4" => true,
-		&_ => false,
+/// Returns whether the file can be used in a collection. Images and videos are detected by their content, not their extension.
+pub fn valid_file(path: &std::path::PathBuf) -> bool {
+	crate::mi::img::is_image(path) || crate::mi::video::is_video(path)
+}
+
+/// Returns the content hash of the file, directories and unreadable files are skipped
+fn hash_file(path: &std::path::PathBuf) -> Option<String> {
+	if !path.is_file() {
+		return None;
+	}
+
+	match crate::mi::fs::file_hash(path) {
+		Ok(h) => Some(h),
+		Err(e) => {
+			warnln(format!("Could not read {}: {}", path.to_string_lossy(), e));
+			None
+		},
 	}
 }
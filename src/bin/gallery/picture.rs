@@ -1,15 +1,39 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use crate::mi::img::ExifData;
+use crate::mi::img::Format;
+use crate::mi::video::VideoInfo;
+use crate::gallery::SortKeys;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Picture {
 	pub title: String,
 
+	/// Longer text about the picture, read from sidecar files or the metadata of the picture
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub description: Option<String>,
+
+	#[serde(default)]
+	pub kind: Kind,
+
+	/// Duration and dimensions of videos
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub video: Option<VideoInfo>,
+
+	/// Published exif metadata of images
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub exif: Option<ExifData>,
+
+	/// Properties of the source used to sort the pictures of the collection, stored in the manifest
+	#[serde(skip)]
+	pub sort_keys: SortKeys,
+
 	#[serde(flatten)]
 	pub image: Image,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Image {
 	// Basename of the image
 	#[serde(rename = "path")]
@@ -18,10 +42,68 @@ pub struct Image {
 	#[serde(skip)]
 	pub update: bool,
 
+	/// Already existing variants that have to be rendered again (e.g. because their resolution changed)
+	#[serde(skip)]
+	pub outdated: Vec<Variant>,
+
 	#[serde(skip)]
 	pub source_path: PathBuf,
 
-	pub original_hash: u64,
+	/// Content hash of the source file (hex encoded BLAKE3). Galleries of version 1 stored a numeric checksum,
+	/// it is migrated to a string and replaced when the source is added again.
+	pub original_hash: String,
+
+	/// Whether the original hash is the checksum of a gallery of version 1
+	#[serde(default, skip_serializing_if = "std::ops::Not::not")]
+	pub legacy_hash: bool,
+
+	/// Perceptual hash (dHash) used to find near duplicates, computed when the files of the image are created
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub perceptual_hash: Option<u64>,
+
+	/// Formats the variants of the image are available in
+	#[serde(default = "crate::gallery::default_formats")]
+	pub formats: Vec<Format>,
+}
+
+/// Kind of the collection items
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+	#[default]
+	Image,
+	Video,
+}
+
+/// The resized versions that are created for each image
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Variant {
+	Thumb,
+	Display,
+	Background,
+	/// Picture representing a collection on overview pages
+	Cover,
 }
 
+impl Variant {
+	/// Name of the variant in template manifests
+	pub fn name(&self) -> &'static str {
+		match self {
+			Variant::Thumb => "thumb",
+			Variant::Display => "display",
+			Variant::Background => "background",
+			Variant::Cover => "cover",
+		}
+	}
 
+	/// The suffix added to the basename of the files of this variant, unless the template declares another one
+	pub fn suffix(&self) -> &'static str {
+		match self {
+			Variant::Thumb => "thumb",
+			Variant::Display => "disp",
+			Variant::Background => "bg",
+			Variant::Cover => "cover",
+		}
+	}
+}
@@ -0,0 +1,232 @@
+use mi::logger::warnln;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::PathBuf;
+use crate::gallery::Picture;
+use crate::mi::img::ExifData;
+
+/// Order of the pictures of a collection
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum SortOrder {
+	/// File names of the sources
+	Name,
+	/// Capture time stored in the exif data, the modification time of the source if not stored
+	ExifDate,
+	/// Modification time of the sources
+	Mtime,
+	/// Shuffled, the same seed gives the same order
+	RandomSeeded(u64),
+	/// Order of the file names in the order file of the input directory, pictures not listed follow by name
+	Manual,
+}
+
+impl std::fmt::Display for SortOrder {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			SortOrder::Name => write!(f, "name"),
+			SortOrder::ExifDate => write!(f, "exif-date"),
+			SortOrder::Mtime => write!(f, "mtime"),
+			SortOrder::RandomSeeded(seed) => write!(f, "random-seeded={}", seed),
+			SortOrder::Manual => write!(f, "manual"),
+		}
+	}
+}
+
+impl std::str::FromStr for SortOrder {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<SortOrder, String> {
+		let (order, seed) = match s.trim().split_once('=') {
+			Some((order, seed)) => (order, Some(seed)),
+			None => (s.trim(), None),
+		};
+
+		match (order, seed) {
+			("name", None) => Ok(SortOrder::Name),
+			("exif-date", None) => Ok(SortOrder::ExifDate),
+			("mtime", None) => Ok(SortOrder::Mtime),
+			("manual", None) => Ok(SortOrder::Manual),
+			("random-seeded", None) => Ok(SortOrder::RandomSeeded(0)),
+			("random-seeded", Some(seed)) => match seed.trim().parse() {
+				Ok(seed) => Ok(SortOrder::RandomSeeded(seed)),
+				Err(_) => Err(format!("Invalid seed \"{}\", must be a positive number", seed)),
+			},
+			_ => Err(format!(
+				"Invalid sort order \"{}\". Valid orders: \"name\", \"exif-date\", \"mtime\", \"random-seeded[=seed]\", \"manual\"",
+				s,
+			)),
+		}
+	}
+}
+
+/// Stored with the syntax of the command line, e.g. "random-seeded=3"
+impl JsonSchema for SortOrder {
+	fn schema_name() -> String {
+		String::from("SortOrder")
+	}
+
+	fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+		String::json_schema(gen)
+	}
+}
+
+impl From<SortOrder> for String {
+	fn from(order: SortOrder) -> String {
+		order.to_string()
+	}
+}
+
+impl TryFrom<String> for SortOrder {
+	type Error = String;
+
+	fn try_from(s: String) -> Result<SortOrder, String> {
+		s.parse()
+	}
+}
+
+/// Properties of the source of a picture that are needed to sort it again when new pictures are added. Only
+/// those used by the order of the collection are stored.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SortKeys {
+	/// File name of the source
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub name: Option<String>,
+	/// Capture time in seconds since 1970-01-01
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub date: Option<i64>,
+	/// Modification time of the source in seconds since 1970-01-01
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub mtime: Option<i64>,
+	/// Position in the order file
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub position: Option<usize>,
+}
+
+impl SortKeys {
+	pub fn is_empty(&self) -> bool {
+		*self == SortKeys::default()
+	}
+}
+
+/// Sorts the pictures in the given order. The keys of pictures whose source is known are read from the source,
+/// the stored keys are used for the others. The capture time is taken from the exif data read while the images
+/// are created, the modification time of the source is used until then. Pictures with equal keys or without the needed keys keep their
+/// relative order, those without keys follow the others.
+pub fn sort_pictures(pictures: &mut Vec<Picture>, order: &SortOrder, collection: &str) {
+	for p in pictures.iter_mut() {
+		read_keys(p, order);
+	}
+
+	if *order == SortOrder::Manual {
+		apply_order_files(pictures);
+	}
+
+	let unsorted = pictures.iter().filter(|p| !has_key(p, order)).count();
+	if unsorted > 0 {
+		warnln(format!("{} pictures of collection \"{}\" cannot be sorted by {}, their sources are not known", unsorted, collection, order));
+	}
+
+	pictures.sort_by_cached_key(|p| {
+		let name = p.sort_keys.name.clone().unwrap_or_default();
+		match order {
+			SortOrder::Name => (p.sort_keys.name.is_none(), 0, name),
+			SortOrder::ExifDate => (p.sort_keys.date.is_none(), p.sort_keys.date.unwrap_or_default(), name),
+			SortOrder::Mtime => (p.sort_keys.mtime.is_none(), p.sort_keys.mtime.unwrap_or_default(), name),
+			SortOrder::RandomSeeded(seed) => (false, 0, random_key(*seed, &p.image.original_hash)),
+			SortOrder::Manual => (p.sort_keys.name.is_none(), p.sort_keys.position.map_or(i64::MAX, |i| i as i64), name),
+		}
+	});
+}
+
+/// Reads the keys used by the order from the source of the picture, if known, and drops the others
+fn read_keys(picture: &mut Picture, order: &SortOrder) {
+	let source = &picture.image.source_path;
+	let keys = &mut picture.sort_keys;
+
+	let known = !source.as_os_str().is_empty();
+	if known {
+		keys.name = source.file_name().map(|n| n.to_string_lossy().to_string());
+		if *order == SortOrder::Mtime {
+			keys.mtime = modification_time(source);
+		}
+	}
+	if *order == SortOrder::ExifDate {
+		keys.date = picture.exif.as_ref().and_then(ExifData::capture_time)
+			.or(keys.date)
+			.or_else(|| modification_time(source).filter(|_| known));
+	}
+
+	if !matches!(order, SortOrder::Name | SortOrder::Manual) {
+		keys.name = None;
+	}
+	if *order != SortOrder::ExifDate {
+		keys.date = None;
+	}
+	if *order != SortOrder::Mtime {
+		keys.mtime = None;
+	}
+	if *order != SortOrder::Manual {
+		keys.position = None;
+	}
+}
+
+/// Sets the positions of the pictures listed in the order files next to their sources. The entries are matched
+/// by directory and file name, pictures whose directory has no order file keep their stored positions.
+fn apply_order_files(pictures: &mut Vec<Picture>) {
+	let mut dirs: Vec<PathBuf> = Vec::new();
+	for p in pictures.iter() {
+		if let Some(dir) = p.image.source_path.parent() {
+			if !p.image.source_path.as_os_str().is_empty() && !dirs.iter().any(|d| d == dir) {
+				dirs.push(dir.to_path_buf());
+			}
+		}
+	}
+
+	let mut ordered_dirs: Vec<PathBuf> = Vec::new();
+	let mut positions: HashMap<PathBuf, usize> = HashMap::new();
+	for dir in dirs {
+		let path = dir.join(crate::gallery::ORDER_FILE_NAME);
+		let content = match std::fs::read_to_string(&path) {
+			Ok(c) => c,
+			Err(_) => continue,
+		};
+
+		for line in content.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+			let next = positions.len();
+			positions.entry(dir.join(line)).or_insert(next);
+		}
+		ordered_dirs.push(dir);
+	}
+
+	for p in pictures.iter_mut() {
+		if p.image.source_path.parent().is_some_and(|d| ordered_dirs.iter().any(|o| o == d)) {
+			p.sort_keys.position = positions.get(&p.image.source_path).copied();
+		}
+	}
+}
+
+fn has_key(picture: &Picture, order: &SortOrder) -> bool {
+	match order {
+		SortOrder::Name | SortOrder::Manual => picture.sort_keys.name.is_some(),
+		SortOrder::ExifDate => picture.sort_keys.date.is_some(),
+		SortOrder::Mtime => picture.sort_keys.mtime.is_some(),
+		SortOrder::RandomSeeded(_) => true,
+	}
+}
+
+fn modification_time(path: &PathBuf) -> Option<i64> {
+	let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok()?;
+	modified.duration_since(std::time::UNIX_EPOCH).ok().map(|d| d.as_secs() as i64)
+}
+
+/// Returns a key that only depends on the seed and the content of the picture, so added pictures do not change
+/// the order of the existing ones
+fn random_key(seed: u64, hash: &str) -> String {
+	let mut hasher = blake3::Hasher::new();
+	hasher.update(&seed.to_le_bytes());
+	hasher.update(hash.as_bytes());
+	hasher.finalize().to_hex().to_string()
+}
@@ -0,0 +1,255 @@
+use mi::error::Error;
+use serde::Deserialize;
+use std::path::{Component, PathBuf};
+use crate::mi::bin::Replace;
+use crate::mi::caption::CaptionSources;
+use crate::mi::img::{Color, ExifFields, Format, Resolution};
+use crate::gallery::DataLayout;
+use crate::gallery::FileNames;
+use crate::gallery::Variant;
+
+/// Declares what a template expects, read from the template.toml of the template directory. Templates without
+/// manifest get the gallery data in their index.html and the pictures in "p/".
+///
+/// Example:
+///
+/// ```toml
+/// pictures_dir = "p"
+///
+/// [[files]]
+/// path = "index.html"
+/// markers = ["data"]
+///
+/// [[variants]]
+/// name = "thumb"
+/// size = "960x540"
+/// formats = ["jpeg", "webp"]
+/// suffix = "thumb"
+///
+/// [defaults]
+/// quality = 85
+/// exif_fields = "date,camera"
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateManifest {
+	/// Directory the template loads the pictures and backgrounds from, relative to the gallery
+	#[serde(default)]
+	pub pictures_dir: Option<PathBuf>,
+
+	/// Files that contain markers, relative to the template directory
+	#[serde(default = "default_files")]
+	pub files: Vec<TemplateFile>,
+
+	/// Image variants the template shows. All variants are created, the sizes and formats are used as defaults.
+	#[serde(default)]
+	pub variants: Vec<TemplateVariant>,
+
+	/// Options used for new galleries when given neither on the command line nor by the project file
+	#[serde(default)]
+	pub defaults: TemplateDefaults,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateFile {
+	pub path: PathBuf,
+	/// Markers filled in the file
+	pub markers: Vec<Marker>,
+}
+
+/// Placeholder in a template file that is replaced when the gallery is written
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Marker {
+	/// The gallery data, `/*{{BEGIN:data*/{}/*END:data}}*/`
+	Data,
+}
+
+impl Marker {
+	pub fn name(&self) -> &'static str {
+		match self {
+			Marker::Data => "data",
+		}
+	}
+
+	pub fn start(&self) -> &'static [u8] {
+		match self {
+			Marker::Data => crate::gallery::PATTERM_DATA_START,
+		}
+	}
+
+	pub fn end(&self) -> &'static [u8] {
+		match self {
+			Marker::Data => crate::gallery::PATTERM_DATA_END,
+		}
+	}
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateVariant {
+	/// One of "thumb", "display", "background", "cover"
+	pub name: Variant,
+	#[serde(default, deserialize_with = "mi::de::deserialize_from_str")]
+	pub size: Option<Resolution>,
+	/// Formats the template loads, JPEG is always created
+	#[serde(default)]
+	pub formats: Vec<Format>,
+	/// Suffix added to the basename of the files, "[basename].[suffix].[extension]"
+	pub suffix: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateDefaults {
+	pub quality: Option<u8>,
+	pub resize_method: Option<String>,
+	#[serde(default, deserialize_with = "mi::de::deserialize_from_str")]
+	pub matte_color: Option<Color>,
+	pub data_layout: Option<DataLayout>,
+	#[serde(default, deserialize_with = "mi::de::deserialize_from_str")]
+	pub exif_fields: Option<ExifFields>,
+	#[serde(default, deserialize_with = "mi::de::deserialize_from_str")]
+	pub caption_sources: Option<CaptionSources>,
+}
+
+fn default_files() -> Vec<TemplateFile> {
+	vec![TemplateFile{ path: PathBuf::from("index.html"), markers: vec![Marker::Data] }]
+}
+
+impl Default for TemplateManifest {
+	fn default() -> TemplateManifest {
+		TemplateManifest{
+			pictures_dir: None,
+			files: default_files(),
+			variants: Vec::new(),
+			defaults: TemplateDefaults::default(),
+		}
+	}
+}
+
+impl TemplateManifest {
+	/// Reads the manifest of the template or gallery directory, directories without manifest get the default one
+	pub fn read(dir: &PathBuf) -> Result<TemplateManifest, Error> {
+		let path = dir.join(crate::gallery::TEMPLATE_MANIFEST_FILE_NAME);
+		if !path.is_file() {
+			return Ok(TemplateManifest::default());
+		}
+
+		let content = std::fs::read_to_string(&path).map_err(|e| Error::io(&path, e))?;
+		let manifest: TemplateManifest = toml::from_str(&content).map_err(|e| Error::Template{ path: path.clone(), message: e.to_string() })?;
+
+		let invalid = |message: String| Err(Error::Template{ path: path.clone(), message });
+		// Files are written in the gallery, they must not point outside of it
+		for f in &manifest.files {
+			if !is_inside(&f.path) {
+				return invalid(format!("File \"{}\" is not inside the template directory", f.path.to_string_lossy()));
+			}
+		}
+		// The files of the picture directory that no picture references are removed by the garbage collection
+		let own_dir = |d: &PathBuf| is_inside(d) && d.components().any(|c| matches!(c, Component::Normal(_)));
+		if let Some(dir) = manifest.pictures_dir.as_ref().filter(|d| !own_dir(d)) {
+			return invalid(format!("Picture directory \"{}\" is not inside the gallery", dir.to_string_lossy()));
+		}
+		if manifest.data_files().is_empty() {
+			return invalid(String::from("No file has the \"data\" marker"));
+		}
+		for (i, v) in manifest.variants.iter().enumerate() {
+			if manifest.variants[..i].iter().any(|o| o.name == v.name) {
+				return invalid(format!("Variant \"{}\" is declared twice", v.name.name()));
+			}
+		}
+
+		// The basename of a file is the part before the first dot, the suffixes tell the variants apart
+		let names = manifest.file_names();
+		let variants = [Variant::Thumb, Variant::Display, Variant::Background, Variant::Cover];
+		for (i, v) in variants.iter().enumerate() {
+			let suffix = names.suffix(*v);
+			if suffix.is_empty() || !suffix.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+				return invalid(format!("Invalid suffix \"{}\" of variant \"{}\"", suffix, v.name()));
+			}
+			if let Some(o) = variants[..i].iter().find(|o| names.suffix(**o) == suffix) {
+				return invalid(format!("Variants \"{}\" and \"{}\" have the same suffix \"{}\"", o.name(), v.name(), suffix));
+			}
+		}
+
+		Ok(manifest)
+	}
+
+	/// Checks that the files of the template in `dir` exist and contain their markers
+	pub fn check(&self, dir: &PathBuf) -> Result<(), Error> {
+		for f in &self.files {
+			let path = dir.join(&f.path);
+			let content = std::fs::read(&path).map_err(|e| Error::Template{ path: path.clone(), message: e.to_string() })?;
+			for m in &f.markers {
+				if content.index_of(m.start(), 0).is_err() || content.index_of(m.end(), 0).is_err() {
+					return Err(Error::Template{ path, message: format!("Marker \"{}\" not found", m.name()) });
+				}
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Copies the template in `dir` into the gallery directory, except for its manifest
+	pub fn copy(dir: &PathBuf, gallery_dir: &PathBuf) -> Result<(), Error> {
+		std::fs::create_dir_all(gallery_dir).map_err(|e| Error::io(gallery_dir, e))?;
+		for path in crate::mi::fs::list_dir(dir) {
+			let name = path.file_name().unwrap_or_default();
+			if name != crate::gallery::TEMPLATE_MANIFEST_FILE_NAME {
+				crate::mi::fs::copy_recursively(&path, &gallery_dir.join(name))?;
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Names of the picture files, the defaults unless the template declares them
+	pub fn file_names(&self) -> FileNames {
+		let mut names = FileNames::default();
+		if let Some(dir) = &self.pictures_dir {
+			names.pictures_dir = dir.clone();
+		}
+		for v in &self.variants {
+			let suffix = match v.name {
+				Variant::Thumb => &mut names.thumb,
+				Variant::Display => &mut names.display,
+				Variant::Background => &mut names.background,
+				Variant::Cover => &mut names.cover,
+			};
+			if let Some(s) = &v.suffix {
+				*suffix = s.clone();
+			}
+		}
+
+		names
+	}
+
+	/// Files the gallery data is written to, relative to the gallery directory
+	pub fn data_files(&self) -> Vec<PathBuf> {
+		self.files.iter().filter(|f| f.markers.contains(&Marker::Data)).map(|f| f.path.clone()).collect()
+	}
+
+	/// Size of the variant declared by the template
+	pub fn size(&self, variant: Variant) -> Option<Resolution> {
+		self.variants.iter().find(|v| v.name == variant).and_then(|v| v.size)
+	}
+
+	/// Formats of all variants declared by the template, none if no variant declares formats
+	pub fn formats(&self) -> Option<Vec<Format>> {
+		let mut formats = Vec::new();
+		for f in self.variants.iter().flat_map(|v| v.formats.iter()) {
+			if !formats.contains(f) {
+				formats.push(*f);
+			}
+		}
+
+		Some(formats).filter(|f| !f.is_empty())
+	}
+}
+
+/// Returns whether the relative path stays inside the directory it is relative to
+fn is_inside(path: &PathBuf) -> bool {
+	!path.is_absolute() && path.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
@@ -1,64 +1,359 @@
-use mi;
+#![allow(clippy::ptr_arg, clippy::module_inception, clippy::too_many_arguments)]
+
+extern crate mi;
 
 mod configuration;
 mod gallery;
 
 mod test;
 
-use configuration::Configuration;
+use configuration::{Command, Configuration};
+use gallery::BuildReport;
 use gallery::Gallery;
-
-// TODO: Video Support
+use gallery::TemplateManifest;
+use mi::error::Error;
+use mi::logger::{errorln, infoln};
 
 // TODO (hauer template): Make sure backgrounds don't run out
-// TODO: Optimize Rotation detection (currently reading images twice)
 
 // TODO: Add Unit Tests
 // TODO: Document APIs inline
-// TODO: Add to existing without recreating images
-// TODO: Detect dupicate backgrounds in collections
 // TODO: Optimize output if requested
 // IDEA: Offer to include originals
 // IDEA: Create collection archives
 // IDEA: Validate input files are valid? (Warn if non-images are found)
-// IDEA: List current gallery status
 
 
 fn main() {
+	if let Err(e) = run() {
+		errorln(e.to_string());
+		std::process::exit(e.exit_code());
+	}
+}
+
+fn run() -> Result<(), Error> {
 	// Fill CLI options
-	let config: Configuration = Configuration::from_cli().unwrap();
+	let config: Configuration = Configuration::from_cli()?;
+
+	match config.command {
+		Command::Init => init(&config),
+		Command::Build | Command::Add | Command::Remove => build(config),
+		Command::List{ pictures } => list(&config, pictures),
+		Command::Status => status(&config),
+		Command::Verify => verify(&config),
+		Command::Gc => collect_garbage(&config, Gallery::from(&config.output_dir)?),
+		Command::Schema => schema(),
+	}
+}
+
+/// Prints the JSON Schema of the gallery data
+fn schema() -> Result<(), Error> {
+	let schema = serde_json::to_string_pretty(&Gallery::schema()).map_err(|e| Error::Internal(e.to_string()))?;
+	println!("{}", schema);
+
+	Ok(())
+}
+
+/// Creates a new gallery without pictures
+fn init(config: &Configuration) -> Result<(), Error> {
+	let mut gallery = Gallery::new();
+	gallery.set_data_layout(config.render.data_layout);
 
+	if let Some(template_dir) = &config.template_dir {
+		gallery.set_template(&TemplateManifest::read(template_dir)?);
+		TemplateManifest::copy(template_dir, &config.output_dir)?;
+	}
+
+	gallery.include_json_data(&config.output_dir)
+}
+
+/// Creates a new gallery or adds pictures to or removes pictures from an existing one
+fn build(config: Configuration) -> Result<(), Error> {
 	let mut gallery = match config.update {
-		true => Gallery::from(&config.output_dir),
+		true => Gallery::from(&config.output_dir)?,
 		false => Gallery::new(),
 	};
 
-	gallery
-		.fill(config.collections, config.image_name_titles)
-		.unwrap();
+	// New galleries name their files as the template declares
+	if let (false, Some(template_dir)) = (config.update, &config.template_dir) {
+		gallery.set_template(&TemplateManifest::read(template_dir)?);
+	}
+
+	// Remove pictures and collections before adding new ones, so replaced pictures can be added again
+	for key in &config.remove_collections {
+		gallery.remove_collection(key)?;
+	}
+	for basename in &config.remove_pictures {
+		gallery.remove_image(basename)?;
+	}
+	for source in &config.remove_sources {
+		gallery.remove_source(source)?;
+	}
+
+	if config.command == Command::Remove {
+		delete_removed(&mut gallery, &config.output_dir)?;
+		return gallery.include_json_data(&config.output_dir);
+	}
+
+	let render = config.render;
+	let mut input = config.input;
+
+	// Sizes that differ from the ones stored in an existing gallery lead to re-rendering the affected variants
+	gallery.set_resolutions(render.tumb_size, render.display_size, render.background_size);
+	gallery.set_cover_resolution(render.cover_size);
+	gallery.set_formats(render.formats.clone());
+	gallery.set_render_settings(render.jpeg_quality, render.resize_method.clone(), render.matte_color);
+	gallery.set_data_layout(render.data_layout);
+	gallery.set_exif_fields(input.exif_fields.clone());
+
+	// Orders given for collections that are not added to are applied to the existing collections
+	let mut sort_existing = Vec::new();
+	for s in &input.sort {
+		let mut given = false;
+		for c in input.collections.iter_mut().filter(|c| s.key.as_ref().is_none_or(|k| *k == c.name)) {
+			c.sort = Some(s.order.clone());
+			given = true;
+		}
+		if let (false, Some(key)) = (given, &s.key) {
+			sort_existing.push((key, s.order.clone()));
+		}
+	}
+
+	// Details given on the command line take precedence over those of the collection files
+	let mut details_existing = Vec::new();
+	for i in &input.collection_info {
+		match input.collections.iter_mut().find(|c| c.name == i.key) {
+			Some(c) => c.details.update(i.details.clone()),
+			None => details_existing.push(i),
+		}
+	}
+
+	// Parents given for collections that are not added to move the existing collections
+	let mut parents_existing = Vec::new();
+	for p in &input.collection_parent {
+		match input.collections.iter_mut().find(|c| c.name == p.key) {
+			Some(c) => c.parent = p.parent.clone(),
+			None => parents_existing.push(p),
+		}
+	}
+
+	let caption_sources = input.caption_sources();
+	gallery.fill(input.collections, input.image_name_titles)?;
+	for p in parents_existing {
+		gallery.set_parent(&p.key, p.parent.as_deref())?;
+	}
+	for (key, order) in sort_existing {
+		gallery.set_sort(key, order)?;
+	}
+	for i in details_existing {
+		gallery.set_details(&i.key, i.details.clone())?;
+	}
+	for c in &input.collection_settings {
+		gallery.set_overrides(&c.key, c.overrides.clone())?;
+	}
+	gallery.read_exif();
+	gallery.read_captions(&caption_sources);
 
 	gallery.remove_duplicates();
+	if input.remove_nested_duplicates {
+		for (key, basename) in gallery.remove_nested_duplicates() {
+			println!("Removed {} from collection {}, one of its child collections shows it", basename, key);
+		}
+	}
 
 	// Create output images (resized versions)
-	gallery
-		.create_images(
-			&config.output_dir,
-			config.jpeg_quality,
-			&config.resize_method,
-			config.threads,
-		)
-		.unwrap();
-
-	if !config.update {
+	let report = gallery.create_images(&config.output_dir, &render.video_frame_command(), render.threads())?;
+
+	// Failed pictures are left out, so the gallery does not reference missing files
+	gallery.remove_failed(&report.failures);
+	report_failures(&report);
+
+	// The perceptual hashes are computed while creating the images
+	if input.near_duplicates || input.merge_near_duplicates {
+		let found = gallery.find_near_duplicates(&config.output_dir, input.near_duplicate_distance, input.merge_near_duplicates, render.threads());
+		for d in &found {
+			println!("Near duplicate: {} looks like {} (distance {})", d.path.to_string_lossy(), d.similar_to.to_string_lossy(), d.distance);
+		}
+		println!("{} near duplicates found", found.len());
+	}
+
+	// Files of removed pictures are deleted after the remaining ones are created, so shared files are kept
+	delete_removed(&mut gallery, &config.output_dir)?;
+
+	if let Some(path) = &render.failure_report {
+		report.write(path)?;
+	}
+
+	let complete = report.check();
+	if render.strict {
+		if let Some(f) = report.failures.into_iter().next() {
+			return Err(f.error);
+		}
+	}
+
+	if let (false, Some(template_dir)) = (config.update, &config.template_dir) {
 		// Copy template
-		crate::mi::fs::copy_recursively(&config.template_dir, &config.output_dir);
+		TemplateManifest::copy(template_dir, &config.output_dir)?;
 	}
 
 	// Create archive if requested
-	if config.create_full_archive {
-		gallery.create_archive_full(&config.output_dir);
+	if input.create_full_archive {
+		gallery.create_archive_full(&config.output_dir)?;
 	}
 
 	// Generate and include JSON structure
-	gallery.include_json_data(&config.output_dir);
+	gallery.include_json_data(&config.output_dir)?;
+
+	// The gallery is written without the failed pictures, but the build is reported as failed
+	complete
+}
+
+/// Deletes the files of the removed pictures and backgrounds that no other picture uses
+fn delete_removed(gallery: &mut Gallery, output_dir: &std::path::PathBuf) -> Result<(), Error> {
+	let deleted = gallery.delete_removed(output_dir)?;
+	if !deleted.is_empty() {
+		infoln(format!("{} files of removed pictures and backgrounds deleted", deleted.len()));
+	}
+
+	Ok(())
+}
+
+/// Logs the pictures and backgrounds whose files could not be created
+fn report_failures(report: &BuildReport) {
+	for f in &report.failures {
+		errorln(format!("Failed {} in collection \"{}\": {}", f.source.to_string_lossy(), f.collection, f.error));
+	}
+	println!("{} pictures and backgrounds processed, {} failed", report.processed, report.failures.len());
+}
+
+/// Prints the collections of the gallery, child collections indented below their parents, and their pictures if
+/// requested
+fn list(config: &Configuration, pictures: bool) -> Result<(), Error> {
+	let gallery = Gallery::from(&config.output_dir)?;
+
+	for k in &gallery.keys() {
+		let c = &gallery.collections[k];
+		let indent = "  ".repeat(gallery.depth(k));
+		println!("{}{} \"{}\": {} pictures, {} backgrounds", indent, k, c.title, c.pictures.len(), c.backgrounds.len());
+
+		if pictures {
+			for p in &c.pictures {
+				match p.title.is_empty() {
+					true => println!("{}  {} {:?}", indent, p.image.basename, p.kind),
+					false => println!("{}  {} {:?} \"{}\"", indent, p.image.basename, p.kind, p.title),
+				}
+			}
+			for b in &c.backgrounds {
+				println!("{}  {} Background", indent, b.basename);
+			}
+		}
+	}
+
+	Ok(())
+}
+
+/// Prints the settings, collections and file sizes of the gallery and the number of orphaned and missing files
+fn status(config: &Configuration) -> Result<(), Error> {
+	let mut gallery = Gallery::from(&config.output_dir)?;
+	let render = &config.render;
+	let settings = gallery.render_settings(&render.video_frame_command());
+
+	let formats: Vec<&str> = gallery.formats.iter().map(|f| f.extension()).collect();
+	println!("Gallery version {}, formats {}, data layout {}", gallery.version, formats.join(", "), gallery.data_layout);
+	println!("Sizes: thumbnails {}, display {}, backgrounds {}, covers {}", gallery.res_thumb, gallery.res_display, gallery.res_background, gallery.res_cover);
+
+	let mut total = (0, 0, 0, 0);
+	for s in gallery.status(&config.output_dir, &settings) {
+		println!(
+			"{} \"{}\": {} pictures, {} videos, {} backgrounds, {} files ({})",
+			s.key, s.title, s.pictures, s.videos, s.backgrounds, s.files, format_size(s.size),
+		);
+		total = (total.0 + s.pictures, total.1 + s.videos, total.2 + s.backgrounds, total.3 + s.size);
+	}
+	println!(
+		"{} collections: {} pictures, {} videos, {} backgrounds ({})",
+		gallery.collections.len(), total.0, total.1, total.2, format_size(total.3),
+	);
+
+	let garbage = gallery.collect_garbage(&config.output_dir, &settings, false)?;
+	println!("{} orphans, {} files missing", garbage.orphans.len(), garbage.missing.len());
+
+	Ok(())
+}
+
+/// Checks the files of the gallery, fails if any of them is missing or cannot be read
+fn verify(config: &Configuration) -> Result<(), Error> {
+	let mut gallery = Gallery::from(&config.output_dir)?;
+	let render = &config.render;
+	let settings = gallery.render_settings(&render.video_frame_command());
+
+	let report = gallery.verify(&config.output_dir, &settings)?;
+	for m in &report.garbage.missing {
+		errorln(format!("{}: Missing (collection \"{}\")", config.output_dir.join(&m.file).to_string_lossy(), m.collection));
+	}
+	for e in &report.invalid {
+		errorln(e.to_string());
+	}
+	for file in &report.garbage.orphans {
+		println!("Orphan: {}", file.to_string_lossy());
+	}
+
+	let problems = report.garbage.missing.len() + report.invalid.len();
+	println!("{} files missing, {} files invalid, {} orphans", report.garbage.missing.len(), report.invalid.len(), report.garbage.orphans.len());
+
+	match problems {
+		0 => Ok(()),
+		_ => Err(Error::Data{ path: config.output_dir.clone(), message: format!("{} files are missing or damaged", problems) }),
+	}
+}
+
+/// Formats a number of bytes for humans
+fn format_size(bytes: u64) -> String {
+	match bytes {
+		b if b >= 1 << 30 => format!("{:.1} GiB", b as f64 / (1u64 << 30) as f64),
+		b if b >= 1 << 20 => format!("{:.1} MiB", b as f64 / (1u64 << 20) as f64),
+		b if b >= 1 << 10 => format!("{:.1} KiB", b as f64 / (1u64 << 10) as f64),
+		b => format!("{} B", b),
+	}
+}
+
+/// Lists or deletes the orphaned files of the gallery and reports or creates its missing files
+fn collect_garbage(config: &Configuration, mut gallery: Gallery) -> Result<(), Error> {
+	let render = &config.render;
+	let settings = gallery.render_settings(&render.video_frame_command());
+	let garbage = gallery.collect_garbage(&config.output_dir, &settings, config.gc_delete)?;
+
+	for file in &garbage.orphans {
+		println!("Orphan: {}", file.to_string_lossy());
+	}
+	for m in &garbage.missing {
+		match &m.source {
+			Some(source) => println!("Missing: {} (collection \"{}\", source {})", m.file.to_string_lossy(), m.collection, source.to_string_lossy()),
+			None => println!("Missing: {} (collection \"{}\")", m.file.to_string_lossy(), m.collection),
+		}
+	}
+
+	let action = match config.gc_delete {
+		true => "deleted",
+		false => "found",
+	};
+	println!("{} orphans {}, {} files missing", garbage.orphans.len(), action, garbage.missing.len());
+
+	if !config.gc_regenerate || garbage.missing.is_empty() {
+		return Ok(());
+	}
+
+	gallery.restore_sources(&garbage.missing);
+	let report = gallery.create_images(&config.output_dir, &render.video_frame_command(), render.threads())?;
+	report_failures(&report);
+
+	// The regenerated images are stored with their new formats and hashes, failed ones are left out
+	gallery.remove_failed(&report.failures);
+	let complete = report.check();
+	if let (true, Some(f)) = (render.strict, report.failures.into_iter().next()) {
+		return Err(f.error);
+	}
+
+	gallery.include_json_data(&config.output_dir)?;
+	complete
 }
@@ -1,7 +1,20 @@
 #![cfg(test)]
 
+use crate::configuration::{CollectionInfo, CollectionParent, Command, Configuration};
+use crate::gallery::BuildReport;
+use crate::gallery::CollectionDetails;
 use crate::gallery::CollectionInput;
+use crate::gallery::DataLayout;
+use crate::gallery::DateRange;
 use crate::gallery::Gallery;
+use crate::gallery::Kind;
+use crate::gallery::RenderOverrides;
+use crate::gallery::SortOrder;
+use crate::gallery::TemplateManifest;
+use crate::gallery::Variant;
+use crate::mi::bin::Replace;
+use crate::mi::caption::CaptionSource;
+use crate::mi::img::{Color, ExifField, ExifFields, Format, Resolution};
 use lazy_static::lazy_static;
 use std::path::PathBuf;
 use std::str::FromStr;
@@ -38,10 +51,62 @@ fn dir_none() -> PathBuf {
 fn create_input(input: PathBuf, backgrounds: PathBuf, title: &str) -> CollectionInput {
 	let input = input.to_string_lossy();
 	let backgrounds = backgrounds.to_string_lossy();
-	let col_str = vec![input.as_ref(), backgrounds.as_ref(), title];
+	let col_str = [input.as_ref(), backgrounds.as_ref(), title];
 	CollectionInput::from_str(col_str.join(";").as_str()).unwrap()
 }
 
+/// Variant size that keeps the tests fast
+const SMALL: Option<Resolution> = Some(Resolution{ width: 160, height: 160 });
+
+/// Temporary directory of a test, removed when dropped
+struct TestDir(PathBuf);
+
+impl std::ops::Deref for TestDir {
+	type Target = PathBuf;
+
+	fn deref(&self) -> &PathBuf {
+		&self.0
+	}
+}
+
+impl AsRef<std::path::Path> for TestDir {
+	fn as_ref(&self) -> &std::path::Path {
+		&self.0
+	}
+}
+
+impl Drop for TestDir {
+	fn drop(&mut self) {
+		std::fs::remove_dir_all(&self.0).ok();
+	}
+}
+
+/// Returns a temporary directory for the test with the given name, it is not created
+fn test_dir(name: &str) -> TestDir {
+	TestDir(std::env::temp_dir().join(format!("static_gallery_test_{}_{}", name, std::process::id())))
+}
+
+/// Creates a temporary gallery directory whose index.html only contains the data placeholder
+fn temp_gallery(name: &str) -> TestDir {
+	let dir = test_dir(name);
+	std::fs::create_dir_all(&dir).unwrap();
+	std::fs::write(dir.join("index.html"), "/*{{BEGIN:data*//*END:data}}*/").unwrap();
+	dir
+}
+
+/// Returns a new gallery with small variants that are resized with a fast method
+fn small_gallery() -> Gallery {
+	let mut gallery = Gallery::new();
+	gallery.set_resolutions(SMALL, SMALL, SMALL);
+	gallery.set_render_settings(None, Some(String::from("linear")), None);
+	gallery
+}
+
+/// Creates the files of the gallery, videos get no poster frame
+fn build_small(gallery: &mut Gallery, out_dir: &PathBuf) -> BuildReport {
+	gallery.create_images(out_dir, "", 2).unwrap()
+}
+
 struct FileCounts {
 	in1: usize,
 	in2: usize,
@@ -374,10 +439,11 @@ fn test_update_existing_collection_same_pictures_and_backgrounds() {
 
 	assert_eq!(gallery.collection_keys.len(), 1);
 
+	// Pictures and backgrounds already part of the collection are not added again
 	let col = &gallery.collections[&gallery.collection_keys[0]];
 	assert_eq!(col.title, "Col 1");
-	assert_eq!(col.pictures.len(), FC.in1 * 2);
-	assert_eq!(col.backgrounds.len(), FC.bg1 * 2);
+	assert_eq!(col.pictures.len(), FC.in1);
+	assert_eq!(col.backgrounds.len(), FC.bg1);
 
 	gallery.remove_duplicates();
 
@@ -385,7 +451,7 @@ fn test_update_existing_collection_same_pictures_and_backgrounds() {
 
 	// Count images that need an update
 	let mut num_updates = 0;
-	for (_, c) in &gallery.collections {
+	for c in gallery.collections.values() {
 		for p in &c.pictures {
 			if p.image.update {
 				num_updates += 1;
@@ -398,5 +464,1415 @@ fn test_update_existing_collection_same_pictures_and_backgrounds() {
 		}
 	}
 
-	assert_eq!(num_updates, FC.in1 + FC.bg1, "Updatablae files should only be half of the added files ({}) it is {}", FC.in1 + FC.bg1, num_updates);
+	assert_eq!(num_updates, FC.in1 + FC.bg1, "Updatable files should only be the first added files ({}) it is {}", FC.in1 + FC.bg1, num_updates);
+}
+
+// TESTS: Resolutions
+
+//   Update gallery with a different thumbnail size
+//       -o out_dir/ -p template_dir/ -c "input_dir1/;bg_dir1/;Col 1"
+//       -u -o out_dir/ --thumb-size 1280x720 -c "input_dir2/;-;Col 1"
+#[test]
+fn test_update_changed_resolution() {
+	let mut gallery = Gallery::new();
+
+	let input_col1 = create_input(dir_in(), dir_bg(), "Col 1");
+	gallery.fill(vec![input_col1], false).unwrap();
+
+	// Previously created images do not need to be created again
+	for c in gallery.collections.values_mut() {
+		for p in c.pictures.iter_mut() {
+			p.image.update = false;
+		}
+		for b in c.backgrounds.iter_mut() {
+			b.update = false;
+		}
+	}
+
+	// Unchanged resolutions do not mark anything as outdated
+	gallery.set_resolutions(Some(gallery.res_thumb), None, Some(gallery.res_background));
+
+	let col = &gallery.collections[&gallery.collection_keys[0]];
+	assert!(col.pictures.iter().all(|p| p.image.outdated.is_empty()));
+	assert!(col.backgrounds.iter().all(|b| b.outdated.is_empty()));
+
+	let res_thumb = Resolution::new("1280x720").unwrap();
+	gallery.set_resolutions(Some(res_thumb), None, None);
+
+	let input_col2 = create_input(dir_in2(), dir_none(), "Col 1");
+	gallery.fill(vec![input_col2], false).unwrap();
+
+	assert_eq!(gallery.res_thumb, res_thumb);
+
+	let col = &gallery.collections[&gallery.collection_keys[0]];
+	assert_eq!(col.pictures.len(), FC.in1 + FC.in2);
+
+	let outdated: Vec<_> = col.pictures.iter().filter(|p| !p.image.outdated.is_empty()).collect();
+	assert_eq!(outdated.len(), FC.in1);
+	assert!(outdated.iter().all(|p| p.image.outdated == vec![Variant::Thumb]));
+	assert!(col.backgrounds.iter().all(|b| b.outdated.is_empty()));
+}
+
+// TESTS: Formats
+
+//   Update gallery with additional formats
+//       -o out_dir/ -p template_dir/ -c "input_dir1/;bg_dir1/;Col 1"
+//       -u -o out_dir/ --formats webp -c "input_dir2/;-;Col 1"
+#[test]
+fn test_update_additional_formats() {
+	let mut gallery = Gallery::new();
+	assert_eq!(gallery.formats, vec![Format::Jpeg]);
+
+	let input_col1 = create_input(dir_in(), dir_bg(), "Col 1");
+	gallery.fill(vec![input_col1], false).unwrap();
+
+	// JPEG is always kept as fallback
+	gallery.set_formats(Some(vec![Format::Webp, Format::Webp]));
+	assert_eq!(gallery.formats, vec![Format::Jpeg, Format::Webp]);
+
+	let col = &gallery.collections[&gallery.collection_keys[0]];
+	assert!(col.pictures.iter().all(|p| p.image.outdated == vec![Variant::Thumb, Variant::Display]));
+	assert!(col.backgrounds.iter().all(|b| b.outdated == vec![Variant::Background]));
+
+	// Formats that are already available do not need to be created again
+	let mut gallery = Gallery::new();
+	let input_col1 = create_input(dir_in(), dir_bg(), "Col 1");
+	gallery.fill(vec![input_col1], false).unwrap();
+	gallery.set_formats(Some(vec![Format::Jpeg]));
+
+	let col = &gallery.collections[&gallery.collection_keys[0]];
+	assert!(col.pictures.iter().all(|p| p.image.outdated.is_empty()));
+	assert!(col.backgrounds.iter().all(|b| b.outdated.is_empty()));
+}
+
+// TESTS: Input formats
+
+//   Create collection from different image formats, files are detected by their content
+//       -o out_dir/ -p template_dir/ -c "formats_dir/;-;Col 1"
+#[test]
+fn test_create_collection_input_formats() {
+	let dir_formats = dir_td().join("formats");
+	assert!(crate::gallery::contains_images(&dir_formats));
+	assert!(!crate::gallery::contains_images(&dir_td()));
+
+	let mut gallery = Gallery::new();
+
+	let input_col1 = create_input(dir_formats, dir_none(), "Col 1");
+	gallery.fill(vec![input_col1], true).unwrap();
+
+	let col = &gallery.collections[&gallery.collection_keys[0]];
+	let mut titles: Vec<&str> = col.pictures.iter().map(|p| p.title.as_str()).collect();
+	titles.sort_unstable();
+	assert_eq!(titles, vec!["animation", "drawing", "scan", "screenshot", "transparent"]);
+}
+
+// TESTS: Videos
+
+//   Create collection with videos, a frame is extracted from each video for the thumbnails
+//       -o out_dir/ -p template_dir/ --video-frame-command "sh extract_frame.sh {input} {output}" -c "video_dir/;-;Col 1"
+#[test]
+fn test_create_collection_videos() {
+	let dir_video = dir_td().join("video");
+	assert!(crate::gallery::contains_images(&dir_video));
+
+	let mut gallery = small_gallery();
+
+	let input_col1 = create_input(dir_video.clone(), dir_none(), "Col 1");
+	gallery.fill(vec![input_col1], true).unwrap();
+
+	let col = &gallery.collections[&gallery.collection_keys[0]];
+	assert_eq!(col.pictures.len(), 2);
+	assert!(col.pictures.iter().all(|p| p.kind == Kind::Video));
+
+	let clip = col.pictures.iter().find(|p| p.title == "clip").unwrap();
+	let info = clip.video.unwrap();
+	assert_eq!((info.width, info.height, info.duration), (640, 360, 2.5));
+
+	let out_dir = test_dir("videos");
+	let command = format!("sh {} {{input}} {{output}}", dir_video.join("extract_frame.sh").to_string_lossy());
+	gallery.create_images(&out_dir, &command, 2).unwrap();
+
+	let pictures_dir = out_dir.join(crate::gallery::PICTURES_DIR_NAME);
+	let col = &gallery.collections[&gallery.collection_keys[0]];
+	for p in &col.pictures {
+		for suffix in &["mp4", "jpg", "disp.jpg", "thumb.jpg"] {
+			let file = pictures_dir.join(format!("{}.{}", p.image.basename, suffix));
+			assert!(file.is_file(), "{} missing", file.to_string_lossy());
+		}
+	}
+}
+
+// TESTS: Hashes
+
+//   Update gallery created before content hashes were used, the checksums are replaced when the sources are added again
+//       -u -o out_dir/ -c "input_dir/;-;Col 1"
+#[test]
+fn test_update_legacy_hashes() {
+	let mut gallery = Gallery::new();
+	let input_col1 = create_input(dir_in(), dir_none(), "Col 1");
+	gallery.fill(vec![input_col1], false).unwrap();
+
+	let out_dir = test_dir("hashes");
+	let pictures_dir = out_dir.join(crate::gallery::PICTURES_DIR_NAME);
+	std::fs::create_dir_all(&pictures_dir).unwrap();
+
+	// Store the gallery the way version 1 did, the files are named by the numeric checksums of the sources
+	let mut data = serde_json::to_value(&gallery).unwrap();
+	data["version"] = serde_json::json!(1);
+	let col = &gallery.collections[&gallery.collection_keys[0]];
+	let pics = data["collections"]["col_1"]["pictures"].as_array_mut().unwrap();
+	for (pic, data) in col.pictures.iter().zip(pics.iter_mut()) {
+		let checksum = crate::mi::fs::file_checksum_legacy(&pic.image.source_path).unwrap();
+		data["path"] = serde_json::json!(checksum.to_string());
+		data["original_hash"] = serde_json::json!(checksum);
+		std::fs::copy(&pic.image.source_path, pictures_dir.join(format!("{}.jpg", checksum))).unwrap();
+	}
+
+	let html = format!(
+		"{}{}{}",
+		String::from_utf8_lossy(crate::gallery::PATTERM_DATA_START),
+		data,
+		String::from_utf8_lossy(crate::gallery::PATTERM_DATA_END)
+	);
+	std::fs::write(out_dir.join("index.html"), html).unwrap();
+
+	// Reading the gallery keeps the checksums
+	let mut updated = Gallery::from(&out_dir).unwrap();
+	assert_eq!(updated.version, crate::gallery::GALLERY_CONFIGURATION_VERSION);
+	let col = &updated.collections[&updated.collection_keys[0]];
+	assert_eq!(col.pictures.len(), FC.in1);
+	assert!(col.pictures.iter().all(|p| p.image.legacy_hash && p.image.original_hash == p.image.basename));
+
+	// Adding the sources again replaces the checksums without duplicating the pictures
+	let input_col1 = create_input(dir_in(), dir_none(), "Col 1");
+	updated.fill(vec![input_col1], false).unwrap();
+	let col = &updated.collections[&updated.collection_keys[0]];
+	assert_eq!(col.pictures.len(), FC.in1);
+	for pic in &col.pictures {
+		assert!(!pic.image.legacy_hash);
+		assert!(!pic.image.update);
+		assert_eq!(pic.image.original_hash, crate::mi::fs::file_hash(&pic.image.source_path).unwrap());
+		assert_eq!(pic.image.basename, crate::mi::fs::file_checksum_legacy(&pic.image.source_path).unwrap().to_string());
+	}
+
+	updated.include_json_data(&out_dir).unwrap();
+	let read = Gallery::from(&out_dir).unwrap();
+	let col = &read.collections[&read.collection_keys[0]];
+	assert!(col.pictures.iter().all(|p| !p.image.legacy_hash && p.image.original_hash != p.image.basename));
+}
+
+// TESTS: Near duplicates
+
+//   Report and merge pictures that look alike
+//       -o out_dir/ -p template_dir/ --near-duplicates -c "input_dir/;-;Col 1"
+//       -o out_dir/ -p template_dir/ --merge-near-duplicates -c "input_dir/;-;Col 1"
+#[test]
+fn test_create_collection_near_duplicates() {
+	let tmp_dir = test_dir("near_duplicates");
+	let input_dir = tmp_dir.join("input");
+	std::fs::create_dir_all(&input_dir).unwrap();
+
+	// A smaller and stronger compressed export of a picture, next to a different picture
+	let source = input_dir.join("IMG_1167_0013.jpg");
+	std::fs::copy(dir_in2().join("IMG_1167_0013.jpg"), &source).unwrap();
+	std::fs::copy(dir_td().join("orientation").join("orientation_1.jpg"), input_dir.join("orientation_1.jpg")).unwrap();
+	let export = crate::mi::img::Variant{ target: input_dir.join("export.jpg"), resolution: Some(Resolution{ width: 640, height: 360 }), format: Format::Jpeg };
+	crate::mi::img::create_variants(&source, &[export], 30, "linear", Color::WHITE).unwrap();
+
+	let mut gallery = small_gallery();
+	let input_col1 = create_input(input_dir.clone(), dir_none(), "Col 1");
+	gallery.fill(vec![input_col1], true).unwrap();
+	gallery.remove_duplicates();
+
+	// The perceptual hashes are computed while creating the images
+	let out_dir = tmp_dir.join("output");
+	build_small(&mut gallery, &out_dir);
+	let col = &gallery.collections[&gallery.collection_keys[0]];
+	assert!(col.pictures.iter().all(|p| p.image.perceptual_hash.is_some()));
+
+	let found = gallery.find_near_duplicates(&out_dir, 5, false, 2);
+	assert_eq!(found.len(), 1);
+	assert_eq!(found[0].path, input_dir.join("export.jpg"));
+	assert_eq!(found[0].similar_to, source);
+
+	// Only reported, both pictures are kept
+	let col = &gallery.collections[&gallery.collection_keys[0]];
+	assert!(col.pictures.iter().all(|p| p.image.update));
+	let export_basename = col.pictures.iter().find(|p| p.title == "export").unwrap().image.basename.clone();
+
+	let found = gallery.find_near_duplicates(&out_dir, 5, true, 2);
+	assert_eq!(found.len(), 1);
+
+	let col = &gallery.collections[&gallery.collection_keys[0]];
+	let original = col.pictures.iter().find(|p| p.title == "IMG_1167_0013").unwrap();
+	let export = col.pictures.iter().find(|p| p.title == "export").unwrap();
+	assert_eq!(export.image.basename, original.image.basename);
+	assert!(original.image.update);
+	assert!(!export.image.update);
+
+	// The files of the merged picture are deleted
+	let deleted = gallery.delete_removed(&out_dir).unwrap();
+	assert_eq!(deleted.len(), 3);
+	assert!(deleted.iter().all(|f| f.file_name().unwrap().to_string_lossy().starts_with(&export_basename)));
+}
+
+// TESTS: Errors
+
+//   Update a directory that does not contain a gallery or whose gallery data is invalid
+//       -u -o out_dir/
+#[test]
+fn test_update_invalid_gallery() {
+	let out_dir = test_dir("invalid");
+	std::fs::create_dir_all(&out_dir).unwrap();
+
+	let e = Gallery::from(&out_dir).unwrap_err();
+	assert_eq!(e.exit_code(), mi::error::EXIT_IO);
+	assert_eq!(e.path(), Some(&out_dir.join("index.html")));
+
+	std::fs::write(out_dir.join("index.html"), "/*{{BEGIN:data*/{\"version\": 2}/*END:data}}*/").unwrap();
+	let e = Gallery::from(&out_dir).unwrap_err();
+	assert_eq!(e.exit_code(), mi::error::EXIT_DATA);
+}
+
+//   Failed pictures are reported with their source and left out of the gallery
+//       -o out_dir/ -p template_dir/ --video-frame-command "false" --failure-report report.json -c "video_dir/;-;Col 1" -c "formats_dir/;-;Col 2"
+#[test]
+fn test_create_images_failures() {
+	let mut gallery = small_gallery();
+
+	let input_col1 = create_input(dir_td().join("video"), dir_none(), "Col 1");
+	let input_col2 = create_input(dir_td().join("formats"), dir_none(), "Col 2");
+	gallery.fill(vec![input_col1, input_col2], true).unwrap();
+
+	let out_dir = test_dir("failures");
+	let report = gallery.create_images(&out_dir, "false", 2).unwrap();
+	assert_eq!(report.processed, 2 + 5);
+	assert_eq!(report.failures.len(), 2);
+	for f in &report.failures {
+		assert_eq!(f.collection, "col_1");
+		assert_eq!(f.source.parent(), Some(dir_td().join("video").as_path()));
+		assert_eq!(f.error.exit_code(), mi::error::EXIT_DECODE);
+	}
+
+	// The build is reported as failed
+	assert_eq!(report.check().unwrap_err().exit_code(), mi::error::EXIT_INCOMPLETE);
+
+	gallery.remove_failed(&report.failures);
+	assert!(gallery.collections["col_1"].pictures.is_empty());
+	assert_eq!(gallery.collections["col_2"].pictures.len(), 5);
+
+	let report_path = out_dir.join("report.json");
+	report.write(&report_path).unwrap();
+	let json: serde_json::Value = serde_json::from_slice(&std::fs::read(&report_path).unwrap()).unwrap();
+	assert_eq!(json["processed"], 2 + 5);
+	assert_eq!(json["failures"][0]["error"]["class"], "decode");
+}
+
+// TESTS: Incremental builds
+
+//   Running the same update again does not create any files, changed settings only re-create affected files
+//       -o out_dir/ -p template_dir/ -c "formats_dir/;-;Col 1"
+//       -u -o out_dir/ -c "formats_dir/;-;Col 1"
+//       -u -o out_dir/ --thumb-size 200x200 -c "formats_dir/;-;Col 1"
+#[test]
+fn test_update_incremental() {
+	let out_dir = temp_gallery("incremental");
+	let pictures_dir = out_dir.join(crate::gallery::PICTURES_DIR_NAME);
+
+	let mut gallery = small_gallery();
+	gallery.fill(vec![create_input(dir_td().join("formats"), dir_none(), "Col 1")], false).unwrap();
+	let report = build_small(&mut gallery, &out_dir);
+	gallery.include_json_data(&out_dir).unwrap();
+
+	assert_eq!(report.processed, 5);
+	let files = std::fs::read_dir(&pictures_dir).unwrap().count();
+	assert_eq!(files, 5 * 3);
+	assert_eq!(gallery.manifest.outputs.len(), files);
+
+	// Same input again
+	let mut gallery = Gallery::from(&out_dir).unwrap();
+	gallery.fill(vec![create_input(dir_td().join("formats"), dir_none(), "Col 1")], false).unwrap();
+	gallery.remove_duplicates();
+	assert_eq!(gallery.collections["col_1"].pictures.len(), 5);
+
+	let report = build_small(&mut gallery, &out_dir);
+	assert_eq!(report.processed, 0);
+	assert_eq!(std::fs::read_dir(&pictures_dir).unwrap().count(), files);
+
+	// Changed thumbnail size
+	gallery.set_resolutions(Some(Resolution{ width: 200, height: 200 }), None, None);
+	let report = build_small(&mut gallery, &out_dir);
+	assert_eq!(report.processed, 5);
+	assert_eq!(std::fs::read_dir(&pictures_dir).unwrap().count(), files);
+
+	let manifest = crate::gallery::Manifest::read(&out_dir).unwrap();
+	for (file, entry) in &manifest.outputs {
+		let render = entry.render.as_ref().unwrap();
+		if file.to_string_lossy().ends_with(".thumb.jpg") {
+			assert_eq!(render.resolution, Some(Resolution{ width: 200, height: 200 }));
+		} else if file.to_string_lossy().ends_with(".disp.jpg") {
+			assert_eq!(render.resolution, SMALL);
+		}
+	}
+
+	// Changed quality and matte color re-create all files, they are stored in the gallery
+	let black = Color{ red: 0, green: 0, blue: 0 };
+	gallery.set_render_settings(Some(60), None, Some(black));
+	let report = build_small(&mut gallery, &out_dir);
+	assert_eq!(report.processed, 5);
+	gallery.include_json_data(&out_dir).unwrap();
+
+	let mut gallery = Gallery::from(&out_dir).unwrap();
+	assert_eq!((gallery.quality, gallery.matte_color), (60, black));
+	gallery.set_render_settings(None, None, None);
+	assert_eq!(build_small(&mut gallery, &out_dir).processed, 0);
+
+	// The manifest is kept inside the output directory
+	assert!(out_dir.join(crate::gallery::MANIFEST_PATH).is_file());
+}
+
+// TESTS: Remove
+
+//   Removed pictures and collections are taken out of the gallery, files still used by other collections are kept
+//       -o out_dir/ -p template_dir/ -c "formats_dir/;-;Col 1" -c "formats_dir/;-;Col 2"
+//       -u -o out_dir/ --remove-collection col_2
+//       -u -o out_dir/ --remove-source formats_dir/transparent.png
+//       -u -o out_dir/ --remove-picture [basename]
+#[test]
+fn test_update_remove() {
+	let out_dir = temp_gallery("remove");
+	let pictures_dir = out_dir.join(crate::gallery::PICTURES_DIR_NAME);
+
+	let mut gallery = small_gallery();
+	gallery.fill(vec![
+		create_input(dir_td().join("formats"), dir_none(), "Col 1"),
+		create_input(dir_td().join("formats"), dir_none(), "Col 2"),
+	], false).unwrap();
+	build_small(&mut gallery, &out_dir);
+	gallery.include_json_data(&out_dir).unwrap();
+	let files = std::fs::read_dir(&pictures_dir).unwrap().count();
+	assert_eq!(files, 5 * 3);
+
+	// Collection sharing all files with another one
+	let mut gallery = Gallery::from(&out_dir).unwrap();
+	gallery.remove_collection("col_2").unwrap();
+	assert!(gallery.remove_collection("col_2").is_err());
+	assert_eq!(gallery.collection_keys, vec![String::from("col_1")]);
+	assert!(gallery.delete_removed(&out_dir).unwrap().is_empty());
+	gallery.include_json_data(&out_dir).unwrap();
+
+	// Source path
+	let mut gallery = Gallery::from(&out_dir).unwrap();
+	assert_eq!(gallery.collections.len(), 1);
+	assert_eq!(gallery.remove_source(&dir_td().join("formats").join("transparent.png")).unwrap(), 1);
+	assert_eq!(gallery.delete_removed(&out_dir).unwrap().len(), 3);
+	assert_eq!(std::fs::read_dir(&pictures_dir).unwrap().count(), files - 3);
+	assert_eq!(gallery.manifest.outputs.len(), files - 3);
+
+	// Basename
+	let basename = gallery.collections["col_1"].pictures[0].image.basename.clone();
+	assert!(gallery.remove_image("0000000000000000").is_err());
+	assert_eq!(gallery.remove_image(&basename).unwrap(), 1);
+	assert_eq!(gallery.delete_removed(&out_dir).unwrap().len(), 3);
+	gallery.include_json_data(&out_dir).unwrap();
+
+	let gallery = Gallery::from(&out_dir).unwrap();
+	assert_eq!(gallery.collections["col_1"].pictures.len(), 3);
+	assert_eq!(std::fs::read_dir(&pictures_dir).unwrap().count(), files - 6);
+}
+
+// TESTS: Garbage collection
+
+//   Files no picture references are listed and deleted, missing files are created again from their sources
+//       -o out_dir/ -p template_dir/ -c "formats_dir/;-;Col 1"
+//       -o out_dir/ -p template_dir/ --gc
+//       -o out_dir/ -p template_dir/ --gc-delete --gc-regenerate
+#[test]
+fn test_gc() {
+	let out_dir = temp_gallery("gc");
+	let pictures_dir = out_dir.join(crate::gallery::PICTURES_DIR_NAME);
+
+	let mut gallery = small_gallery();
+	gallery.fill(vec![create_input(dir_td().join("formats"), dir_none(), "Col 1")], false).unwrap();
+	build_small(&mut gallery, &out_dir);
+	gallery.include_json_data(&out_dir).unwrap();
+
+	let basename = gallery.collections["col_1"].pictures[0].image.basename.clone();
+	let missing = PathBuf::from(crate::gallery::PICTURES_DIR_NAME).join(format!("{}.disp.jpg", basename));
+	let orphan = PathBuf::from(crate::gallery::PICTURES_DIR_NAME).join("0000000000000000.thumb.jpg");
+	std::fs::remove_file(out_dir.join(&missing)).unwrap();
+	std::fs::write(out_dir.join(&orphan), "").unwrap();
+
+	// List only
+	let mut gallery = Gallery::from(&out_dir).unwrap();
+	let settings = gallery.render_settings("");
+	let garbage = gallery.collect_garbage(&out_dir, &settings, false).unwrap();
+	assert_eq!(garbage.orphans, vec![orphan.clone()]);
+	assert_eq!(garbage.missing.len(), 1);
+	assert_eq!(garbage.missing[0].file, missing);
+	assert_eq!(garbage.missing[0].collection, "col_1");
+	assert!(garbage.missing[0].source.is_some());
+	assert!(out_dir.join(&orphan).exists());
+
+	// Delete and regenerate
+	let garbage = gallery.collect_garbage(&out_dir, &settings, true).unwrap();
+	assert!(!out_dir.join(&orphan).exists());
+	gallery.restore_sources(&garbage.missing);
+	let report = build_small(&mut gallery, &out_dir);
+	assert_eq!(report.processed, 1);
+	assert!(out_dir.join(&missing).exists());
+	gallery.remove_failed(&report.failures);
+	gallery.include_json_data(&out_dir).unwrap();
+	assert_eq!(Gallery::from(&out_dir).unwrap().collections["col_1"].pictures.len(), 5);
+
+	let garbage = gallery.collect_garbage(&out_dir, &settings, false).unwrap();
+	assert!(garbage.orphans.is_empty());
+	assert!(garbage.missing.is_empty());
+	assert_eq!(std::fs::read_dir(&pictures_dir).unwrap().count(), 5 * 3);
+}
+
+// TESTS: Commands
+
+//   Commands and the flags of the previous command line interface lead to the same configuration
+//       add -o out_dir/ -c "formats_dir/;-;Col 1"
+//       -u -o out_dir/ -p template_dir/ --remove-collection col_1
+//       remove -o out_dir/ --collection col_1
+//       list -o out_dir/ --pictures
+#[test]
+fn test_configuration_commands() {
+	let out_dir = temp_gallery("commands");
+	let parse = |args: &[&str]| {
+		let out = out_dir.to_string_lossy();
+		let args = ["static_gallery"].iter().chain(args).map(|a| a.replace("out_dir/", &out).into()).collect();
+		Configuration::from_args(args)
+	};
+	let collection = format!("{};-;Col 1", dir_td().join("formats").to_string_lossy());
+	let template = PathBuf::from(&std::env::var("CARGO_MANIFEST_DIR").unwrap()).join("data").join("templates").join("hauer");
+
+	let config = parse(&["add", "-o", "out_dir/", "-c", &collection, "--jpeg-quality", "80"]).unwrap();
+	assert_eq!(config.command, Command::Add);
+	assert!(config.update);
+	assert_eq!(config.input.collections.len(), 1);
+	assert_eq!(config.render.jpeg_quality(), 80);
+
+	let config = parse(&["-u", "-o", "out_dir/", "-p", &template.to_string_lossy(), "--remove-collection", "col_1"]).unwrap();
+	assert_eq!(config.command, Command::Remove);
+	assert_eq!(config.remove_collections, vec![String::from("col_1")]);
+
+	let config = parse(&["remove", "-o", "out_dir/", "--collection", "col_1", "--collection", "col_2"]).unwrap();
+	assert_eq!(config.command, Command::Remove);
+	assert_eq!(config.remove_collections.len(), 2);
+
+	let config = parse(&["list", "-o", "out_dir/", "--pictures"]).unwrap();
+	assert_eq!(config.command, Command::List{ pictures: true });
+
+	// Nothing to remove, no template for a new gallery, no gallery to update
+	assert!(parse(&["remove", "-o", "out_dir/"]).is_err());
+	assert!(parse(&["build", "-o", "out_dir/", "-c", &collection]).is_err());
+	let err = parse(&["status", "-o", "out_dir/missing"]).unwrap_err();
+	assert_eq!(err.exit_code(), mi::error::EXIT_CONFIG);
+}
+
+// TESTS: Verify
+
+//   Missing and damaged files are found
+//       build -o out_dir/ -p template_dir/ -c "formats_dir/;-;Col 1"
+//       verify -o out_dir/
+//       status -o out_dir/
+#[test]
+fn test_verify() {
+	let out_dir = temp_gallery("verify");
+
+	let mut gallery = small_gallery();
+	gallery.set_formats(Some(vec![Format::Jpeg, Format::Webp]));
+	gallery.fill(vec![create_input(dir_td().join("formats"), dir_none(), "Col 1")], false).unwrap();
+	build_small(&mut gallery, &out_dir);
+	let settings = gallery.render_settings("");
+
+	let report = gallery.verify(&out_dir, &settings).unwrap();
+	assert!(report.garbage.missing.is_empty());
+	assert!(report.invalid.is_empty());
+
+	let status = gallery.status(&out_dir, &settings);
+	assert_eq!(status.len(), 1);
+	assert_eq!(status[0].pictures, 5);
+	assert_eq!(status[0].files, 5 * 5);
+	assert!(status[0].size > 0);
+
+	// Truncated display version and missing thumbnail
+	let basename = gallery.collections["col_1"].pictures[0].image.basename.clone();
+	let pictures_dir = out_dir.join(crate::gallery::PICTURES_DIR_NAME);
+	let display = pictures_dir.join(format!("{}.disp.jpg", basename));
+	let data = std::fs::read(&display).unwrap();
+	std::fs::write(&display, &data[..data.len() / 2]).unwrap();
+	std::fs::remove_file(pictures_dir.join(format!("{}.thumb.webp", basename))).unwrap();
+
+	let report = gallery.verify(&out_dir, &settings).unwrap();
+	assert_eq!(report.garbage.missing.len(), 1);
+	assert_eq!(report.invalid.len(), 1);
+	assert_eq!(report.invalid[0].path(), Some(&display));
+}
+
+// TESTS: Project file
+
+//   Options and collections are read from the project file, options given on the command line take precedence
+//       build -f gallery.toml --jpeg-quality 90
+#[test]
+fn test_configuration_project() {
+	let project_dir = test_dir("project");
+	std::fs::create_dir_all(&project_dir).unwrap();
+	let template = PathBuf::from(&std::env::var("CARGO_MANIFEST_DIR").unwrap()).join("data").join("templates").join("hauer");
+	let project = project_dir.join("gallery.toml");
+	std::fs::write(&project, format!(r#"
+		output = "out"
+		template = "{template}"
+		display_size = "1280x720"
+		formats = ["jpeg", "webp"]
+		quality = 80
+		resize_method = "linear"
+
+		[[collections]]
+		title = "Col 1"
+		inputs = ["{formats}/*.png", "{input}"]
+		description = "First collection"
+
+		[[collections]]
+		title = "Col 2"
+		inputs = ["{input}"]
+		backgrounds = ["{backgrounds}"]
+		"#,
+		template = template.to_string_lossy(),
+		formats = dir_td().join("formats").to_string_lossy(),
+		input = dir_in().to_string_lossy(),
+		backgrounds = dir_bg().to_string_lossy(),
+	)).unwrap();
+
+	let args = ["static_gallery", "build", "-f", &project.to_string_lossy(), "--jpeg-quality", "90"];
+	let config = Configuration::from_args(args.iter().map(|a| a.into()).collect()).unwrap();
+	assert_eq!(config.output_dir, project_dir.join("out"));
+	assert_eq!(config.template_dir, Some(template));
+	assert_eq!(config.render.display_size, Some(Resolution{ width: 1280, height: 720 }));
+	assert_eq!(config.render.formats, Some(vec![Format::Jpeg, Format::Webp]));
+	assert_eq!(config.render.jpeg_quality(), 90);
+	assert_eq!(config.render.resize_method(), "linear");
+
+	let collections = &config.input.collections;
+	assert_eq!(collections.len(), 2);
+	assert_eq!(collections[0].name, "col_1");
+	assert_eq!(collections[0].details.description.as_deref(), Some("First collection"));
+	assert_eq!(collections[0].input_files.len(), 1 + FC.in1);
+	assert_eq!(collections[1].background_files.len(), FC.bg1);
+
+	let mut gallery = Gallery::new();
+	gallery.fill(config.input.collections, false).unwrap();
+	assert_eq!(gallery.collections["col_1"].pictures.len(), 1 + FC.in1);
+	assert_eq!(gallery.collections["col_1"].description.as_deref(), Some("First collection"));
+
+	// Unknown keys are errors
+	std::fs::write(&project, "output = \"out\"\nquallity = 80\n").unwrap();
+	let args = ["static_gallery", "add", "-f", &project.to_string_lossy()];
+	assert!(Configuration::from_args(args.iter().map(|a| a.into()).collect()).is_err());
+
+	// Only images and videos of input directories are used, inputs without any are errors
+	let input_dir = project_dir.join("input");
+	std::fs::create_dir_all(&input_dir).unwrap();
+	std::fs::write(input_dir.join("order.txt"), "picture.jpg\n").unwrap();
+	std::fs::write(input_dir.join("collection.toml"), "location = \"Berlin\"\n").unwrap();
+	std::fs::write(&project, format!(
+		"output = \"out\"\ntemplate = \"{}\"\n[[collections]]\ntitle = \"Col 1\"\ninputs = [\"input\"]\n",
+		config.template_dir.as_ref().unwrap().to_string_lossy(),
+	)).unwrap();
+	let args = ["static_gallery", "build", "-f", &project.to_string_lossy()];
+	assert!(Configuration::from_args(args.iter().map(|a| a.into()).collect()).is_err());
+	std::fs::copy(dir_td().join("orientation").join("orientation_1.jpg"), input_dir.join("picture.jpg")).unwrap();
+	let config = Configuration::from_args(args.iter().map(|a| a.into()).collect()).unwrap();
+	assert_eq!(config.input.collections[0].input_files, vec![input_dir.join("picture.jpg")]);
+	assert_eq!(config.input.collections[0].details.location.as_deref(), Some("Berlin"));
+}
+
+// TESTS: Collection settings
+
+//   Collections with own render settings are created with them, the others with the settings of the gallery
+//       build -o out_dir/ -p template_dir/ --collection-settings "col_2:display-size=200x200,jpeg-quality=60"
+//             -c "formats_dir/;-;Col 1" -c "orientation_dir/;-;Col 2"
+//       add -o out_dir/ --collection-settings "col_1:resize-method=nearest"
+#[test]
+fn test_collection_overrides() {
+	let out_dir = temp_gallery("overrides");
+
+	let overrides = RenderOverrides::from_str("display-size=200x200,jpeg-quality=60").unwrap();
+	assert_eq!(overrides.res_display, Some(Resolution{ width: 200, height: 200 }));
+	assert_eq!(overrides.quality, Some(60));
+	assert!(RenderOverrides::from_str("quality=60").is_err());
+
+	let mut gallery = small_gallery();
+	let mut input_col2 = create_input(dir_td().join("orientation"), dir_none(), "Col 2");
+	input_col2.render = overrides;
+	gallery.fill(vec![create_input(dir_td().join("formats"), dir_none(), "Col 1"), input_col2], false).unwrap();
+	let report = build_small(&mut gallery, &out_dir);
+	assert_eq!(report.processed, 5 + 8);
+	gallery.include_json_data(&out_dir).unwrap();
+
+	let col1: Vec<String> = gallery.collections["col_1"].pictures.iter().map(|p| p.image.basename.clone()).collect();
+	for (file, entry) in &gallery.manifest.outputs {
+		let name = file.file_name().unwrap().to_string_lossy();
+		let render = entry.render.as_ref().unwrap();
+		let in_col1 = col1.iter().any(|b| name.starts_with(b.as_str()));
+		assert_eq!(render.quality, if in_col1 { crate::gallery::DEFAULT_JPEG_QUALITY } else { 60 });
+		if name.ends_with(".disp.jpg") {
+			assert_eq!(render.resolution, if in_col1 { SMALL } else { Some(Resolution{ width: 200, height: 200 }) });
+		}
+	}
+
+	// Stored in the gallery data, only collections with own settings have them
+	let mut gallery = Gallery::from(&out_dir).unwrap();
+	assert!(gallery.collections["col_1"].render.is_empty());
+	assert_eq!(gallery.collections["col_2"].render.quality, Some(60));
+
+	// Changed settings only re-create the files of that collection
+	gallery.set_overrides("col_1", RenderOverrides::from_str("resize-method=nearest").unwrap()).unwrap();
+	assert!(gallery.set_overrides("col_3", RenderOverrides::default()).is_err());
+	let report = build_small(&mut gallery, &out_dir);
+	assert_eq!(report.processed, 5);
+	assert_eq!(gallery.collections["col_2"].render.quality, Some(60));
+}
+
+// TESTS: Exif metadata
+
+//   Only the selected exif fields are published, changing the selection removes the ones no longer published
+//       build -o out_dir/ -p template_dir/ --exif-fields "date,camera,iso" -c "input_dir/;-;Col 1"
+//       add -o out_dir/ --exif-fields "date,camera,iso,lens" -c "input_dir/;-;Col 1"
+//       add -o out_dir/ --exif-fields iso
+//       add -o out_dir/ --exif-fields none
+#[test]
+fn test_exif_fields() {
+	let out_dir = temp_gallery("exif");
+	let in_dir = test_dir("exif_input");
+	std::fs::create_dir_all(&in_dir).unwrap();
+	std::fs::copy(dir_in2().join("IMG_1167_0013.jpg"), in_dir.join("IMG_1167_0013.jpg")).unwrap();
+
+	// Read while the images are created
+	let mut gallery = small_gallery();
+	gallery.set_exif_fields(Some(ExifFields::from_str("date,camera,iso").unwrap()));
+	gallery.fill(vec![create_input(in_dir.clone(), dir_none(), "Col 1")], false).unwrap();
+	gallery.read_exif();
+	assert!(gallery.collections["col_1"].pictures.iter().all(|p| p.exif.is_none()));
+	build_small(&mut gallery, &out_dir);
+
+	for p in &gallery.collections["col_1"].pictures {
+		let exif = p.exif.as_ref().unwrap();
+		assert!(exif.date.is_some() && exif.camera.is_some() && exif.iso.is_some());
+		assert!(exif.lens.is_none() && exif.exposure.is_none() && exif.aperture.is_none() && exif.focal_length.is_none());
+	}
+	gallery.include_json_data(&out_dir).unwrap();
+
+	// Stored in the gallery data
+	let mut gallery = Gallery::from(&out_dir).unwrap();
+	assert_eq!(gallery.exif_fields, vec![ExifField::Date, ExifField::Camera, ExifField::Iso]);
+	assert!(gallery.collections["col_1"].pictures.iter().all(|p| p.exif.as_ref().is_some_and(|e| e.camera.is_some())));
+
+	// Newly selected fields are read for the existing pictures whose source is given
+	gallery.set_exif_fields(Some(ExifFields::from_str("date,camera,iso,lens").unwrap()));
+	gallery.fill(vec![create_input(in_dir.clone(), dir_none(), "Col 1")], false).unwrap();
+	gallery.read_exif();
+	for p in &gallery.collections["col_1"].pictures {
+		let source = crate::mi::img::get_exif_data(&p.image.source_path).unwrap().unwrap();
+		assert_eq!(p.exif.as_ref().unwrap().lens, source.lens);
+	}
+	assert!(gallery.collections["col_1"].pictures.iter().any(|p| p.exif.as_ref().is_some_and(|e| e.lens.is_some())));
+
+	// Fields can be removed without the sources
+	gallery.set_exif_fields(Some(ExifFields::from_str("iso").unwrap()));
+	for p in &gallery.collections["col_1"].pictures {
+		let exif = p.exif.as_ref().unwrap();
+		assert!(exif.iso.is_some() && exif.date.is_none() && exif.camera.is_none());
+	}
+
+	// Not given keeps the selection
+	gallery.set_exif_fields(None);
+	assert_eq!(gallery.exif_fields, vec![ExifField::Iso]);
+
+	gallery.set_exif_fields(Some(ExifFields::from_str("none").unwrap()));
+	assert!(gallery.collections["col_1"].pictures.iter().all(|p| p.exif.is_none()));
+	gallery.include_json_data(&out_dir).unwrap();
+	let html = std::fs::read_to_string(out_dir.join("index.html")).unwrap();
+	assert!(!html.contains("exif"));
+}
+
+// TESTS: Sort
+
+fn source_names(gallery: &Gallery, key: &str) -> Vec<String> {
+	gallery.collections[key].pictures.iter()
+		.map(|p| p.sort_keys.name.clone().unwrap_or_default())
+		.collect()
+}
+
+//   Pictures are sorted by capture time, modification time, a seeded random order or the name
+//       build -o out_dir/ -p template_dir/ --sort exif-date -c "date_dir/;-;Col 1"
+//       build -o out_dir/ -p template_dir/ --sort mtime -c "sort_dir/;-;Col 1"
+//       build -o out_dir/ -p template_dir/ --sort random-seeded=7 -c "orientation_dir/;-;Col 1"
+#[test]
+fn test_sort_orders() {
+	// A picture taken in 2016 but modified later than a picture without capture time
+	let date_dir = test_dir("sort_date");
+	std::fs::create_dir_all(&date_dir).unwrap();
+	let set_modified = |file: &PathBuf, secs: u64| {
+		let modified = std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs);
+		std::fs::File::options().write(true).open(file).unwrap().set_modified(modified).unwrap();
+	};
+	let photo = date_dir.join("IMG_1167_0013.jpg");
+	std::fs::copy(dir_in2().join("IMG_1167_0013.jpg"), &photo).unwrap();
+	set_modified(&photo, 1_900_000_000);
+	let undated = date_dir.join("orientation_1.jpg");
+	std::fs::copy(dir_td().join("orientation").join("orientation_1.jpg"), &undated).unwrap();
+	set_modified(&undated, 1_600_000_000);
+
+	let mut input = create_input(date_dir.clone(), dir_none(), "Col 1");
+	input.sort = Some(SortOrder::ExifDate);
+	let mut gallery = small_gallery();
+	gallery.fill(vec![input], false).unwrap();
+
+	// The modification times are used until the exif data is read while creating the images
+	assert!(gallery.collections["col_1"].pictures.iter().all(|p| p.sort_keys.date.is_some() && p.sort_keys.name.is_none()));
+	let sources = |gallery: &Gallery| gallery.collections["col_1"].pictures.iter()
+		.map(|p| p.image.source_path.clone())
+		.collect::<Vec<PathBuf>>();
+	assert_eq!(sources(&gallery), vec![undated.clone(), photo.clone()]);
+
+	let out_dir = date_dir.join("out");
+	build_small(&mut gallery, &out_dir);
+	assert_eq!(sources(&gallery), vec![photo.clone(), undated.clone()]);
+	let col = &gallery.collections["col_1"];
+	assert_eq!(col.pictures[0].sort_keys.date, crate::mi::img::get_capture_time(&photo).unwrap());
+	assert_eq!(col.pictures[1].sort_keys.date, Some(1_600_000_000));
+
+	// Modification times in reverse order of the names
+	let sort_dir = test_dir("sort_mtime");
+	std::fs::create_dir_all(&sort_dir).unwrap();
+	for i in 1..=4 {
+		let file = sort_dir.join(format!("orientation_{}.jpg", i));
+		std::fs::copy(dir_td().join("orientation").join(format!("orientation_{}.jpg", i)), &file).unwrap();
+		set_modified(&file, 1_600_000_000 - i * 60);
+	}
+	let mut input = create_input(sort_dir.clone(), dir_none(), "Col 1");
+	input.sort = Some(SortOrder::from_str("mtime").unwrap());
+	let mut gallery = Gallery::new();
+	gallery.fill(vec![input], false).unwrap();
+	let names: Vec<String> = gallery.collections["col_1"].pictures.iter().map(|p| p.image.source_path.file_name().unwrap().to_string_lossy().to_string()).collect();
+	assert_eq!(names, vec!["orientation_4.jpg", "orientation_3.jpg", "orientation_2.jpg", "orientation_1.jpg"]);
+
+	// The same seed gives the same order
+	let shuffled = |seed: u64| {
+		let mut input = create_input(dir_td().join("orientation"), dir_none(), "Col 1");
+		input.sort = Some(SortOrder::RandomSeeded(seed));
+		let mut gallery = Gallery::new();
+		gallery.fill(vec![input], false).unwrap();
+		gallery.collections["col_1"].pictures.iter().map(|p| p.image.basename.clone()).collect::<Vec<String>>()
+	};
+	assert_eq!(shuffled(7), shuffled(7));
+	assert_ne!(shuffled(7), shuffled(8));
+	assert_eq!(shuffled(7).len(), 8);
+
+	assert_eq!(SortOrder::from_str("random-seeded").unwrap(), SortOrder::RandomSeeded(0));
+	assert_eq!(SortOrder::from_str("random-seeded=42").unwrap().to_string(), "random-seeded=42");
+	assert_eq!(SortOrder::from_str("manual").unwrap(), SortOrder::Manual);
+	assert!(SortOrder::from_str("date").is_err());
+	assert!(SortOrder::from_str("name=1").is_err());
+}
+
+//   The order is kept when pictures are added, the order file is read from the input directory
+//       build -o out_dir/ -p template_dir/ --sort manual -c "manual_dir/;-;Col 1" -c "name_dir/;-;Col 2"
+//       add -o out_dir/ -c "more_dir/;-;Col 1" -c "more_dir/;-;Col 2" --sort col_2:name
+#[test]
+fn test_sort_append() {
+	let out_dir = temp_gallery("sort");
+	let manual_dir = out_dir.join("manual");
+	let name_dir = out_dir.join("name");
+	let more_dir = out_dir.join("more");
+	for (dir, pictures) in [(&manual_dir, vec![1, 2, 4]), (&name_dir, vec![5, 6, 8]), (&more_dir, vec![3, 7])] {
+		std::fs::create_dir_all(dir).unwrap();
+		for i in pictures {
+			let name = format!("orientation_{}.jpg", i);
+			std::fs::copy(dir_td().join("orientation").join(&name), dir.join(&name)).unwrap();
+		}
+	}
+	std::fs::write(manual_dir.join(crate::gallery::ORDER_FILE_NAME), "# Shown first\norientation_4.jpg\n\norientation_1.jpg\n").unwrap();
+
+	let mut col1 = create_input(manual_dir.clone(), dir_none(), "Col 1");
+	col1.sort = Some(SortOrder::Manual);
+	let mut gallery = Gallery::new();
+	gallery.fill(vec![col1, create_input(name_dir.clone(), dir_none(), "Col 2")], false).unwrap();
+	assert_eq!(source_names(&gallery, "col_1"), vec!["orientation_4.jpg", "orientation_1.jpg", "orientation_2.jpg"]);
+	assert!(gallery.collections["col_2"].sort.is_none());
+	gallery.include_json_data(&out_dir).unwrap();
+
+	// The sort keys are not published
+	assert!(!std::fs::read_to_string(out_dir.join("index.html")).unwrap().contains("orientation_4.jpg"));
+	assert_eq!(crate::gallery::Manifest::read(&out_dir).unwrap().sort_keys["col_1"].len(), 3);
+
+	// Stored positions are used without order file, pictures not listed follow by name
+	let mut gallery = Gallery::from(&out_dir).unwrap();
+	assert_eq!(gallery.collections["col_1"].sort, Some(SortOrder::Manual));
+	assert!(gallery.collections["col_2"].pictures.iter().all(|p| p.sort_keys.is_empty()));
+	gallery.fill(vec![create_input(more_dir.clone(), dir_none(), "Col 1")], false).unwrap();
+	assert_eq!(source_names(&gallery, "col_1"), vec!["orientation_4.jpg", "orientation_1.jpg", "orientation_2.jpg", "orientation_3.jpg", "orientation_7.jpg"]);
+
+	// Collections without order keep the order the pictures were added in, their names are only known when
+	// their sources are given again
+	let mut col2 = create_input(more_dir.clone(), dir_none(), "Col 2");
+	col2.sort = Some(SortOrder::Name);
+	gallery.fill(vec![col2], false).unwrap();
+	assert_eq!(source_names(&gallery, "col_2")[..2], ["orientation_3.jpg", "orientation_7.jpg"]);
+
+	gallery.fill(vec![create_input(name_dir.clone(), dir_none(), "Col 2")], false).unwrap();
+	assert_eq!(
+		source_names(&gallery, "col_2"),
+		vec!["orientation_3.jpg", "orientation_5.jpg", "orientation_6.jpg", "orientation_7.jpg", "orientation_8.jpg"],
+	);
+
+	assert!(gallery.set_sort("col_3", SortOrder::Name).is_err());
+
+	// Entries of an order file only apply to the pictures of its directory
+	std::fs::write(more_dir.join(crate::gallery::ORDER_FILE_NAME), "orientation_7.jpg\norientation_2.jpg\norientation_3.jpg\n").unwrap();
+	let mut gallery = Gallery::new();
+	for dir in [&manual_dir, &more_dir] {
+		let mut input = create_input(dir.clone(), dir_none(), "Col 1");
+		input.sort = Some(SortOrder::Manual);
+		gallery.fill(vec![input], false).unwrap();
+	}
+	assert_eq!(
+		source_names(&gallery, "col_1"),
+		vec!["orientation_4.jpg", "orientation_1.jpg", "orientation_7.jpg", "orientation_3.jpg", "orientation_2.jpg"],
+	);
+}
+
+// TESTS: Captions
+
+//   Titles and descriptions are read from sidecar files and the metadata of the pictures, the image names are
+//   used as titles for the others
+//       build -o out_dir/ -p template_dir/ --image-name-titles -c "caption_dir/;-;Col 1"
+//       build -o out_dir/ -p template_dir/ --caption-sources none -c "caption_dir/;-;Col 1"
+#[test]
+fn test_captions() {
+	let caption_dir = test_dir("captions");
+	std::fs::create_dir_all(&caption_dir).unwrap();
+	for i in 1..=2 {
+		let name = format!("orientation_{}.jpg", i);
+		std::fs::copy(dir_td().join("orientation").join(&name), caption_dir.join(&name)).unwrap();
+	}
+	std::fs::copy(dir_in2().join("IMG_1209_0015.jpg"), caption_dir.join("IMG_1209_0015.jpg")).unwrap();
+	std::fs::write(caption_dir.join("orientation_1.jpg.txt"), "Upright\nStored without rotation").unwrap();
+
+	let captions = |sources: &[CaptionSource]| {
+		let mut gallery = Gallery::new();
+		gallery.fill(vec![create_input(caption_dir.clone(), dir_none(), "Col 1")], true).unwrap();
+		gallery.read_captions(sources);
+		let mut captions: Vec<(String, Option<String>)> = gallery.collections["col_1"].pictures.iter().map(|p| (p.title.clone(), p.description.clone())).collect();
+		captions.sort();
+		captions
+	};
+
+	assert_eq!(captions(&CaptionSource::DEFAULT), vec![
+		(String::from("IMG_1209_0015"), None),
+		(String::from("Upright"), Some(String::from("Stored without rotation"))),
+		(String::from("orientation_2"), None),
+	]);
+	assert_eq!(captions(&[CaptionSource::Txt, CaptionSource::Exif]), vec![
+		(String::from("Kahului"), None),
+		(String::from("Upright"), Some(String::from("Stored without rotation"))),
+		(String::from("orientation_2"), None),
+	]);
+	assert_eq!(captions(&[]), vec![
+		(String::from("IMG_1209_0015"), None),
+		(String::from("orientation_1"), None),
+		(String::from("orientation_2"), None),
+	]);
+
+	// Pictures already in the gallery keep their captions
+	let mut gallery = Gallery::new();
+	gallery.fill(vec![create_input(caption_dir.clone(), dir_none(), "Col 1")], true).unwrap();
+	for p in gallery.collections.get_mut("col_1").unwrap().pictures.iter_mut() {
+		p.image.update = p.title == "orientation_1";
+	}
+	gallery.read_captions(&CaptionSource::DEFAULT);
+	let mut titles: Vec<&str> = gallery.collections["col_1"].pictures.iter().map(|p| p.title.as_str()).collect();
+	titles.sort_unstable();
+	assert_eq!(titles, vec!["IMG_1209_0015", "Upright", "orientation_2"]);
+}
+
+// TESTS: Collection details
+
+//   Description, dates, location and cover are read from the collection file of the input directory, those
+//   given on the command line take precedence. The cover gets its own variant.
+//       build -o out_dir/ -p template_dir/ --cover-size 200x200 --collection-info "col_1:location=Wailea"
+//             -c "details_dir/;-;Col 1"
+//       add -o out_dir/ --collection-info "col_1:cover=orientation_3.jpg"
+#[test]
+fn test_collection_details() {
+	let details_dir = test_dir("details");
+	let out_dir = details_dir.join("out");
+	let in_dir = details_dir.join("in");
+	std::fs::create_dir_all(&out_dir).unwrap();
+	std::fs::create_dir_all(&in_dir).unwrap();
+	std::fs::write(out_dir.join("index.html"), "/*{{BEGIN:data*//*END:data}}*/").unwrap();
+	for i in 1..=3 {
+		let name = format!("orientation_{}.jpg", i);
+		std::fs::copy(dir_td().join("orientation").join(&name), in_dir.join(&name)).unwrap();
+	}
+	std::fs::write(in_dir.join(crate::gallery::COLLECTION_FILE_NAME), concat!(
+		"description = \"Two weeks on *Maui*\"\n",
+		"dates = \"2023-07-01..2023-07-14\"\n",
+		"location = \"Maui\"\n",
+		"cover = \"orientation_2.jpg\"\n",
+	)).unwrap();
+
+	assert_eq!(DateRange::from_str("2023-07").unwrap(), DateRange{ start: String::from("2023-07"), end: String::from("2023-07") });
+	assert!(DateRange::from_str("2023-13-01").is_err());
+	assert!(DateRange::from_str("2023-07-14..2023-07-01").is_err());
+	assert!(CollectionInfo::from_str("col_1:weather=sunny").is_err());
+	assert!(CollectionInfo::from_str("col_1").is_err());
+
+	let mut input = create_input(in_dir.clone(), dir_none(), "Col 1");
+	assert_eq!(input.details.location.as_deref(), Some("Maui"));
+	input.details.update(CollectionInfo::from_str("col_1:location=Wailea").unwrap().details);
+
+	let mut gallery = small_gallery();
+	gallery.set_cover_resolution(Some(Resolution{ width: 200, height: 200 }));
+	gallery.fill(vec![input], false).unwrap();
+	build_small(&mut gallery, &out_dir);
+	gallery.include_json_data(&out_dir).unwrap();
+
+	let gallery = Gallery::from(&out_dir).unwrap();
+	let col = &gallery.collections["col_1"];
+	assert_eq!(col.description.as_deref(), Some("Two weeks on *Maui*"));
+	assert_eq!(col.description_html.as_deref(), Some("<p>Two weeks on <em>Maui</em></p>\n"));
+	assert_eq!(col.dates.as_ref().map(|d| d.to_string()).as_deref(), Some("2023-07-01..2023-07-14"));
+	assert_eq!(col.location.as_deref(), Some("Wailea"));
+	assert_eq!(gallery.res_cover, Resolution{ width: 200, height: 200 });
+
+	// Only the cover has the cover variant
+	let cover = col.cover.clone().unwrap();
+	let covers: Vec<PathBuf> = gallery.manifest.outputs.keys().filter(|f| f.to_string_lossy().contains(".cover.")).cloned().collect();
+	assert_eq!(covers, vec![PathBuf::from(crate::gallery::PICTURES_DIR_NAME).join(format!("{}.{}.jpg", cover, Variant::Cover.suffix()))]);
+	assert!(out_dir.join(&covers[0]).is_file());
+
+	// A new cover is rendered when updating, one that is not part of the collection is an error
+	let mut gallery = Gallery::from(&out_dir).unwrap();
+	assert!(gallery.set_details("col_1", CollectionDetails{ cover: Some(String::from("IMG_5741.jpg")), ..CollectionDetails::default() }).is_err());
+	let new_cover = gallery.collections["col_1"].pictures.iter().find(|p| p.image.basename != cover).unwrap().image.basename.clone();
+	gallery.set_details("col_1", CollectionDetails{ cover: Some(new_cover.clone()), ..CollectionDetails::default() }).unwrap();
+	let report = build_small(&mut gallery, &out_dir);
+	assert_eq!(report.processed, 1);
+	assert!(out_dir.join(crate::gallery::PICTURES_DIR_NAME).join(format!("{}.cover.jpg", new_cover)).is_file());
+	assert_eq!(gallery.collections["col_1"].location.as_deref(), Some("Wailea"));
+
+	// Removing the cover picture removes the cover
+	gallery.remove_image(&new_cover).unwrap();
+	assert_eq!(gallery.collections["col_1"].cover, None);
+}
+
+// TESTS: Scan
+
+//   Each leaf directory of the tree is a collection, directories of the same name are told apart by their path.
+//   Backgrounds come from the nearest "_backgrounds" directory. Nested directories are nested collections.
+//       build -o out_dir/ -p template_dir/ --scan scan_dir/
+//       build -o out_dir/ -p template_dir/ --scan scan_dir/ --scan-nested
+#[test]
+fn test_scan() {
+	let scan_dir = test_dir("scan");
+	let out_dir = test_dir("scan_out");
+	let template = PathBuf::from(&std::env::var("CARGO_MANIFEST_DIR").unwrap()).join("data").join("templates").join("hauer");
+	let picture = dir_td().join("orientation").join("orientation_1.jpg");
+	for dir in ["day-1", "day-1/_backgrounds", "day-2", "day-2/morning", "day-2/evening", "day-3/morning", "_backgrounds"].iter() {
+		std::fs::create_dir_all(scan_dir.join(dir)).unwrap();
+		std::fs::copy(&picture, scan_dir.join(dir).join("picture.jpg")).unwrap();
+	}
+	std::fs::create_dir_all(scan_dir.join(".hidden")).unwrap();
+	std::fs::copy(&picture, scan_dir.join(".hidden").join("picture.jpg")).unwrap();
+	// Links to parent directories are not followed again
+	#[cfg(unix)]
+	std::os::unix::fs::symlink(&scan_dir, scan_dir.join("day-2").join("morning").join("loop")).unwrap();
+
+	let scanned = |nested: bool| {
+		let mut args = vec!["static_gallery", "build", "-o", out_dir.to_str().unwrap(), "-p", template.to_str().unwrap(), "--scan", scan_dir.to_str().unwrap()];
+		if nested {
+			args.push("--scan-nested");
+		}
+		let config = Configuration::from_args(args.iter().map(|a| a.into()).collect()).unwrap();
+		config.input.collections.iter()
+			.map(|c| (
+				c.name.clone(),
+				c.title.clone(),
+				c.background_dir.clone().unwrap().strip_prefix(&scan_dir).unwrap().to_path_buf(),
+				c.parent.clone(),
+			))
+			.collect::<Vec<(String, String, PathBuf, Option<String>)>>()
+	};
+	let collection = |name: &str, title: &str, backgrounds: &str, parent: Option<&str>| {
+		(String::from(name), String::from(title), PathBuf::from(backgrounds), parent.map(String::from))
+	};
+
+	assert_eq!(scanned(false), vec![
+		collection("day-1", "day-1", "day-1/_backgrounds", None),
+		collection("evening", "evening", "_backgrounds", None),
+		collection("day-2_-_morning", "day-2 / morning", "_backgrounds", None),
+		collection("day-3_-_morning", "day-3 / morning", "_backgrounds", None),
+	]);
+	assert_eq!(scanned(true), vec![
+		collection("day-1", "day-1", "day-1/_backgrounds", None),
+		collection("day-2", "day-2", "_backgrounds", None),
+		collection("day-2_-_evening", "evening", "_backgrounds", Some("day-2")),
+		collection("day-2_-_morning", "morning", "_backgrounds", Some("day-2")),
+		collection("day-3", "day-3", "_backgrounds", None),
+		collection("day-3_-_morning", "morning", "_backgrounds", Some("day-3")),
+	]);
+
+	let missing = scan_dir.join("missing");
+	let args = ["static_gallery", "build", "-o", out_dir.to_str().unwrap(), "-p", template.to_str().unwrap(), "--scan", missing.to_str().unwrap()];
+	assert!(Configuration::from_args(args.iter().map(|a| a.into()).collect()).is_err());
+}
+
+// TESTS: Nested collections
+
+//   Collections are shown below their parents, parents without pictures are left out of the flat list of
+//   collections. Pictures of a parent that its children show as well are removed from the parent if requested.
+//       build -o out_dir/ -p template_dir/ -c "input_dir/;-;Event" -c "input_dir/;-;Day 1" -c "input_dir2/;-;Day 2"
+//             -c "input_dir3/;-;Other" --collection-parent "day_1:event" --collection-parent "day_2:event" --archive
+//             --remove-nested-duplicates
+//       add -o out_dir/ --collection-parent "other:day_2"
+//       remove -o out_dir/ --collection day_2
+#[test]
+fn test_nested_collections() {
+	let out_dir = temp_gallery("nested");
+
+	let parent = CollectionParent::from_str("Day 1:event").unwrap();
+	assert_eq!((parent.key.as_str(), parent.parent.as_deref()), ("day_1", Some("event")));
+	assert_eq!(CollectionParent::from_str("day_1:").unwrap().parent, None);
+
+	let mut day_1 = create_input(dir_in(), dir_none(), "Day 1");
+	day_1.parent = Some(String::from("event"));
+	let mut day_2 = create_input(dir_in2(), dir_none(), "Day 2");
+	day_2.parent = Some(String::from("event"));
+	let inputs = vec![create_input(dir_in(), dir_none(), "Event"), day_1, day_2, create_input(dir_in3(), dir_none(), "Other")];
+
+	let mut gallery = Gallery::new();
+	gallery.fill(inputs, false).unwrap();
+	assert_eq!(gallery.keys(), vec!["event", "day_1", "day_2", "other"]);
+	assert_eq!(gallery.root_keys, vec!["event", "other"]);
+	assert_eq!(gallery.collections["event"].children, vec!["day_1", "day_2"]);
+	assert_eq!(gallery.depth("day_2"), 1);
+
+	// Kept unless requested
+	gallery.remove_duplicates();
+	assert_eq!(gallery.collections["event"].pictures.len(), FC.in1);
+
+	// The pictures of the parent are shown by its first child
+	let removed = gallery.remove_nested_duplicates();
+	assert_eq!(removed.len(), FC.in1);
+	assert!(removed.iter().all(|(key, _)| key == "event"));
+	assert!(gallery.collections["event"].pictures.is_empty());
+	assert_eq!(gallery.collections["day_1"].pictures.len(), FC.in1);
+	assert_eq!(gallery.collection_keys, vec!["day_1", "day_2", "other"]);
+
+	// The archive keeps the tree
+	gallery.create_archive_full(&out_dir).unwrap();
+	let archive = zip::ZipArchive::new(std::fs::File::open(out_dir.join(crate::gallery::FULL_ARCHIVE_PATH)).unwrap()).unwrap();
+	let names: Vec<&str> = archive.file_names().collect();
+	assert!(names.iter().any(|n| n.starts_with("event/day_2/")));
+	assert!(names.iter().any(|n| n.starts_with("other/")));
+	gallery.include_json_data(&out_dir).unwrap();
+
+	// Stored in the gallery data
+	let mut gallery = Gallery::from(&out_dir).unwrap();
+	assert_eq!(gallery.keys(), vec!["event", "day_1", "day_2", "other"]);
+	assert_eq!(gallery.collections["day_2"].parent.as_deref(), Some("event"));
+
+	// Collections cannot be moved below themselves
+	assert!(gallery.set_parent("event", Some("day_1")).is_err());
+	assert!(gallery.set_parent("event", Some("missing")).is_err());
+	gallery.set_parent("other", Some("day_2")).unwrap();
+	assert_eq!(gallery.keys(), vec!["event", "day_1", "day_2", "other"]);
+	assert_eq!(gallery.root_keys, vec!["event"]);
+	assert_eq!(gallery.depth("other"), 2);
+
+	// Children are removed with their parent
+	gallery.remove_collection("day_2").unwrap();
+	assert_eq!(gallery.keys(), vec!["event", "day_1"]);
+	assert_eq!(gallery.collections["event"].children, vec!["day_1"]);
+	assert!(!gallery.collections.contains_key("other"));
+}
+
+//   All collections of galleries before version 3 are top level collections
+#[test]
+fn test_nested_collections_legacy() {
+	let out_dir = temp_gallery("nested_legacy");
+
+	let mut gallery = Gallery::new();
+	gallery.fill(vec![create_input(dir_in(), dir_none(), "Col 1"), create_input(dir_in2(), dir_none(), "Col 2")], false).unwrap();
+	gallery.version = 2;
+	gallery.root_keys.clear();
+	gallery.include_json_data(&out_dir).unwrap();
+
+	let gallery = Gallery::from(&out_dir).unwrap();
+	assert_eq!(gallery.version, crate::gallery::GALLERY_CONFIGURATION_VERSION);
+	assert_eq!(gallery.root_keys, vec!["col_1", "col_2"]);
+	assert_eq!(gallery.keys(), gallery.collection_keys);
+}
+
+// TESTS: Data layout
+
+//   The gallery data is written to data.json and the collection files, and read from any layout
+//       add -o out_dir/ --data-layout collections
+//       add -o out_dir/ --data-layout file
+//       add -o out_dir/ --data-layout inline
+#[test]
+fn test_data_layout() {
+	let out_dir = test_dir("layout");
+	let template = PathBuf::from(&std::env::var("CARGO_MANIFEST_DIR").unwrap()).join("data").join("templates").join("hauer");
+	crate::mi::fs::copy_recursively(&template, &out_dir).unwrap();
+	let data_file = out_dir.join(crate::gallery::DATA_FILE_NAME);
+	let collection_file = |key: &str| out_dir.join(crate::gallery::COLLECTIONS_DIR_NAME).join(format!("{}.json", key));
+	let index = || String::from_utf8(std::fs::read(out_dir.join("index.html")).unwrap()).unwrap();
+
+	let args = ["static_gallery", "add", "-o", out_dir.to_str().unwrap(), "--data-layout", "collections"];
+	let config = Configuration::from_args(args.iter().map(|a| a.into()).collect()).unwrap();
+	assert_eq!(config.render.data_layout, Some(DataLayout::Collections));
+
+	let mut gallery = Gallery::new();
+	gallery.fill(vec![
+		create_input(dir_td().join("formats"), dir_bg(), "Col 1"),
+		create_input(dir_td().join("video"), dir_bg(), "Col 2"),
+	], false).unwrap();
+	let pictures = gallery.collections["col_1"].pictures.len();
+
+	// One file per collection, data.json only has the collections without pictures
+	gallery.set_data_layout(config.render.data_layout);
+	gallery.include_json_data(&out_dir).unwrap();
+	let stub = index().into_bytes().between(crate::gallery::PATTERM_DATA_START, crate::gallery::PATTERM_DATA_END);
+	assert_eq!(
+		serde_json::from_slice::<serde_json::Value>(&stub).unwrap(),
+		serde_json::json!({ "version": crate::gallery::GALLERY_CONFIGURATION_VERSION, "data_file": "data.json" }),
+	);
+	let data: serde_json::Value = serde_json::from_slice(&std::fs::read(&data_file).unwrap()).unwrap();
+	assert_eq!(data["collections"]["col_1"]["data_file"], "c/col_1.json");
+	assert_eq!(data["collections"]["col_1"]["pictures"].as_array().unwrap().len(), 0);
+	assert!(collection_file("col_1").is_file() && collection_file("col_2").is_file());
+
+	let mut read = Gallery::from(&out_dir).unwrap();
+	assert_eq!(read.data_layout, DataLayout::Collections);
+	assert_eq!(read.collections["col_1"].pictures.len(), pictures);
+	assert_eq!(read.collections["col_1"].data_file, None);
+
+	// Files of removed collections are deleted
+	read.remove_collection("col_2").unwrap();
+	read.include_json_data(&out_dir).unwrap();
+	assert!(collection_file("col_1").is_file() && !collection_file("col_2").exists());
+
+	// All data in data.json
+	read.set_data_layout(Some(DataLayout::File));
+	read.include_json_data(&out_dir).unwrap();
+	assert!(!out_dir.join(crate::gallery::COLLECTIONS_DIR_NAME).exists());
+	let mut read = Gallery::from(&out_dir).unwrap();
+	assert_eq!(read.collections["col_1"].pictures.len(), pictures);
+
+	// Embedded again
+	read.set_data_layout(Some(DataLayout::Inline));
+	read.include_json_data(&out_dir).unwrap();
+	assert!(!data_file.exists());
+	assert!(index().contains("\"data_layout\": \"inline\""));
+	assert_eq!(Gallery::from(&out_dir).unwrap().collections["col_1"].pictures.len(), pictures);
+
+	// A missing data file cannot be read
+	read.set_data_layout(Some(DataLayout::File));
+	read.include_json_data(&out_dir).unwrap();
+	std::fs::remove_file(&data_file).unwrap();
+	assert!(Gallery::from(&out_dir).is_err());
+}
+
+// TESTS: Data versions
+
+fn write_gallery_data(out_dir: &PathBuf, data: &serde_json::Value) {
+	let html = format!(
+		"{}{}{}",
+		String::from_utf8_lossy(crate::gallery::PATTERM_DATA_START),
+		data,
+		String::from_utf8_lossy(crate::gallery::PATTERM_DATA_END)
+	);
+	std::fs::write(out_dir.join("index.html"), html).unwrap();
+}
+
+//   Data of older versions is migrated step by step, data of newer versions is refused
+//       status -o out_dir/
+#[test]
+fn test_data_versions() {
+	let out_dir = test_dir("versions");
+	std::fs::create_dir_all(&out_dir).unwrap();
+
+	let mut gallery = Gallery::new();
+	gallery.fill(vec![create_input(dir_td().join("formats"), dir_bg(), "Col 1")], false).unwrap();
+	let current = serde_json::to_value(&gallery).unwrap();
+
+	// Version 3 has the data embedded
+	let mut data = current.clone();
+	data["version"] = serde_json::json!(3);
+	data.as_object_mut().unwrap().remove("data_layout");
+	write_gallery_data(&out_dir, &data);
+	assert_eq!(Gallery::from(&out_dir).unwrap().data_layout, DataLayout::Inline);
+
+	// Version 2 has no tree of collections
+	let mut data = current.clone();
+	data["version"] = serde_json::json!(2);
+	data.as_object_mut().unwrap().remove("root_keys");
+	write_gallery_data(&out_dir, &data);
+	let migrated = Gallery::from(&out_dir).unwrap();
+	assert_eq!(migrated.version, crate::gallery::GALLERY_CONFIGURATION_VERSION);
+	assert_eq!(migrated.root_keys, vec!["col_1"]);
+
+	// Version 1 has no version and numeric checksums
+	let mut data = current.clone();
+	data.as_object_mut().unwrap().remove("version");
+	data.as_object_mut().unwrap().remove("root_keys");
+	data["collections"]["col_1"]["backgrounds"][0]["original_hash"] = serde_json::json!(1234);
+	write_gallery_data(&out_dir, &data);
+	let migrated = Gallery::from(&out_dir).unwrap();
+	assert_eq!(migrated.root_keys, vec!["col_1"]);
+	assert_eq!(migrated.collections["col_1"].backgrounds[0].original_hash, "1234");
+
+	// Newer and invalid versions cannot be read
+	let mut data = current.clone();
+	data["version"] = serde_json::json!(crate::gallery::GALLERY_CONFIGURATION_VERSION + 1);
+	write_gallery_data(&out_dir, &data);
+	let error = Gallery::from(&out_dir).unwrap_err().to_string();
+	assert!(error.contains("only supports up to version"), "{}", error);
+	data["version"] = serde_json::json!("3");
+	write_gallery_data(&out_dir, &data);
+	assert!(Gallery::from(&out_dir).is_err());
+}
+
+//   The published schema matches the gallery data
+//       schema > data/gallery.schema.json
+#[test]
+fn test_data_schema() {
+	let path = PathBuf::from(&std::env::var("CARGO_MANIFEST_DIR").unwrap()).join("data").join("gallery.schema.json");
+	let published: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+	let schema = serde_json::to_value(Gallery::schema()).unwrap();
+	assert!(published == schema, "{} is outdated, create it again with \"static_gallery schema\"", path.to_string_lossy());
+
+	// All stored fields are described
+	let mut gallery = Gallery::new();
+	gallery.fill(vec![create_input(dir_td().join("video"), dir_bg(), "Col 1")], false).unwrap();
+	let data = serde_json::to_value(&gallery).unwrap();
+	let described = |value: &serde_json::Value, definition: &serde_json::Value| {
+		value.as_object().unwrap().keys().all(|k| definition["properties"].get(k).is_some())
+	};
+	assert!(described(&data, &schema));
+	assert!(described(&data["collections"]["col_1"], &schema["definitions"]["Collection"]));
+	assert!(described(&data["collections"]["col_1"]["backgrounds"][0], &schema["definitions"]["Image"]));
+}
+
+// TESTS: Template manifest
+
+//   The manifest of the template declares the files with markers, the variants and default options
+//       build -o out_dir/ -p template_dir/ -c "input01/;backgrounds01/;Col 1"
+//       build -o out_dir/ -p template_dir/ -c "input01/;backgrounds01/;Col 1" --thumb-size 320x180 --jpeg-quality 60
+//       build -o out_dir/ -p template_dir/ -c "input01/;backgrounds01/;Col 1" --formats jpeg
+#[test]
+fn test_template_manifest() {
+	let template_dir = test_dir("template");
+	let out_dir = test_dir("template_out");
+	let template = PathBuf::from(&std::env::var("CARGO_MANIFEST_DIR").unwrap()).join("data").join("templates").join("hauer");
+	crate::mi::fs::copy_recursively(&template, &template_dir).unwrap();
+	std::fs::write(template_dir.join("embed.html"), "<script>const data = /*{{BEGIN:data*/{}/*END:data}}*/;</script>").unwrap();
+	let manifest_path = template_dir.join(crate::gallery::TEMPLATE_MANIFEST_FILE_NAME);
+	let write_manifest = |variants: &str, defaults: &str| {
+		let files = "[[files]]\npath = \"index.html\"\nmarkers = [\"data\"]\n\n[[files]]\npath = \"embed.html\"\nmarkers = [\"data\"]\n";
+		std::fs::write(&manifest_path, format!("{}\n{}\n[defaults]\n{}\n", files, variants, defaults)).unwrap();
+	};
+	let config = |extra: &[&str]| {
+		let collection = format!("{};{};Col 1", dir_in().to_string_lossy(), dir_bg().to_string_lossy());
+		let mut args = vec!["static_gallery", "build", "-o", out_dir.to_str().unwrap(), "-p", template_dir.to_str().unwrap(), "-c", &collection];
+		args.extend_from_slice(extra);
+		Configuration::from_args(args.iter().map(|a| a.into()).collect())
+	};
+
+	// Defaults of the manifest are used unless given
+	write_manifest("[[variants]]\nname = \"thumb\"\nsize = \"800x450\"\nformats = [\"jpeg\", \"webp\"]\n", "quality = 85\ndata_layout = \"file\"");
+	let c = config(&[]).unwrap();
+	assert_eq!(c.render.tumb_size, Some(Resolution{ width: 800, height: 450 }));
+	assert_eq!(c.render.display_size, None);
+	assert_eq!(c.render.formats, Some(vec![Format::Jpeg, Format::Webp]));
+	assert_eq!(c.render.jpeg_quality, Some(85));
+	assert_eq!(c.render.data_layout, Some(DataLayout::File));
+	let c = config(&["--thumb-size", "320x180", "--jpeg-quality", "60"]).unwrap();
+	assert_eq!(c.render.tumb_size, Some(Resolution{ width: 320, height: 180 }));
+	assert_eq!(c.render.jpeg_quality, Some(60));
+
+	// The formats the template loads are required
+	assert!(config(&["--formats", "jpeg"]).is_err());
+	assert!(config(&["--formats", "jpeg,webp,avif"]).is_ok());
+
+	// The gallery data is written to and read from the files with the data marker, they are recorded when the
+	// template is used. The manifest is not copied.
+	let mut gallery = Gallery::new();
+	gallery.set_template(&TemplateManifest::read(&template_dir).unwrap());
+	TemplateManifest::copy(&template_dir, &out_dir).unwrap();
+	gallery.fill(vec![create_input(dir_in(), dir_bg(), "Col 1")], false).unwrap();
+	gallery.include_json_data(&out_dir).unwrap();
+	for file in ["index.html", "embed.html"].iter() {
+		assert!(String::from_utf8(std::fs::read(out_dir.join(file)).unwrap()).unwrap().contains("\"root_keys\""));
+	}
+	assert!(out_dir.join("js").is_dir());
+	assert!(!out_dir.join(crate::gallery::TEMPLATE_MANIFEST_FILE_NAME).exists());
+	let mut read = Gallery::from(&out_dir).unwrap();
+	assert_eq!(read.collections["col_1"].pictures.len(), gallery.collections["col_1"].pictures.len());
+
+	// Without data files the gallery can neither be written nor read
+	read.manifest.data_files.clear();
+	assert!(read.include_json_data(&out_dir).is_err());
+	assert!(Gallery::from(&out_dir).is_err());
+	std::fs::remove_dir_all(&out_dir).unwrap();
+
+	// The pictures are created in the declared directory with the declared suffixes, updates keep them
+	std::fs::write(&manifest_path, "pictures_dir = \"img\"\n\n[[variants]]\nname = \"thumb\"\nsuffix = \"small\"\n").unwrap();
+	let mut gallery = small_gallery();
+	gallery.set_template(&TemplateManifest::read(&template_dir).unwrap());
+	TemplateManifest::copy(&template_dir, &out_dir).unwrap();
+	gallery.fill(vec![create_input(dir_td().join("orientation"), dir_none(), "Col 1")], false).unwrap();
+	build_small(&mut gallery, &out_dir);
+	gallery.include_json_data(&out_dir).unwrap();
+	let basename = &gallery.collections["col_1"].pictures[0].image.basename;
+	assert!(out_dir.join("img").join(format!("{}.small.jpg", basename)).is_file());
+	assert!(out_dir.join("img").join(format!("{}.disp.jpg", basename)).is_file());
+	assert!(!out_dir.join(crate::gallery::PICTURES_DIR_NAME).exists());
+	let mut read = Gallery::from(&out_dir).unwrap();
+	let settings = read.render_settings("");
+	let report = read.collect_garbage(&out_dir, &settings, false).unwrap();
+	assert!(report.orphans.is_empty() && report.missing.is_empty());
+	std::fs::remove_dir_all(&out_dir).unwrap();
+
+	// Invalid manifests and templates that do not match theirs
+	write_manifest("[[variants]]\nname = \"thumb\"\n\n[[variants]]\nname = \"thumb\"\n", "");
+	assert!(config(&[]).is_err());
+	write_manifest("[[variants]]\nname = \"poster\"\n", "");
+	assert!(config(&[]).is_err());
+	write_manifest("", "colors = 3");
+	assert!(config(&[]).is_err());
+	std::fs::write(&manifest_path, "[[files]]\npath = \"../index.html\"\nmarkers = [\"data\"]\n").unwrap();
+	assert!(config(&[]).unwrap_err().to_string().contains("not inside the template directory"));
+	std::fs::write(&manifest_path, "[[files]]\npath = \"/tmp/index.html\"\nmarkers = [\"data\"]\n").unwrap();
+	assert!(config(&[]).unwrap_err().to_string().contains("not inside the template directory"));
+	for dir in ["../p", "."].iter() {
+		std::fs::write(&manifest_path, format!("pictures_dir = \"{}\"\n", dir)).unwrap();
+		assert!(config(&[]).unwrap_err().to_string().contains("is not inside the gallery"));
+	}
+	write_manifest("[[variants]]\nname = \"display\"\nsuffix = \"thumb\"\n", "");
+	assert!(config(&[]).unwrap_err().to_string().contains("have the same suffix"));
+	write_manifest("[[variants]]\nname = \"thumb\"\nsuffix = \"small.jpg\"\n", "");
+	assert!(config(&[]).unwrap_err().to_string().contains("Invalid suffix"));
+	write_manifest("", "");
+	assert!(config(&[]).is_ok());
+	std::fs::write(template_dir.join("embed.html"), "<script>const data = {};</script>").unwrap();
+	assert!(config(&[]).is_err());
+	std::fs::remove_file(template_dir.join("embed.html")).unwrap();
+	assert!(config(&[]).is_err());
 }
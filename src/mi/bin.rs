@@ -15,7 +15,7 @@ pub trait Replace {
 
 impl Replace for Vec<u8> {
 	fn replace(&self, pattern: &[u8], replacement: &[u8]) -> Vec<u8> {
-		if pattern.len() == 0 {
+		if pattern.is_empty() {
 			return self.clone();
 		}
 
@@ -24,7 +24,7 @@ impl Replace for Vec<u8> {
 
 		let mut i = 0;
 		while i < self.len() {
-			if self[i] == pattern[0] {
+			if self[i] == pattern[0] && i + pattern.len() <= self.len() {
 				let mut matching = true;
 				for n in 1..pattern.len() {
 					if self[i + n] != pattern[n] {
@@ -35,7 +35,7 @@ impl Replace for Vec<u8> {
 				if matching {
 					let mut r = Vec::from(replacement);
 					new.append(&mut r);
-					i = i + pattern.len();
+					i += pattern.len();
 				} else {
 					new.push(self[i]);
 				}
@@ -43,14 +43,14 @@ impl Replace for Vec<u8> {
 			} else {
 				new.push(self[i]);
 			}
-			i = i + 1;
+			i += 1;
 		}
 
 		new
 	}
 
 	fn replace_between(&self, pattern_start: &[u8], pattern_end: &[u8], replacement: &[u8]) -> Vec<u8> {
-		if pattern_start.len() == 0 || pattern_end.len() == 0 {
+		if pattern_start.is_empty() || pattern_end.is_empty() {
 			return self.to_vec();
 		}
 
@@ -81,7 +81,7 @@ impl Replace for Vec<u8> {
 		let mut found = false;
 
 		let mut i = start;
-		while i < self.len() {
+		while i + pattern.len() <= self.len() {
 			if self[i] == pattern[0] {
 				let mut matching = true;
 				for n in 1..pattern.len() {
@@ -95,7 +95,7 @@ impl Replace for Vec<u8> {
 					break;
 				}
 			}
-		 	i = i + 1;
+		 	i += 1;
 		}
 
 		if found {
@@ -107,7 +107,7 @@ impl Replace for Vec<u8> {
 
 
 	fn between(&self, pattern_start: &[u8], pattern_end: &[u8]) -> Vec<u8> {
-		if pattern_start.len() == 0 || pattern_end.len() == 0 {
+		if pattern_start.is_empty() || pattern_end.is_empty() {
 			return self.to_vec();
 		}
 
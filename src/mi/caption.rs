@@ -0,0 +1,420 @@
+use exif::{In, Tag};
+use serde::Deserialize;
+use std::fmt;
+use std::io::{BufRead, BufReader, Read};
+use std::path::PathBuf;
+
+use crate::bin::Replace;
+use crate::error::Error;
+use crate::logger;
+
+/// Bytes read from the start of files that are not JPEG to find the embedded metadata
+const EMBEDDED_READ_LIMIT: u64 = 256 * 1024;
+
+/// Title and description of a picture
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Caption {
+	pub title: Option<String>,
+	pub description: Option<String>,
+}
+
+impl Caption {
+	fn new(title: Option<String>, description: Option<String>) -> Caption {
+		Caption{ title: clean(title), description: clean(description) }
+	}
+
+	/// Sets the fields that are not set yet from the other caption
+	fn complete(&mut self, other: Caption) {
+		self.title = self.title.take().or(other.title);
+		self.description = self.description.take().or(other.description);
+	}
+
+	fn is_complete(&self) -> bool {
+		self.title.is_some() && self.description.is_some()
+	}
+}
+
+/// Where captions of pictures are read from
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CaptionSource {
+	/// Text file next to the picture ("IMG_1234.jpg.txt" or "IMG_1234.txt"), the first line is the title, the
+	/// following lines are the description
+	Txt,
+	/// JSON file next to the picture ("IMG_1234.jpg.json" or "IMG_1234.json") with "title" and "description"
+	Json,
+	/// XMP file next to the picture ("IMG_1234.jpg.xmp" or "IMG_1234.xmp") with dc:title and dc:description
+	Xmp,
+	/// XMP packet stored in the picture
+	EmbeddedXmp,
+	/// IPTC object name and caption stored in the picture (JPEG)
+	Iptc,
+	/// Exif image description, used as title
+	Exif,
+}
+
+impl CaptionSource {
+	/// Order in which the sources are used if not given, captions stored in the pictures are only read if enabled
+	pub const DEFAULT: [CaptionSource; 3] = [
+		CaptionSource::Txt,
+		CaptionSource::Json,
+		CaptionSource::Xmp,
+	];
+
+	/// Returns whether the caption is read from a separate file, which is possible for all kinds of files
+	pub fn is_sidecar(&self) -> bool {
+		matches!(self, CaptionSource::Txt | CaptionSource::Json | CaptionSource::Xmp)
+	}
+}
+
+impl fmt::Display for CaptionSource {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			CaptionSource::Txt => write!(f, "txt"),
+			CaptionSource::Json => write!(f, "json"),
+			CaptionSource::Xmp => write!(f, "xmp"),
+			CaptionSource::EmbeddedXmp => write!(f, "embedded-xmp"),
+			CaptionSource::Iptc => write!(f, "iptc"),
+			CaptionSource::Exif => write!(f, "exif"),
+		}
+	}
+}
+
+impl std::str::FromStr for CaptionSource {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<CaptionSource, String> {
+		match s.trim().to_lowercase().as_str() {
+			"txt" => Ok(CaptionSource::Txt),
+			"json" => Ok(CaptionSource::Json),
+			"xmp" => Ok(CaptionSource::Xmp),
+			"embedded-xmp" => Ok(CaptionSource::EmbeddedXmp),
+			"iptc" => Ok(CaptionSource::Iptc),
+			"exif" => Ok(CaptionSource::Exif),
+			_ => Err(format!(
+				"Invalid caption source \"{}\". Valid sources: \"txt\", \"json\", \"xmp\", \"embedded-xmp\", \"iptc\", \"exif\"",
+				s,
+			)),
+		}
+	}
+}
+
+/// Caption sources in the order they are used, given as comma separated list or "none"
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaptionSources(pub Vec<CaptionSource>);
+
+impl Default for CaptionSources {
+	fn default() -> CaptionSources {
+		CaptionSources(CaptionSource::DEFAULT.to_vec())
+	}
+}
+
+impl std::str::FromStr for CaptionSources {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<CaptionSources, String> {
+		if matches!(s.trim().to_lowercase().as_str(), "none" | "") {
+			return Ok(CaptionSources(Vec::new()));
+		}
+
+		let mut sources = Vec::new();
+		for source in s.split(',') {
+			let source = CaptionSource::from_str(source)?;
+			if !sources.contains(&source) {
+				sources.push(source);
+			}
+		}
+		Ok(CaptionSources(sources))
+	}
+}
+
+/// Reads the caption of the picture from the given sources. Each field is taken from the first source that has
+/// it. Sources that cannot be read are reported as warning and skipped. The metadata stored in the picture is
+/// read once for all embedded sources.
+pub fn read_caption(image_path: &PathBuf, sources: &[CaptionSource]) -> Caption {
+	let mut caption = Caption::default();
+	let mut embedded: Option<Option<Embedded>> = None;
+
+	for source in sources {
+		let result = match source {
+			CaptionSource::Txt => read_txt(image_path),
+			CaptionSource::Json => read_json(image_path),
+			CaptionSource::Xmp => read_xmp_sidecar(image_path),
+			CaptionSource::EmbeddedXmp | CaptionSource::Iptc | CaptionSource::Exif => {
+				let embedded = embedded.get_or_insert_with(|| Embedded::read(image_path).map_err(|e| logger::warnln(e.to_string())).ok());
+				match (embedded, source) {
+					(None, _) => Ok(None),
+					(Some(e), CaptionSource::EmbeddedXmp) => e.read_xmp(image_path),
+					(Some(e), CaptionSource::Iptc) => e.read_iptc(image_path),
+					(Some(e), _) => e.read_exif(image_path),
+				}
+			},
+		};
+
+		match result {
+			Ok(Some(c)) => caption.complete(c),
+			Ok(None) => (),
+			Err(e) => logger::warnln(e.to_string()),
+		}
+
+		if caption.is_complete() {
+			break;
+		}
+	}
+
+	caption
+}
+
+/// Returns the first existing sidecar file, named like the picture with the extension appended or replaced
+fn sidecar(image_path: &PathBuf, extension: &str) -> Option<PathBuf> {
+	let mut appended = image_path.clone().into_os_string();
+	appended.push(".");
+	appended.push(extension);
+
+	vec![PathBuf::from(appended), image_path.with_extension(extension)].into_iter().find(|p| p.is_file())
+}
+
+fn read_txt(image_path: &PathBuf) -> Result<Option<Caption>, Error> {
+	let path = match sidecar(image_path, "txt") {
+		Some(p) => p,
+		None => return Ok(None),
+	};
+	let text = std::fs::read_to_string(&path).map_err(|e| Error::io(&path, e))?;
+
+	let text = text.trim();
+	Ok(Some(match text.split_once('\n') {
+		Some((title, description)) => Caption::new(Some(String::from(title)), Some(String::from(description))),
+		None => Caption::new(Some(String::from(text)), None),
+	}))
+}
+
+fn read_json(image_path: &PathBuf) -> Result<Option<Caption>, Error> {
+	#[derive(Deserialize)]
+	struct Sidecar {
+		#[serde(alias = "caption")]
+		title: Option<String>,
+		description: Option<String>,
+	}
+
+	let path = match sidecar(image_path, "json") {
+		Some(p) => p,
+		None => return Ok(None),
+	};
+	let data = std::fs::read(&path).map_err(|e| Error::io(&path, e))?;
+	let sidecar: Sidecar = serde_json::from_slice(&data).map_err(|e| Error::Data{ path: path.clone(), message: e.to_string() })?;
+
+	// Exported photo metadata often uses the file name as title
+	let file_name = image_path.file_name().unwrap_or_default().to_string_lossy();
+	let title = sidecar.title.filter(|t| *t != file_name);
+
+	Ok(Some(Caption::new(title, sidecar.description)))
+}
+
+fn read_xmp_sidecar(image_path: &PathBuf) -> Result<Option<Caption>, Error> {
+	let path = match sidecar(image_path, "xmp") {
+		Some(p) => p,
+		None => return Ok(None),
+	};
+	let xml = std::fs::read_to_string(&path).map_err(|e| Error::io(&path, e))?;
+
+	parse_xmp(&xml).map(Some).map_err(|message| Error::Data{ path, message })
+}
+
+/// Returns dc:title and dc:description of the XMP data. Language alternatives use the default language or the
+/// first one given.
+fn parse_xmp(xml: &str) -> Result<Caption, String> {
+	const DC: &str = "http://purl.org/dc/elements/1.1/";
+	const RDF: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+
+	let document = roxmltree::Document::parse(xml).map_err(|e| format!("Invalid XMP data: {}", e))?;
+
+	let property = |name: &str| {
+		let node = document.descendants().find(|n| n.has_tag_name((DC, name)))?;
+		let items: Vec<roxmltree::Node> = node.descendants().filter(|n| n.has_tag_name((RDF, "li"))).collect();
+		let text = match items.iter().find(|n| n.attribute(("http://www.w3.org/XML/1998/namespace", "lang")) == Some("x-default")) {
+			Some(item) => item.text(),
+			None => items.first().map_or(node.text(), |item| item.text()),
+		};
+		text.map(String::from)
+	};
+
+	Ok(Caption::new(property("title"), property("description")))
+}
+
+/// Metadata stored in a picture. Only the segments that can contain captions are read from JPEG files, only
+/// the start of other files.
+struct Embedded {
+	/// APP1 and APP13 segments of JPEG files or the start of other files
+	data: Vec<u8>,
+	/// Exif data of JPEG files (TIFF structure)
+	exif: Option<Vec<u8>>,
+	jpeg: bool,
+	/// Whether the file is longer than the data read
+	truncated: bool,
+}
+
+impl Embedded {
+	fn read(image_path: &PathBuf) -> Result<Embedded, Error> {
+		let file = std::fs::File::open(image_path).map_err(|e| Error::io(image_path, e))?;
+		let mut reader = BufReader::new(file);
+
+		let start = reader.fill_buf().map_err(|e| Error::io(image_path, e))?;
+		if start.starts_with(&[0xff, 0xd8]) {
+			let mut embedded = Embedded{ data: Vec::new(), exif: None, jpeg: true, truncated: false };
+			return match embedded.read_jpeg(reader) {
+				// Metadata read before the end of an incomplete file is still used
+				Err(e) if e.kind() != std::io::ErrorKind::UnexpectedEof => Err(Error::io(image_path, e)),
+				_ => Ok(embedded),
+			};
+		}
+
+		let mut data = Vec::new();
+		reader.take(EMBEDDED_READ_LIMIT + 1).read_to_end(&mut data).map_err(|e| Error::io(image_path, e))?;
+		let truncated = data.len() as u64 > EMBEDDED_READ_LIMIT;
+		data.truncate(EMBEDDED_READ_LIMIT as usize);
+
+		Ok(Embedded{ data, exif: None, jpeg: false, truncated })
+	}
+
+	/// Reads the segments before the image data, APP1 contains Exif and XMP, APP13 the Photoshop resources
+	fn read_jpeg(&mut self, mut reader: BufReader<std::fs::File>) -> Result<(), std::io::Error> {
+		const APP1: u8 = 0xe1;
+		const APP13: u8 = 0xed;
+		const EXIF: &[u8] = b"Exif\0\0";
+
+		// Start of image
+		reader.read_exact(&mut [0u8; 2])?;
+
+		loop {
+			let mut byte = [0u8; 1];
+			reader.read_exact(&mut byte)?;
+			if byte[0] != 0xff {
+				break;
+			}
+			// Markers may be padded with fill bytes
+			let mut marker = 0xff;
+			while marker == 0xff {
+				reader.read_exact(&mut byte)?;
+				marker = byte[0];
+			}
+			match marker {
+				// Start of scan or end of image, no metadata follows
+				0xda | 0xd9 => break,
+				// Markers without segment
+				0x01 | 0xd0..=0xd8 => continue,
+				_ => (),
+			}
+
+			let mut length = [0u8; 2];
+			reader.read_exact(&mut length)?;
+			let length = (u16::from_be_bytes(length) as usize).saturating_sub(2);
+
+			match marker {
+				APP1 | APP13 => {
+					let mut segment = vec![0u8; length];
+					reader.read_exact(&mut segment)?;
+					match segment.strip_prefix(EXIF) {
+						Some(exif) if marker == APP1 && self.exif.is_none() => self.exif = Some(exif.to_vec()),
+						_ => self.data.extend_from_slice(&segment),
+					}
+				},
+				_ => reader.seek_relative(length as i64)?,
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Reads the XMP packet, which is not compressed in any of the supported formats
+	fn read_xmp(&self, image_path: &PathBuf) -> Result<Option<Caption>, Error> {
+		const START: &[u8] = b"<x:xmpmeta";
+		const END: &[u8] = b"</x:xmpmeta>";
+
+		let start = match self.data.index_of(START, 0) {
+			Ok(i) => i,
+			Err(_) => return Ok(None),
+		};
+		let end = match self.data.index_of(END, start) {
+			Ok(i) => i + END.len(),
+			Err(_) if self.truncated => return Ok(None),
+			Err(_) => return Err(Error::Data{ path: image_path.clone(), message: String::from("Incomplete XMP data") }),
+		};
+
+		let xml = String::from_utf8_lossy(&self.data[start..end]);
+		parse_xmp(&xml).map(Some).map_err(|message| Error::Data{ path: image_path.clone(), message })
+	}
+
+	/// Reads object name (2:05) and caption (2:120) of the IPTC data stored in the Photoshop resources of JPEG files
+	fn read_iptc(&self, image_path: &PathBuf) -> Result<Option<Caption>, Error> {
+		const RESOURCE: &[u8] = b"8BIM\x04\x04";
+
+		let data = &self.data;
+		let start = match data.index_of(RESOURCE, 0) {
+			Ok(i) => i + RESOURCE.len(),
+			Err(_) => return Ok(None),
+		};
+		let invalid = || Error::Data{ path: image_path.clone(), message: String::from("Invalid IPTC data") };
+
+		// Resource name as Pascal string padded to an even length, followed by the size of the resource data
+		let name_length = *data.get(start).ok_or_else(invalid)? as usize;
+		let size_start = start + (name_length + 2) / 2 * 2;
+		let size = data.get(size_start..size_start + 4).ok_or_else(invalid)?;
+		let size = u32::from_be_bytes([size[0], size[1], size[2], size[3]]) as usize;
+		let records = data.get(size_start + 4..size_start + 4 + size).ok_or_else(invalid)?;
+
+		let mut caption = Caption::default();
+		let mut utf8 = false;
+		let mut i = 0;
+		while i + 5 <= records.len() && records[i] == 0x1c {
+			let (record, dataset) = (records[i + 1], records[i + 2]);
+			let length = u16::from_be_bytes([records[i + 3], records[i + 4]]) as usize;
+			let value = records.get(i + 5..i + 5 + length).ok_or_else(invalid)?;
+
+			match (record, dataset) {
+				// Coded character set, ESC % G is UTF-8
+				(1, 90) => utf8 = value == b"\x1b%G",
+				(2, 5) => caption.title = Some(iptc_text(value, utf8)),
+				(2, 120) => caption.description = Some(iptc_text(value, utf8)),
+				_ => (),
+			}
+			i += 5 + length;
+		}
+
+		Ok(Some(Caption::new(caption.title, caption.description)))
+	}
+
+	fn read_exif(&self, image_path: &PathBuf) -> Result<Option<Caption>, Error> {
+		let result = match &self.exif {
+			Some(exif) => exif::Reader::new().read_raw(exif.clone()),
+			None if self.jpeg => return Ok(None),
+			None => exif::Reader::new().read_from_container(&mut std::io::Cursor::new(&self.data)),
+		};
+		let exif_data = match result {
+			Ok(d) => d,
+			Err(exif::Error::NotFound(_)) => return Ok(None),
+			// The exif data may continue after the start of the file that was read
+			Err(_) if self.truncated => return Ok(None),
+			Err(e) => return Err(Error::Exif{ path: image_path.clone(), message: e.to_string() }),
+		};
+
+		let description = match exif_data.get_field(Tag::ImageDescription, In::PRIMARY).map(|f| &f.value) {
+			Some(exif::Value::Ascii(v)) => v.first().map(|s| String::from_utf8_lossy(s).to_string()),
+			_ => None,
+		};
+
+		Ok(Some(Caption::new(description, None)))
+	}
+}
+
+/// Decodes IPTC text, which is Latin-1 unless UTF-8 is declared or the text is valid UTF-8
+fn iptc_text(value: &[u8], utf8: bool) -> String {
+	match std::str::from_utf8(value) {
+		Ok(s) => String::from(s),
+		Err(_) if utf8 => String::from_utf8_lossy(value).to_string(),
+		Err(_) => value.iter().map(|b| *b as char).collect(),
+	}
+}
+
+/// Trims the text, empty texts are treated as missing
+fn clean(text: Option<String>) -> Option<String> {
+	text.map(|t| String::from(t.trim_matches(|c: char| c == '\0' || c.is_whitespace()))).filter(|t| !t.is_empty())
+}
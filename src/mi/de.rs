@@ -0,0 +1,21 @@
+use serde::{Deserialize, Deserializer};
+use std::str::FromStr;
+
+/// Deserializes an optional value from its string representation, for `#[serde(deserialize_with = "...")]` of
+/// values that are written as on the command line (e.g. "960x540")
+pub fn deserialize_from_str<'de, D: Deserializer<'de>, T: FromStr>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+	T::Err: std::fmt::Display,
+{
+	let value = String::deserialize(deserializer)?;
+	T::from_str(&value).map(Some).map_err(serde::de::Error::custom)
+}
+
+/// Deserializes an optional list of values from their string representations
+pub fn deserialize_list_from_str<'de, D: Deserializer<'de>, T: FromStr>(deserializer: D) -> Result<Option<Vec<T>>, D::Error>
+where
+	T::Err: std::fmt::Display,
+{
+	let values = Vec::<String>::deserialize(deserializer)?;
+	values.iter().map(|v| T::from_str(v).map_err(serde::de::Error::custom)).collect::<Result<Vec<T>, D::Error>>().map(Some)
+}
@@ -0,0 +1,116 @@
+use std::fmt;
+use std::path::PathBuf;
+
+/// Exit code when the gallery was created successfully
+pub const EXIT_OK: i32 = 0;
+/// Exit code for files or directories that could not be read or written
+pub const EXIT_IO: i32 = 1;
+/// Exit code for gallery data of an existing gallery that could not be read
+pub const EXIT_DATA: i32 = 2;
+/// Exit code for invalid command line options
+pub const EXIT_CONFIG: i32 = 3;
+/// Exit code for an invalid template
+pub const EXIT_TEMPLATE: i32 = 4;
+/// Exit code for images or videos that could not be decoded or encoded
+pub const EXIT_DECODE: i32 = 5;
+/// Exit code for invalid exif data
+pub const EXIT_EXIF: i32 = 6;
+/// Exit code when the gallery was created, but some pictures or backgrounds failed and were left out
+pub const EXIT_INCOMPLETE: i32 = 7;
+/// Exit code for unexpected internal errors (e.g. a crashed worker thread)
+pub const EXIT_INTERNAL: i32 = 70;
+
+/// Errors of the gallery creation. Errors concerning a file contain the path of that file.
+#[derive(Debug)]
+pub enum Error {
+	/// A file or directory could not be read or written
+	Io { path: PathBuf, source: std::io::Error },
+	/// The gallery data of an existing gallery is missing or invalid
+	Data { path: PathBuf, message: String },
+	/// Invalid command line options
+	Config(String),
+	/// The template is missing required files or placeholders
+	Template { path: PathBuf, message: String },
+	/// An image or video could not be decoded or encoded
+	Decode { path: PathBuf, message: String },
+	/// The exif data of an image could not be read
+	Exif { path: PathBuf, message: String },
+	/// The given number of pictures and backgrounds failed and were left out of the gallery
+	Incomplete(usize),
+	/// Unexpected errors, e.g. a worker thread that panicked
+	Internal(String),
+}
+
+impl Error {
+	pub fn io(path: &PathBuf, source: std::io::Error) -> Error {
+		Error::Io{ path: path.clone(), source }
+	}
+
+	pub fn decode<E: fmt::Display>(path: &PathBuf, error: E) -> Error {
+		Error::Decode{ path: path.clone(), message: error.to_string() }
+	}
+
+	/// Returns the path of the file the error occurred for, if any
+	pub fn path(&self) -> Option<&PathBuf> {
+		match self {
+			Error::Io{ path, .. }
+			| Error::Data{ path, .. }
+			| Error::Template{ path, .. }
+			| Error::Decode{ path, .. }
+			| Error::Exif{ path, .. } => Some(path),
+			Error::Config(_) | Error::Incomplete(_) | Error::Internal(_) => None,
+		}
+	}
+
+	/// Returns the name of the error class, e.g. for machine readable reports
+	pub fn class(&self) -> &'static str {
+		match self {
+			Error::Io{ .. } => "io",
+			Error::Data{ .. } => "data",
+			Error::Config(_) => "config",
+			Error::Template{ .. } => "template",
+			Error::Decode{ .. } => "decode",
+			Error::Exif{ .. } => "exif",
+			Error::Incomplete(_) => "incomplete",
+			Error::Internal(_) => "internal",
+		}
+	}
+
+	/// Returns the documented process exit code for this class of errors
+	pub fn exit_code(&self) -> i32 {
+		match self {
+			Error::Io{ .. } => EXIT_IO,
+			Error::Data{ .. } => EXIT_DATA,
+			Error::Config(_) => EXIT_CONFIG,
+			Error::Template{ .. } => EXIT_TEMPLATE,
+			Error::Decode{ .. } => EXIT_DECODE,
+			Error::Exif{ .. } => EXIT_EXIF,
+			Error::Incomplete(_) => EXIT_INCOMPLETE,
+			Error::Internal(_) => EXIT_INTERNAL,
+		}
+	}
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Error::Io{ path, source } => write!(f, "{}: {}", path.to_string_lossy(), source),
+			Error::Data{ path, message } => write!(f, "{}: Invalid gallery data: {}", path.to_string_lossy(), message),
+			Error::Config(message) => write!(f, "{}", message),
+			Error::Template{ path, message } => write!(f, "{}: Invalid template: {}", path.to_string_lossy(), message),
+			Error::Decode{ path, message } => write!(f, "{}: {}", path.to_string_lossy(), message),
+			Error::Exif{ path, message } => write!(f, "{}: Invalid exif data: {}", path.to_string_lossy(), message),
+			Error::Incomplete(failed) => write!(f, "{} pictures and backgrounds failed and were left out of the gallery", failed),
+			Error::Internal(message) => write!(f, "Internal error: {}", message),
+		}
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Error::Io{ source, .. } => Some(source),
+			_ => None,
+		}
+	}
+}
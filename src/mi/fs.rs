@@ -1,4 +1,6 @@
+use std::io::BufRead;
 use std::path::PathBuf;
+use crate::error::Error;
 use crate::logger::{errorln, debugln};
 
 
@@ -17,19 +19,19 @@ pub fn file_exists(dir: &PathBuf) -> bool {
 }
 
 
-pub fn copy_recursively(from: &PathBuf, to: &PathBuf) {
+pub fn copy_recursively(from: &PathBuf, to: &PathBuf) -> Result<(), Error> {
 	if from.is_file() {
-		std::fs::copy(from, to).unwrap();
+		std::fs::copy(from, to).map_err(|e| Error::io(from, e))?;
 	} else if from.is_dir() {
 		let entries = match std::fs::read_dir(from) {
 			Ok(f) => f,
 			Err(e) => {
-				panic!("{}", e);
+				return Err(Error::io(from, e));
 			}
 		};
 
 		if !to.is_dir() {
-			std::fs::create_dir_all(to).unwrap();
+			std::fs::create_dir_all(to).map_err(|e| Error::io(to, e))?;
 		}
 
 		for entry in entries {
@@ -43,12 +45,15 @@ pub fn copy_recursively(from: &PathBuf, to: &PathBuf) {
 			let mut to = to.clone();
 			to.push(path.file_name().unwrap());
 
-			copy_recursively(&path, &to);
+			copy_recursively(&path, &to)?;
 		}
 
 	} else {
-		panic!("Unsupported type of directory entry: {}", from.to_str().unwrap());
+		let e = std::io::Error::other("Unsupported type of directory entry");
+		return Err(Error::io(from, e));
 	}
+
+	Ok(())
 }
 
 pub fn list_dir(dir: &PathBuf) -> Vec<PathBuf> {
@@ -133,17 +138,28 @@ pub fn sanitize(s: &str) -> String {
 	cleaned
 }
 
-pub fn hash_quick(data: Vec<u8>) -> u64 {
-	let mut n = 0u64;
-
-	for c in data {
-		n += c as u64;
+/// Returns the checksum version 1 of the gallery stored for the images and named their files by, the sum of all
+/// bytes of the file
+pub fn file_checksum_legacy(p: &PathBuf) -> Result<u64, Error> {
+	let mut file = std::io::BufReader::new(std::fs::File::open(p).map_err(|e| Error::io(p, e))?);
+	let mut sum = 0u64;
+	loop {
+		let buffer = file.fill_buf().map_err(|e| Error::io(p, e))?;
+		if buffer.is_empty() {
+			return Ok(sum);
+		}
+		sum += buffer.iter().map(|b| *b as u64).sum::<u64>();
+		let len = buffer.len();
+		file.consume(len);
 	}
-
-	n
 }
 
+/// Returns the BLAKE3 hash of the file content as hex string. The file is read in chunks, so large files
+/// (e.g. videos) are not loaded into memory.
+pub fn file_hash(p: &PathBuf) -> Result<String, Error> {
+	let file = std::fs::File::open(p).map_err(|e| Error::io(p, e))?;
+	let mut hasher = blake3::Hasher::new();
+	hasher.update_reader(file).map_err(|e| Error::io(p, e))?;
 
-pub fn file_hash_quick(p: &PathBuf) -> u64 {
-	hash_quick(std::fs::read(p).unwrap())
+	Ok(hasher.finalize().to_hex().to_string())
 }
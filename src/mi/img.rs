@@ -1,8 +1,11 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::{fmt::{self, format}, path::PathBuf, string};
+use std::{fmt::{self}, io::Read, path::PathBuf};
+use std::convert::TryFrom;
 use image::GenericImageView;
 use exif::{In, Tag};
 
+use crate::error::Error;
 use crate::logger;
 // use crate::debug;
 
@@ -12,112 +15,522 @@ const RESOLUTION_MIN:Resolution = Resolution{ width: 150, height: 150 };
 // const RESOLUTION_QHD:Resolution = Resolution{ width: 2560, height: 1440 };
 // const RESOLUTION_UHD:Resolution = Resolution{ width: 3840, height: 2160 };
 
+// Encoding speed for AVIF images (1 = slowest and best, 10 = fastest)
+const AVIF_SPEED: u8 = 6;
+
+
+/// Orientation of the stored image data as given by the exif orientation tag
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Orientation {
+	/// 1: Stored upright
+	Normal,
+	/// 2: Mirrored horizontally
+	MirrorHorizontal,
+	/// 3: Rotated by 180°
+	Rotate180,
+	/// 4: Mirrored vertically
+	MirrorVertical,
+	/// 5: Mirrored horizontally and rotated by 270° clockwise (rows and columns swapped)
+	Transpose,
+	/// 6: Needs to be rotated by 90° clockwise
+	Rotate90,
+	/// 7: Mirrored horizontally and rotated by 90° clockwise
+	Transverse,
+	/// 8: Needs to be rotated by 270° clockwise
+	Rotate270,
+}
+
+impl Orientation {
+	/// Returns the orientation for the given exif orientation value, unknown values are treated as normal
+	pub fn from_exif(value: u32) -> Orientation {
+		match value {
+			2 => Orientation::MirrorHorizontal,
+			3 => Orientation::Rotate180,
+			4 => Orientation::MirrorVertical,
+			5 => Orientation::Transpose,
+			6 => Orientation::Rotate90,
+			7 => Orientation::Transverse,
+			8 => Orientation::Rotate270,
+			_ => Orientation::Normal,
+		}
+	}
+
+	/// Transforms the stored image data so that it is shown upright
+	pub fn apply(self, image: image::DynamicImage) -> image::DynamicImage {
+		match self {
+			Orientation::Normal => image,
+			Orientation::MirrorHorizontal => image.fliph(),
+			Orientation::Rotate180 => image.rotate180(),
+			Orientation::MirrorVertical => image.flipv(),
+			Orientation::Transpose => image.rotate90().fliph(),
+			Orientation::Rotate90 => image.rotate90(),
+			Orientation::Transverse => image.rotate270().fliph(),
+			Orientation::Rotate270 => image.rotate270(),
+		}
+	}
+}
+
+
+/// Reads the orientation from the exif data of the given image file content. Images without exif data are
+/// treated as stored upright.
+pub fn get_orientation(image_path: &PathBuf, data: &[u8]) -> Result<Orientation, Error> {
+	Ok(parse_exif(image_path, data)?.map_or(Orientation::Normal, |e| orientation(&e)))
+}
+
+/// Parses the exif data of the given image file content
+fn parse_exif(image_path: &PathBuf, data: &[u8]) -> Result<Option<exif::Exif>, Error> {
+	read_exif(image_path, &mut std::io::Cursor::new(data))
+}
+
+fn orientation(exif_data: &exif::Exif) -> Orientation {
+	match exif_data.get_field(Tag::Orientation, In::PRIMARY) {
+		None => Orientation::Normal,
+		Some(field) => Orientation::from_exif(field.value.get_uint(0).unwrap_or(1)),
+	}
+}
+
+/// Exif metadata of a picture that can be published in the gallery data
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ExifData {
+	/// Capture date and time as "YYYY-MM-DDTHH:MM:SS", followed by the time zone offset if known
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub date: Option<String>,
+	/// Camera make and model
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub camera: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub lens: Option<String>,
+	/// Exposure time in seconds, e.g. "1/250" or "2"
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub exposure: Option<String>,
+	/// F-number of the aperture
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub aperture: Option<f32>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub iso: Option<u32>,
+	/// Focal length in millimeters
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub focal_length: Option<f32>,
+}
 
-enum Rotation {
-	None, R90, R180, R270
+impl ExifData {
+	pub fn is_empty(&self) -> bool {
+		*self == ExifData::default()
+	}
+
+	/// Returns the capture time as seconds since 1970-01-01. The time is converted to UTC if the time zone offset
+	/// is stored, otherwise the local time of the camera is used as is.
+	pub fn capture_time(&self) -> Option<i64> {
+		let date = self.date.as_ref()?;
+		let number = |start: usize, len: usize| date.get(start..start + len)?.parse::<i64>().ok();
+
+		let days = days_from_civil(number(0, 4)?, number(5, 2)?, number(8, 2)?);
+		let seconds = days * 86400 + number(11, 2)? * 3600 + number(14, 2)? * 60 + number(17, 2)?;
+		let offset = match (date.get(19..20), number(20, 2), number(23, 2)) {
+			(Some("-"), Some(h), Some(m)) => -(h * 3600 + m * 60),
+			(Some(_), Some(h), Some(m)) => h * 3600 + m * 60,
+			_ => 0,
+		};
+		Some(seconds - offset)
+	}
+
+	/// Removes the values of all fields that are not in the given list
+	pub fn retain(&mut self, fields: &[ExifField]) {
+		let keep = |field: ExifField| fields.contains(&field);
+
+		if !keep(ExifField::Date) { self.date = None; }
+		if !keep(ExifField::Camera) { self.camera = None; }
+		if !keep(ExifField::Lens) { self.lens = None; }
+		if !keep(ExifField::Exposure) { self.exposure = None; }
+		if !keep(ExifField::Aperture) { self.aperture = None; }
+		if !keep(ExifField::Iso) { self.iso = None; }
+		if !keep(ExifField::FocalLength) { self.focal_length = None; }
+	}
 }
 
+/// Exif field that can be published in the gallery data
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ExifField {
+	Date,
+	Camera,
+	Lens,
+	Exposure,
+	Aperture,
+	Iso,
+	FocalLength,
+}
+
+impl ExifField {
+	pub const ALL: [ExifField; 7] = [
+		ExifField::Date,
+		ExifField::Camera,
+		ExifField::Lens,
+		ExifField::Exposure,
+		ExifField::Aperture,
+		ExifField::Iso,
+		ExifField::FocalLength,
+	];
+}
 
-fn get_rotation(image_path: &PathBuf) -> Rotation {
-	let file = std::fs::File::open(image_path).unwrap();
-    let mut bufreader = std::io::BufReader::new(file);
-    let exifreader = exif::Reader::new();
-    let res_exif_data = exifreader.read_from_container(&mut bufreader);
+impl std::str::FromStr for ExifField {
+	type Err = String;
 
-	if res_exif_data.is_err() {
-		logger::warnln(format!("Could not read exif data for: {}", image_path.to_string_lossy()));
-		return Rotation::None;
+	fn from_str(s: &str) -> Result<ExifField, String> {
+		match s.trim().to_lowercase().as_str() {
+			"date" => Ok(ExifField::Date),
+			"camera" => Ok(ExifField::Camera),
+			"lens" => Ok(ExifField::Lens),
+			"exposure" => Ok(ExifField::Exposure),
+			"aperture" => Ok(ExifField::Aperture),
+			"iso" => Ok(ExifField::Iso),
+			"focal-length" | "focal_length" => Ok(ExifField::FocalLength),
+			_ => Err(format!(
+				"Invalid exif field \"{}\". Valid fields: \"date\", \"camera\", \"lens\", \"exposure\", \"aperture\", \"iso\", \"focal-length\"",
+				s,
+			)),
+		}
 	}
-	let exif_data = res_exif_data.unwrap();
+}
 
-	let exif_field = exif_data.get_field(Tag::Orientation, In::PRIMARY);
+/// Selection of published exif fields, given as comma separated list of fields, "all" or "none"
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExifFields(pub Vec<ExifField>);
 
-	let r = match exif_field {
-		None => 1,
-		Some(value) => value.value.get_uint(0).unwrap(),
+impl std::str::FromStr for ExifFields {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<ExifFields, String> {
+		match s.trim().to_lowercase().as_str() {
+			"all" => return Ok(ExifFields(ExifField::ALL.to_vec())),
+			"none" | "" => return Ok(ExifFields(Vec::new())),
+			_ => (),
+		}
+
+		let mut fields = Vec::new();
+		for f in s.split(',') {
+			let field = ExifField::from_str(f)?;
+			if !fields.contains(&field) {
+				fields.push(field);
+			}
+		}
+		Ok(ExifFields(fields))
+	}
+}
+
+/// Reads the exif metadata of the given image file. Returns none for images without exif data or without any of
+/// the supported fields.
+pub fn get_exif_data(image_path: &PathBuf) -> Result<Option<ExifData>, Error> {
+	let file = std::fs::File::open(image_path).map_err(|e| Error::io(image_path, e))?;
+	let mut reader = std::io::BufReader::new(file);
+	Ok(read_exif(image_path, &mut reader)?.as_ref().and_then(exif_metadata))
+}
+
+/// Parses the exif data of the image read from the given reader
+fn read_exif<R: std::io::BufRead + std::io::Seek>(image_path: &PathBuf, reader: &mut R) -> Result<Option<exif::Exif>, Error> {
+	let exifreader = exif::Reader::new();
+	match exifreader.read_from_container(reader) {
+		Ok(d) => Ok(Some(d)),
+		Err(exif::Error::NotFound(_)) => Ok(None),
+		Err(e) => Err(Error::Exif{ path: image_path.clone(), message: e.to_string() }),
+	}
+}
+
+/// Returns the supported fields of the exif data, none if it has none of them
+fn exif_metadata(exif_data: &exif::Exif) -> Option<ExifData> {
+	let field = |tag: Tag| exif_data.get_field(tag, In::PRIMARY).map(|f| &f.value);
+	let text = |tag: Tag| match field(tag) {
+		Some(exif::Value::Ascii(v)) => v.first()
+			.map(|s| String::from_utf8_lossy(s).trim_matches(|c: char| c == '\0' || c.is_whitespace()).to_string())
+			.filter(|s| !s.is_empty()),
+		_ => None,
+	};
+	let rational = |tag: Tag| match field(tag) {
+		Some(exif::Value::Rational(v)) => v.first().copied().filter(|r| r.denom != 0),
+		_ => None,
+	};
+
+	// Cameras often repeat the make in the model name
+	let camera = match (text(Tag::Make), text(Tag::Model)) {
+		(Some(make), Some(model)) if model.starts_with(&make) => Some(model),
+		(Some(make), Some(model)) => Some(format!("{} {}", make, model)),
+		(make, model) => model.or(make),
 	};
 
-	let rotation = match r {
-		1 => Rotation::None,
-		2 => Rotation::None, // Mirror horizontal
-		3 => Rotation::R180,
-		4 => Rotation::None, // Mirror vertical
-		5 => Rotation::None, // Mirror horizontal and rotate 270 CW
-		6 => Rotation::R90,
-		7 => Rotation::None, // Mirror horizontal and rotate 90 CW
-		8 => Rotation::R270,
-		_other => Rotation::None,
+	let exposure = rational(Tag::ExposureTime).filter(|r| r.num != 0).map(|r| match r.num >= r.denom {
+		true => format!("{}", r.to_f64()),
+		false => format!("1/{}", (r.denom as f64 / r.num as f64).round()),
+	});
+
+	let data = ExifData{
+		date: capture_date(exif_data).map(|d| format_exif_date(&d)),
+		camera,
+		lens: text(Tag::LensModel),
+		exposure,
+		aperture: rational(Tag::FNumber).map(|r| (r.to_f32() * 10.0).round() / 10.0),
+		iso: field(Tag::PhotographicSensitivity).and_then(|v| v.get_uint(0)),
+		focal_length: rational(Tag::FocalLength).map(|r| (r.to_f32() * 10.0).round() / 10.0),
 	};
 
+	Some(data).filter(|d| !d.is_empty())
+}
 
-	// print!("Rotation: ");
-	// print!("{}", image_path.to_str().unwrap());
-	// print!(" - ");
-	// match rotation {
-	// 	Rotation::None => print!("None"),
-	// 	Rotation::R180 => print!("180"),
-	// 	Rotation::R270 => print!("270"),
-	// 	Rotation::R90  => print!("90"),
-	// };
-	// println!("");
-
-	rotation
-}
-
-pub fn resize(source: &PathBuf, target: &PathBuf, resolution: Resolution, quality: u8, method: &String) {
-	let method = match method.as_str() {
-		"lanczos3" => image::imageops::FilterType::Lanczos3,
-		"gaussian" => image::imageops::FilterType::Gaussian,
-		"nearest" => image::imageops::FilterType::Nearest,
-		"cubic" => image::imageops::FilterType::CatmullRom,
-		"linear"=> image::imageops::FilterType::Triangle,
-		_ => panic!("Invalid resize method: {}", method),
+/// Reads the capture time of the given image file from its exif data, as seconds since 1970-01-01
+pub fn get_capture_time(image_path: &PathBuf) -> Result<Option<i64>, Error> {
+	Ok(get_exif_data(image_path)?.and_then(|e| e.capture_time()))
+}
+
+/// Returns the original date and time of the picture (DateTimeOriginal with OffsetTimeOriginal), or the date
+/// and time of the file if the original is not stored
+fn capture_date(exif_data: &exif::Exif) -> Option<exif::DateTime> {
+	let field = |tag: Tag| match exif_data.get_field(tag, In::PRIMARY).map(|f| &f.value) {
+		Some(exif::Value::Ascii(v)) => v.first().map(|s| s.as_slice()),
+		_ => None,
 	};
 
-	let mut image = image::open(source).unwrap();
+	if let Some(mut date) = field(Tag::DateTimeOriginal).and_then(|s| exif::DateTime::from_ascii(s).ok()) {
+		if let Some(offset) = field(Tag::OffsetTimeOriginal) {
+			date.parse_offset(offset).ok();
+		}
+		return Some(date);
+	}
+
+	let mut date = field(Tag::DateTime).and_then(|s| exif::DateTime::from_ascii(s).ok())?;
+	if let Some(offset) = field(Tag::OffsetTime) {
+		date.parse_offset(offset).ok();
+	}
+	Some(date)
+}
 
-	image = match get_rotation(source) {
-		Rotation::R90 => image.rotate90(),
-		Rotation::R180 => image.rotate180(),
-		Rotation::R270 => image.rotate270(),
-		Rotation::None => image,
+/// Returns the number of days since 1970-01-01 of the given date of the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+	let year = if month <= 2 { year - 1 } else { year };
+	let era = if year >= 0 { year } else { year - 399 } / 400;
+	let year_of_era = year - era * 400;
+	let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+	let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+	era * 146097 + day_of_era - 719468
+}
+
+fn format_exif_date(d: &exif::DateTime) -> String {
+	let date = format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}", d.year, d.month, d.day, d.hour, d.minute, d.second);
+	match d.offset {
+		Some(o) => format!("{}{}{:02}:{:02}", date, if o < 0 { '-' } else { '+' }, o.abs() / 60, o.abs() % 60),
+		None => date,
+	}
+}
+
+fn filter_type(method: &str) -> Result<image::imageops::FilterType, Error> {
+	match method {
+		"lanczos3" => Ok(image::imageops::FilterType::Lanczos3),
+		"gaussian" => Ok(image::imageops::FilterType::Gaussian),
+		"nearest" => Ok(image::imageops::FilterType::Nearest),
+		"cubic" => Ok(image::imageops::FilterType::CatmullRom),
+		"linear"=> Ok(image::imageops::FilterType::Triangle),
+		_ => Err(Error::Config(format!("Invalid resize method: {}", method))),
+	}
+}
+
+/// Input formats that can be used as pictures
+const INPUT_FORMATS: [image::ImageFormat; 6] = [
+	image::ImageFormat::Jpeg,
+	image::ImageFormat::Png,
+	image::ImageFormat::Tiff,
+	image::ImageFormat::WebP,
+	image::ImageFormat::Gif,
+	image::ImageFormat::Bmp,
+];
+
+/// Returns whether the file is an image in one of the supported input formats. The format is detected by the
+/// content (magic bytes) of the file, the file extension is ignored.
+pub fn is_image(path: &PathBuf) -> bool {
+	let mut header = [0u8; 16];
+	let read = match std::fs::File::open(path).and_then(|mut f| f.read(&mut header)) {
+		Ok(n) => n,
+		Err(_) => return false,
 	};
 
-	let ratio = image.width() as f64 / image.height() as f64;
-	let mut width = resolution.width as f64;
-	let mut height = resolution.height as f64;
+	match image::guess_format(&header[..read]) {
+		Ok(format) => INPUT_FORMATS.contains(&format),
+		Err(_) => false,
+	}
+}
+
+/// Blends images with transparency onto the matte color as the output formats are created without alpha channel.
+/// Images with more than 8 bits per channel are converted to 8 bits.
+pub fn flatten(image: image::DynamicImage, matte: Color) -> image::DynamicImage {
+	if !image.color().has_alpha() {
+		return match image {
+			image::DynamicImage::ImageRgb8(_) | image::DynamicImage::ImageLuma8(_) => image,
+			_ => image::DynamicImage::ImageRgb8(image.to_rgb8()),
+		};
+	}
+
+	let rgba = image.to_rgba8();
+	let mut rgb = image::RgbImage::new(rgba.width(), rgba.height());
+	for (x, y, pixel) in rgba.enumerate_pixels() {
+		let alpha = pixel[3] as u32;
+		let blend = |c: u8, m: u8| ((c as u32 * alpha + m as u32 * (255 - alpha) + 127) / 255) as u8;
+		rgb.put_pixel(x, y, image::Rgb([
+			blend(pixel[0], matte.red),
+			blend(pixel[1], matte.green),
+			blend(pixel[2], matte.blue),
+		]));
+	}
+
+	image::DynamicImage::ImageRgb8(rgb)
+}
+
+/// Reads and decodes the image and applies the orientation stored in its exif data. Invalid exif data is
+/// reported as warning, the image is used as stored in that case.
+pub fn open(source: &PathBuf) -> Result<image::DynamicImage, Error> {
+	open_with_exif(source).map(|(image, _)| image)
+}
+
+/// Opens the image like `open` and returns the supported fields of its exif data as well, which is only parsed
+/// once for both
+pub fn open_with_exif(source: &PathBuf) -> Result<(image::DynamicImage, Option<ExifData>), Error> {
+	let data = std::fs::read(source).map_err(|e| Error::io(source, e))?;
+	let image = image::load_from_memory(&data).map_err(|e| Error::decode(source, e))?;
+
+	let exif_data = parse_exif(source, &data).unwrap_or_else(|e| {
+		logger::warnln(e.to_string());
+		None
+	});
+	let orientation = exif_data.as_ref().map_or(Orientation::Normal, orientation);
+
+	Ok((orientation.apply(image), exif_data.as_ref().and_then(exif_metadata)))
+}
+
+/// Decodes the whole image to check it is complete and valid
+pub fn check(path: &PathBuf) -> Result<(), Error> {
+	image::open(path).map(|_| ()).map_err(|e| Error::decode(path, e))
+}
+
+/// Returns the perceptual difference hash (dHash) of the image. Each bit tells whether a pixel of a 9x8 grayscale
+/// version is darker than its right neighbour, so the same picture with different size or compression gets a hash
+/// with only a few differing bits.
+pub fn dhash(image: &image::DynamicImage) -> u64 {
+	let small = image.thumbnail_exact(9, 8).to_luma8();
+
+	let mut hash = 0u64;
+	for y in 0..8 {
+		for x in 0..8 {
+			hash <<= 1;
+			if small.get_pixel(x, y)[0] < small.get_pixel(x + 1, y)[0] {
+				hash |= 1;
+			}
+		}
+	}
 
-	if width / ratio > resolution.height as f64 {
-		height = width / ratio;
+	hash
+}
+
+/// Returns the number of differing bits of two perceptual hashes
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+	(a ^ b).count_ones()
+}
+
+/// Returns the size of the image scaled to cover the given resolution while keeping its aspect ratio
+fn cover_size(width: u32, height: u32, resolution: Resolution) -> (u32, u32) {
+	let ratio = width as f64 / height as f64;
+	let mut new_width = resolution.width as f64;
+	let mut new_height = resolution.height as f64;
+
+	if new_width / ratio > new_height {
+		new_height = new_width / ratio;
 	} else {
-		width = height / ratio;
+		new_width = new_height * ratio;
 	}
 
-	let new_image = image::imageops::resize(&image, width as u32, height as u32, method);
+	(new_width.round() as u32, new_height.round() as u32)
+}
+
+fn encode(image: &image::DynamicImage, target: &PathBuf, format: Format, quality: u8) -> Result<(), Error> {
+	match format {
+		Format::Jpeg => {
+			let mut out = std::fs::File::create(target).map_err(|e| Error::io(target, e))?;
+			let mut enc = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut out, quality);
+			enc.encode_image(image).map_err(|e| Error::decode(target, e))
+		},
+		Format::Webp => {
+			let rgb = image.to_rgb8();
+			let data = webp::Encoder::from_rgb(&rgb, rgb.width(), rgb.height()).encode(quality as f32);
+			std::fs::write(target, &*data).map_err(|e| Error::io(target, e))
+		},
+		Format::Avif => {
+			let rgb = image.to_rgb8();
+			let pixels: Vec<ravif::RGB8> = rgb.pixels().map(|p| ravif::RGB8::new(p[0], p[1], p[2])).collect();
+			let data = ravif::Encoder::new()
+				.with_quality(quality as f32)
+				.with_speed(AVIF_SPEED)
+				.encode_rgb(ravif::Img::new(&pixels[..], rgb.width() as usize, rgb.height() as usize))
+				.map_err(|e| Error::decode(target, e))?;
+			std::fs::write(target, data.avif_file).map_err(|e| Error::io(target, e))
+		},
+	}
+}
 
-	let mut out = std::fs::File::create(target).unwrap();
-	let mut enc = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut out, quality);
-	enc.encode_image(&new_image).unwrap();
+/// A version of an image written to the target path. Variants without resolution are written in full size.
+#[derive(Debug, Clone)]
+pub struct Variant {
+	pub target: PathBuf,
+	pub resolution: Option<Resolution>,
+	pub format: Format,
+}
 
+/// Properties of the source image read while creating its variants
+#[derive(Debug, Clone)]
+pub struct SourceInfo {
+	/// Perceptual hash of the decoded image
+	pub dhash: u64,
+	/// Supported fields of the exif data
+	pub exif: Option<ExifData>,
 }
 
-pub fn recode(source: &PathBuf, target: &PathBuf, quality: u8) {
-	let mut image = image::open(source).unwrap();
+/// Creates all variants of the source image. The image is only read and decoded once, the variants are created
+/// from the largest to the smallest, each one resized from the previous one if that is large enough.
+/// Transparent parts of the image are filled with the matte color.
+pub fn create_variants(source: &PathBuf, variants: &[Variant], quality: u8, method: &str, matte: Color) -> Result<SourceInfo, Error> {
+	let filter = filter_type(method)?;
+	let (image, exif) = open_with_exif(source)?;
+	let info = SourceInfo{ dhash: dhash(&image), exif };
+	let original = flatten(image, matte);
+
+	let mut sized: Vec<(&Variant, (u32, u32))> = variants
+		.iter()
+		.map(|v| {
+			let size = match v.resolution {
+				Some(res) => cover_size(original.width(), original.height(), res),
+				None => (original.width(), original.height()),
+			};
+			(v, size)
+		})
+		.collect();
+	sized.sort_by_key(|(_, (width, height))| std::cmp::Reverse(*width as u64 * *height as u64));
+
+	let mut previous: Option<image::DynamicImage> = None;
+	for (variant, (width, height)) in sized {
+		// Enlarging a smaller version would lose quality, use the original in that case
+		let base = match &previous {
+			Some(p) if p.width() >= width && p.height() >= height => p,
+			_ => &original,
+		};
+
+		if base.width() == width && base.height() == height {
+			encode(base, &variant.target, variant.format, quality)?;
+		} else {
+			let resized = base.resize_exact(width, height, filter);
+			encode(&resized, &variant.target, variant.format, quality)?;
+			previous = Some(resized);
+		}
+	}
 
-	image = match get_rotation(source) {
-		Rotation::R90 => image.rotate90(),
-		Rotation::R180 => image.rotate180(),
-		Rotation::R270 => image.rotate270(),
-		Rotation::None => image,
-	};
-	
-	let mut out = std::fs::File::create(target).unwrap();
-	let mut enc = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut out, quality);
-	enc.encode_image(&image).unwrap();
+	Ok(info)
 }
 
 
-#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct Resolution {
 	pub width: u32,
 	pub height: u32
@@ -157,9 +570,12 @@ impl Resolution {
 		}
 	}
 
-	// pub fn to_string(&self) -> String {
-	// 	format!("{}x{}", self.width, self.height)
-	// }
+}
+
+impl fmt::Display for Resolution {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}x{}", self.width, self.height)
+	}
 }
 
 impl std::str::FromStr for Resolution {
@@ -172,4 +588,109 @@ impl std::str::FromStr for Resolution {
 }
 
 
+/// Output file format of the created image versions
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum Format {
+	#[serde(rename = "jpg", alias = "jpeg")]
+	Jpeg,
+	#[serde(rename = "webp")]
+	Webp,
+	#[serde(rename = "avif")]
+	Avif,
+}
+
+impl Format {
+	/// The file extension used for this format
+	pub fn extension(&self) -> &'static str {
+		match self {
+			Format::Jpeg => "jpg",
+			Format::Webp => "webp",
+			Format::Avif => "avif",
+		}
+	}
+}
+
+impl fmt::Display for Format {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.extension())
+	}
+}
+
+impl std::str::FromStr for Format {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Format, String> {
+		match s.trim().to_lowercase().as_str() {
+			"jpeg" | "jpg" => Ok(Format::Jpeg),
+			"webp" => Ok(Format::Webp),
+			"avif" => Ok(Format::Avif),
+			_ => Err(format!("Invalid format \"{}\". Valid formats: \"jpeg\", \"webp\", \"avif\"", s)),
+		}
+	}
+}
+
+
+/// Color used to fill transparent parts of images
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct Color {
+	pub red: u8,
+	pub green: u8,
+	pub blue: u8,
+}
+
+impl Color {
+	pub const WHITE: Color = Color{ red: 255, green: 255, blue: 255 };
+}
+
+impl fmt::Display for Color {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "#{:02x}{:02x}{:02x}", self.red, self.green, self.blue)
+	}
+}
+
+impl std::str::FromStr for Color {
+	type Err = String;
+
+	/// Parses colors in the hexadecimal format "#rrggbb" (the leading "#" is optional)
+	fn from_str(s: &str) -> Result<Color, String> {
+		let hex = s.trim().trim_start_matches('#');
+		if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+			return Err(format!("Invalid color \"{}\", must be in format #rrggbb", s));
+		}
+
+		let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+
+		Ok(Color{
+			red: channel(0),
+			green: channel(2),
+			blue: channel(4),
+		})
+	}
+}
+
+/// Stored in the format "#rrggbb"
+impl JsonSchema for Color {
+	fn schema_name() -> String {
+		String::from("Color")
+	}
+
+	fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+		String::json_schema(gen)
+	}
+}
+
+impl From<Color> for String {
+	fn from(color: Color) -> String {
+		color.to_string()
+	}
+}
+
+impl TryFrom<String> for Color {
+	type Error = String;
+
+	fn try_from(s: String) -> Result<Color, String> {
+		s.parse()
+	}
+}
 
@@ -1,6 +1,12 @@
-#[macro_use]
+#![allow(clippy::ptr_arg)]
 
+pub mod error;
 pub mod img;
 pub mod fs;
 pub mod logger;
-pub mod bin;
\ No newline at end of file
+pub mod bin;
+pub mod caption;
+pub mod video;
+pub mod de;
+
+mod test;
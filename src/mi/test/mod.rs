@@ -0,0 +1,425 @@
+#![cfg(test)]
+
+use crate::caption::{Caption, CaptionSource, CaptionSources};
+use crate::error::Error;
+use crate::img::{Color, ExifData, ExifField, ExifFields, Orientation};
+use crate::video::VideoInfo;
+use image::GenericImageView;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+fn dir_orientation() -> PathBuf {
+	PathBuf::from(&std::env::var("CARGO_MANIFEST_DIR").unwrap())
+		.join("data")
+		.join("test")
+		.join("orientation")
+}
+
+fn fixture(orientation: u32) -> PathBuf {
+	dir_orientation().join(format!("orientation_{}.jpg", orientation))
+}
+
+// The fixtures contain the same picture stored with each exif orientation. Shown upright it is 32x16 pixels
+// large and has a red top left, a green top right, a blue bottom left and a white bottom right quarter.
+fn assert_upright(orientation: u32) {
+	let image = crate::img::open(&fixture(orientation)).unwrap();
+
+	assert_eq!((image.width(), image.height()), (32, 16), "Wrong size for orientation {}", orientation);
+
+	let expected = [
+		((8, 4), [255, 0, 0]),
+		((24, 4), [0, 255, 0]),
+		((8, 12), [0, 0, 255]),
+		((24, 12), [255, 255, 255]),
+	];
+
+	for ((x, y), color) in expected.iter() {
+		let pixel = image.get_pixel(*x, *y);
+		for c in 0..3 {
+			let diff = (pixel[c] as i16 - color[c] as i16).abs();
+			assert!(diff < 32, "Wrong color {:?} at {}x{} for orientation {}, expected {:?}", pixel, x, y, orientation, color);
+		}
+	}
+}
+
+// TESTS: Orientation
+
+#[test]
+fn test_orientation_from_exif() {
+	assert_eq!(Orientation::from_exif(1), Orientation::Normal);
+	assert_eq!(Orientation::from_exif(2), Orientation::MirrorHorizontal);
+	assert_eq!(Orientation::from_exif(3), Orientation::Rotate180);
+	assert_eq!(Orientation::from_exif(4), Orientation::MirrorVertical);
+	assert_eq!(Orientation::from_exif(5), Orientation::Transpose);
+	assert_eq!(Orientation::from_exif(6), Orientation::Rotate90);
+	assert_eq!(Orientation::from_exif(7), Orientation::Transverse);
+	assert_eq!(Orientation::from_exif(8), Orientation::Rotate270);
+
+	// Invalid values are ignored
+	assert_eq!(Orientation::from_exif(0), Orientation::Normal);
+	assert_eq!(Orientation::from_exif(9), Orientation::Normal);
+}
+
+#[test]
+fn test_orientation_read_from_file() {
+	for o in 1..=8 {
+		let path = fixture(o);
+		let data = std::fs::read(&path).unwrap();
+		assert_eq!(crate::img::get_orientation(&path, &data).unwrap(), Orientation::from_exif(o));
+	}
+}
+
+#[test]
+fn test_orientation_normal() {
+	assert_upright(1);
+}
+
+#[test]
+fn test_orientation_mirror_horizontal() {
+	assert_upright(2);
+}
+
+#[test]
+fn test_orientation_rotate_180() {
+	assert_upright(3);
+}
+
+#[test]
+fn test_orientation_mirror_vertical() {
+	assert_upright(4);
+}
+
+#[test]
+fn test_orientation_transpose() {
+	assert_upright(5);
+}
+
+#[test]
+fn test_orientation_rotate_90() {
+	assert_upright(6);
+}
+
+#[test]
+fn test_orientation_transverse() {
+	assert_upright(7);
+}
+
+#[test]
+fn test_orientation_rotate_270() {
+	assert_upright(8);
+}
+
+// TESTS: Input formats
+
+fn dir_formats() -> PathBuf {
+	PathBuf::from(&std::env::var("CARGO_MANIFEST_DIR").unwrap())
+		.join("data")
+		.join("test")
+		.join("formats")
+}
+
+#[test]
+fn test_is_image_by_content() {
+	assert!(crate::img::is_image(&dir_formats().join("transparent.png")));
+	assert!(crate::img::is_image(&dir_formats().join("scan.tif")));
+	assert!(crate::img::is_image(&dir_formats().join("drawing.bmp")));
+	assert!(crate::img::is_image(&dir_formats().join("animation.gif")));
+	assert!(crate::img::is_image(&fixture(1)));
+
+	// The extension is ignored
+	assert!(crate::img::is_image(&dir_formats().join("screenshot.dat")));
+	assert!(!crate::img::is_image(&dir_formats().join("notes.jpg")));
+
+	assert!(!crate::img::is_image(&dir_formats().join("missing.png")));
+	assert!(!crate::img::is_image(&dir_formats()));
+}
+
+#[test]
+fn test_flatten_transparency() {
+	let matte = Color{ red: 0, green: 0, blue: 255 };
+	let image = crate::img::flatten(crate::img::open(&dir_formats().join("transparent.png")).unwrap(), matte);
+
+	assert!(!image.color().has_alpha());
+	// Opaque parts are kept, transparent parts get the matte color
+	assert_eq!(image.get_pixel(2, 2), image::Rgba([255, 0, 0, 255]));
+	assert_eq!(image.get_pixel(12, 2), image::Rgba([0, 0, 255, 255]));
+}
+
+#[test]
+fn test_color_from_str() {
+	assert_eq!(Color::from_str("#ffffff").unwrap(), Color::WHITE);
+	assert_eq!(Color::from_str("10a0Ff").unwrap(), Color{ red: 16, green: 160, blue: 255 });
+	assert_eq!(Color{ red: 16, green: 160, blue: 255 }.to_string(), "#10a0ff");
+
+	assert!(Color::from_str("#fff").is_err());
+	assert!(Color::from_str("#gggggg").is_err());
+}
+
+// TESTS: Videos
+
+fn dir_video() -> PathBuf {
+	PathBuf::from(&std::env::var("CARGO_MANIFEST_DIR").unwrap())
+		.join("data")
+		.join("test")
+		.join("video")
+}
+
+#[test]
+fn test_is_video() {
+	assert!(crate::video::is_video(&dir_video().join("clip.mp4")));
+	assert!(!crate::video::is_video(&dir_video().join("extract_frame.sh")));
+	assert!(!crate::video::is_video(&fixture(1)));
+	assert!(!crate::video::is_video(&dir_video().join("missing.mp4")));
+
+	// Only the brands of the file type box tell videos from other ISO base media files
+	let dir = std::env::temp_dir().join(format!("mi_test_is_video_{}", std::process::id()));
+	std::fs::create_dir_all(&dir).unwrap();
+	let ftyp = |name: &str, brands: &[&[u8; 4]]| {
+		let mut data = ((8 + 4 * brands.len() + 4) as u32).to_be_bytes().to_vec();
+		data.extend_from_slice(b"ftyp");
+		data.extend_from_slice(brands[0]);
+		data.extend_from_slice(&[0, 0, 0, 0]);
+		for b in &brands[1..] {
+			data.extend_from_slice(*b);
+		}
+		let path = dir.join(name);
+		std::fs::write(&path, data).unwrap();
+		crate::video::is_video(&path)
+	};
+	assert!(ftyp("video.mp4", &[b"mp42", b"isom", b"mp42"]));
+	assert!(ftyp("video.mov", &[b"qt  ", b"qt  "]));
+	assert!(!ftyp("still.heic", &[b"heic", b"mif1", b"heic"]));
+	assert!(!ftyp("still.avif", &[b"avif", b"avif", b"mif1", b"miaf"]));
+	assert!(!ftyp("audio.m4a", &[b"M4A ", b"M4A ", b"mp42", b"isom"]));
+	assert!(!ftyp("unknown.mp4", &[b"abcd"]));
+	std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_read_video_info() {
+	// The video track follows an audio track
+	let info = crate::video::read_info(&dir_video().join("clip.mp4")).unwrap();
+	assert_eq!(info, VideoInfo{ duration: 2.5, width: 640, height: 360 });
+
+	// The movie box follows the media data and the track is rotated by 90°
+	let info = crate::video::read_info(&dir_video().join("portrait.mp4")).unwrap();
+	assert_eq!(info, VideoInfo{ duration: 2.5, width: 1080, height: 1920 });
+
+	assert!(crate::video::read_info(&fixture(1)).is_err());
+
+	// Truncated file whose movie box claims to be huge
+	let path = std::env::temp_dir().join(format!("mi_test_truncated_{}.mp4", std::process::id()));
+	let mut data = std::fs::read(dir_video().join("clip.mp4")).unwrap()[..36].to_vec();
+	data.extend_from_slice(&[0, 0, 0, 1]);
+	data.extend_from_slice(b"moov");
+	data.extend_from_slice(&u64::MAX.to_be_bytes());
+	std::fs::write(&path, data).unwrap();
+	assert!(crate::video::read_info(&path).is_err());
+	std::fs::remove_file(&path).unwrap();
+}
+
+// TESTS: Hashes
+
+#[test]
+fn test_file_hash() {
+	let dir = std::env::temp_dir().join(format!("mi_test_hash_{}", std::process::id()));
+	std::fs::create_dir_all(&dir).unwrap();
+
+	// Same bytes in a different order
+	std::fs::write(dir.join("a"), [1u8, 2, 3]).unwrap();
+	std::fs::write(dir.join("b"), [3u8, 2, 1]).unwrap();
+	std::fs::write(dir.join("c"), [1u8, 2, 3]).unwrap();
+
+	let a = crate::fs::file_hash(&dir.join("a")).unwrap();
+	assert_eq!(a.len(), 64);
+	assert!(a.chars().all(|c| c.is_ascii_hexdigit()));
+	assert_ne!(a, crate::fs::file_hash(&dir.join("b")).unwrap());
+	assert_eq!(a, crate::fs::file_hash(&dir.join("c")).unwrap());
+
+	assert!(crate::fs::file_hash(&dir.join("missing")).is_err());
+
+	std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// TESTS: Perceptual hashes
+
+#[test]
+fn test_dhash_near_duplicates() {
+	let dir_in = PathBuf::from(&std::env::var("CARGO_MANIFEST_DIR").unwrap())
+		.join("data")
+		.join("test")
+		.join("input02");
+	let original = crate::img::open(&dir_in.join("IMG_1167_0013.jpg")).unwrap();
+	let other = crate::img::open(&dir_in.join("IMG_1209_0015.jpg")).unwrap();
+
+	// The same picture smaller and stronger compressed
+	let mut data = Vec::new();
+	let small = original.thumbnail(640, 640);
+	image::codecs::jpeg::JpegEncoder::new_with_quality(&mut data, 30).encode_image(&small).unwrap();
+	let export = image::load_from_memory(&data).unwrap();
+
+	let hash = crate::img::dhash(&original);
+	assert!(crate::img::hamming_distance(hash, crate::img::dhash(&export)) <= 5);
+	assert!(crate::img::hamming_distance(hash, crate::img::dhash(&other)) > 5);
+	assert_eq!(crate::img::hamming_distance(0b1011, 0b0110), 3);
+}
+
+// TESTS: Exif metadata
+
+#[test]
+fn test_read_exif_data() {
+	let photo = PathBuf::from(&std::env::var("CARGO_MANIFEST_DIR").unwrap())
+		.join("data")
+		.join("test")
+		.join("input01")
+		.join("IMG_5741.jpg");
+	let exif = crate::img::get_exif_data(&photo).unwrap().unwrap();
+	assert_eq!(exif, ExifData{
+		date: Some(String::from("2016-07-16T11:53:29")),
+		camera: Some(String::from("Canon EOS 6D")),
+		lens: Some(String::from("EF50mm f/1.4 USM")),
+		exposure: Some(String::from("1/1600")),
+		aperture: Some(1.4),
+		iso: Some(100),
+		focal_length: Some(50.0),
+	});
+	// Also returned when the image is decoded
+	assert_eq!(crate::img::open_with_exif(&photo).unwrap().1, Some(exif.clone()));
+
+	// Capture time in seconds since 1970-01-01, in UTC if the time zone is stored
+	assert_eq!(crate::img::get_capture_time(&photo).unwrap(), Some(1468670009));
+	let with_offset = ExifData{ date: Some(String::from("2016-07-16T11:53:29+02:00")), ..exif };
+	assert_eq!(with_offset.capture_time(), Some(1468670009 - 7200));
+
+	// Only the orientation is stored
+	assert_eq!(crate::img::get_exif_data(&fixture(6)).unwrap(), None);
+
+	// No exif data at all
+	assert_eq!(crate::img::get_exif_data(&dir_formats().join("transparent.png")).unwrap(), None);
+}
+
+#[test]
+fn test_exif_fields() {
+	assert_eq!(ExifFields::from_str("all").unwrap().0, ExifField::ALL.to_vec());
+	assert_eq!(ExifFields::from_str("none").unwrap().0, Vec::new());
+	assert_eq!(ExifFields::from_str("date, focal-length,date").unwrap().0, vec![ExifField::Date, ExifField::FocalLength]);
+	assert!(ExifFields::from_str("date,gps").is_err());
+
+	let mut exif = ExifData{
+		date: Some(String::from("2016-07-16T11:53:29")),
+		camera: Some(String::from("Canon EOS 6D")),
+		iso: Some(100),
+		..ExifData::default()
+	};
+	exif.retain(&[ExifField::Camera, ExifField::Lens]);
+	assert_eq!(exif, ExifData{ camera: Some(String::from("Canon EOS 6D")), ..ExifData::default() });
+
+	exif.retain(&[]);
+	assert!(exif.is_empty());
+}
+
+// TESTS: Captions
+
+#[test]
+fn test_caption_embedded() {
+	let photo = PathBuf::from(&std::env::var("CARGO_MANIFEST_DIR").unwrap())
+		.join("data")
+		.join("test")
+		.join("input02")
+		.join("IMG_1209_0015.jpg");
+
+	// Captions stored in the picture are not read by default
+	assert_eq!(crate::caption::read_caption(&photo, &CaptionSource::DEFAULT), Caption::default());
+	let caption = crate::caption::read_caption(&photo, &[CaptionSource::EmbeddedXmp, CaptionSource::Iptc]);
+	assert_eq!(caption, Caption{ title: Some(String::from("Kahului")), description: None });
+	// Also stored as exif image description
+	assert_eq!(crate::caption::read_caption(&photo, &[CaptionSource::Exif]).title, Some(String::from("Kahului")));
+	assert_eq!(crate::caption::read_caption(&photo, &[CaptionSource::Iptc, CaptionSource::Txt]), Caption::default());
+
+	// Only the metadata segments before the image data are read
+	let dir = std::env::temp_dir().join(format!("mi_test_caption_embedded_{}", std::process::id()));
+	std::fs::create_dir_all(&dir).unwrap();
+	let mut data = std::fs::read(fixture(1)).unwrap();
+	data.extend_from_slice(br#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+		<rdf:Description rdf:about="" xmlns:dc="http://purl.org/dc/elements/1.1/"><dc:title>Trailer</dc:title></rdf:Description>
+		</rdf:RDF></x:xmpmeta>"#);
+	std::fs::write(dir.join("IMG_1.jpg"), data).unwrap();
+	assert_eq!(crate::caption::read_caption(&dir.join("IMG_1.jpg"), &[CaptionSource::EmbeddedXmp]), Caption::default());
+
+	std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_caption_sidecars() {
+	let dir = std::env::temp_dir().join(format!("mi_test_captions_{}", std::process::id()));
+	std::fs::create_dir_all(&dir).unwrap();
+	let photo = dir.join("IMG_1.jpg");
+
+	// IPTC object name and caption in the Photoshop resources (APP13) after the start of image
+	let mut records = Vec::new();
+	for (dataset, value) in [(5u8, "Harbour"), (120u8, "Boats in the harbour")] {
+		records.extend_from_slice(&[0x1c, 2, dataset, 0, value.len() as u8]);
+		records.extend_from_slice(value.as_bytes());
+	}
+	let mut resources = b"Photoshop 3.0\0" .to_vec();
+	resources.extend_from_slice(b"8BIM\x04\x04\x00\x00");
+	resources.extend_from_slice(&(records.len() as u32).to_be_bytes());
+	resources.extend_from_slice(&records);
+	let jpeg = std::fs::read(fixture(1)).unwrap();
+	let mut data = vec![0xff, 0xd8, 0xff, 0xed];
+	data.extend_from_slice(&((resources.len() + 2) as u16).to_be_bytes());
+	data.extend_from_slice(&resources);
+	data.extend_from_slice(&jpeg[2..]);
+	std::fs::write(&photo, data).unwrap();
+	assert_eq!(crate::caption::read_caption(&photo, &CaptionSource::DEFAULT), Caption::default());
+	assert_eq!(crate::caption::read_caption(&photo, &[CaptionSource::Txt, CaptionSource::Iptc]), Caption{
+		title: Some(String::from("Harbour")),
+		description: Some(String::from("Boats in the harbour")),
+	});
+
+	std::fs::write(dir.join("IMG_1.jpg.txt"), "Sunset\n\nThe last evening at the harbour.\n").unwrap();
+	std::fs::write(dir.join("IMG_1.json"), r#"{ "title": "IMG_1.jpg", "description": "From the photo export" }"#).unwrap();
+	std::fs::write(dir.join("IMG_1.xmp"), r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+		<rdf:Description rdf:about="" xmlns:dc="http://purl.org/dc/elements/1.1/">
+			<dc:title><rdf:Alt><rdf:li xml:lang="de">Hafen</rdf:li><rdf:li xml:lang="x-default">Harbour at night</rdf:li></rdf:Alt></dc:title>
+		</rdf:Description></rdf:RDF></x:xmpmeta>"#).unwrap();
+
+	let read = |sources: &str| crate::caption::read_caption(&photo, &CaptionSources::from_str(sources).unwrap().0);
+	assert_eq!(read("txt"), Caption{ title: Some(String::from("Sunset")), description: Some(String::from("The last evening at the harbour.")) });
+	// The file name is not used as title
+	assert_eq!(read("json,xmp"), Caption{ title: Some(String::from("Harbour at night")), description: Some(String::from("From the photo export")) });
+	assert_eq!(read("xmp,txt"), Caption{ title: Some(String::from("Harbour at night")), description: Some(String::from("The last evening at the harbour.")) });
+	assert_eq!(read("none"), Caption::default());
+	assert!(CaptionSources::from_str("txt,sidecar").is_err());
+
+	// Invalid sidecars are skipped
+	std::fs::write(dir.join("IMG_1.json"), "{ title: ").unwrap();
+	assert_eq!(read("json,iptc").title, Some(String::from("Harbour")));
+
+	std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// TESTS: Errors
+
+#[test]
+fn test_open_errors() {
+	let notes = dir_formats().join("notes.jpg");
+	match crate::img::open(&notes) {
+		Err(e @ Error::Decode{ .. }) => {
+			assert_eq!(e.path(), Some(&notes));
+			assert_eq!(e.exit_code(), crate::error::EXIT_DECODE);
+		},
+		r => panic!("Unexpected result {:?}", r.map(|_| ())),
+	}
+
+	let missing = dir_formats().join("missing.png");
+	match crate::img::open(&missing) {
+		Err(e @ Error::Io{ .. }) => assert_eq!(e.exit_code(), crate::error::EXIT_IO),
+		r => panic!("Unexpected result {:?}", r.map(|_| ())),
+	}
+
+	// Images without exif data are not an error
+	let png = dir_formats().join("transparent.png");
+	let data = std::fs::read(&png).unwrap();
+	assert_eq!(crate::img::get_orientation(&png, &data).unwrap(), Orientation::Normal);
+}
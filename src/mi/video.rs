@@ -0,0 +1,234 @@
+use crate::error::Error;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+/// Metadata of a video as read from its container
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct VideoInfo {
+	/// Duration in seconds
+	pub duration: f64,
+	pub width: u32,
+	pub height: u32,
+}
+
+/// Brands of the "ftyp" box of MP4 and QuickTime videos
+const VIDEO_BRANDS: [&[u8; 4]; 16] = [
+	b"isom", b"iso2", b"iso3", b"iso4", b"iso5", b"iso6", b"mp41", b"mp42",
+	b"avc1", b"qt  ", b"M4V ", b"mmp4", b"3gp4", b"3gp5", b"3g2a", b"dash",
+];
+
+/// Brands of ISO base media files that are no videos: HEIF/AVIF stills and image sequences, audio
+const OTHER_BRANDS: [&[u8; 4]; 14] = [
+	b"heic", b"heix", b"heim", b"heis", b"hevc", b"hevx", b"mif1", b"msf1",
+	b"miaf", b"avif", b"avis", b"M4A ", b"M4B ", b"M4P ",
+];
+
+/// Returns whether the file is an MP4 (ISO base media) video. The format is detected by the brands of the "ftyp"
+/// box at the start of the file, the file extension is ignored.
+pub fn is_video(path: &PathBuf) -> bool {
+	let mut header = [0u8; 8];
+	let mut file = match std::fs::File::open(path) {
+		Ok(f) => f,
+		Err(_) => return false,
+	};
+	if file.read_exact(&mut header).is_err() || &header[4..8] != b"ftyp" {
+		return false;
+	}
+
+	// Major brand, minor version and compatible brands, the box is small in valid files
+	let size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+	if !(16..=1024).contains(&size) {
+		return false;
+	}
+	let mut content = Vec::new();
+	if file.take(size - 8).read_to_end(&mut content).is_err() || content.len() < 8 {
+		return false;
+	}
+
+	let brands: Vec<&[u8]> = content[..4].chunks(4).chain(content[8..].chunks_exact(4)).collect();
+	brands.iter().any(|b| VIDEO_BRANDS.iter().any(|v| &v[..] == *b))
+		&& !brands.iter().any(|b| OTHER_BRANDS.iter().any(|o| &o[..] == *b))
+}
+
+/// Reads duration and dimensions of an MP4 file from its movie header and its first video track
+pub fn read_info(path: &PathBuf) -> Result<VideoInfo, Error> {
+	let mut file = std::fs::File::open(path).map_err(|e| Error::io(path, e))?;
+	let moov = find_top_level_box(&mut file, b"moov")
+		.map_err(|e| Error::io(path, e))?
+		.ok_or_else(|| Error::decode(path, "No movie box found"))?;
+
+	let mvhd = find_box(&moov, b"mvhd").ok_or_else(|| Error::decode(path, "No movie header found"))?;
+	let duration = parse_mvhd(mvhd).ok_or_else(|| Error::decode(path, "Invalid movie header"))?;
+
+	for trak in boxes(&moov).filter(|(t, _)| t == b"trak").map(|(_, b)| b) {
+		let is_video_track = find_box(trak, b"mdia")
+			.and_then(|mdia| find_box(mdia, b"hdlr"))
+			.map(|hdlr| hdlr.len() >= 12 && &hdlr[8..12] == b"vide")
+			.unwrap_or(false);
+
+		if !is_video_track {
+			continue;
+		}
+
+		let tkhd = find_box(trak, b"tkhd").ok_or_else(|| Error::decode(path, "No track header found"))?;
+		let (width, height) = parse_tkhd(tkhd).ok_or_else(|| Error::decode(path, "Invalid track header"))?;
+
+		return Ok(VideoInfo{ duration, width, height });
+	}
+
+	Err(Error::decode(path, "No video track found"))
+}
+
+/// Creates a still image of the video by running the given command. The placeholders "{input}" and "{output}"
+/// in the command are replaced by the video path and the path of the image to be written. The command is not run
+/// in a shell, arguments are separated by whitespace.
+pub fn extract_frame(command: &str, source: &PathBuf, target: &PathBuf) -> Result<(), Error> {
+	let args: Vec<String> = command
+		.split_whitespace()
+		.map(|arg| {
+			arg.replace("{input}", &source.to_string_lossy())
+				.replace("{output}", &target.to_string_lossy())
+		})
+		.collect();
+
+	if args.is_empty() {
+		return Err(Error::Config(String::from("No command given to extract video frames")));
+	}
+
+	let output = std::process::Command::new(&args[0])
+		.args(&args[1..])
+		.output()
+		.map_err(|e| Error::io(&PathBuf::from(&args[0]), e))?;
+
+	if !output.status.success() {
+		return Err(Error::decode(source, format!(
+			"Extracting frame failed ({}): {}",
+			output.status,
+			String::from_utf8_lossy(&output.stderr).trim()
+		)));
+	}
+
+	if !target.is_file() {
+		return Err(Error::decode(source, format!("Extracting frame did not create {}", target.to_string_lossy())));
+	}
+
+	Ok(())
+}
+
+
+/// Seeks through the top level boxes of the file and returns the content of the first box with the given type
+fn find_top_level_box(file: &mut std::fs::File, box_type: &[u8; 4]) -> std::io::Result<Option<Vec<u8>>> {
+	let file_size = file.metadata()?.len();
+	let mut position = 0u64;
+
+	while position + 8 <= file_size {
+		file.seek(SeekFrom::Start(position))?;
+		let mut header = [0u8; 8];
+		file.read_exact(&mut header)?;
+
+		let mut size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+		let mut header_size = 8u64;
+		if size == 1 {
+			let mut large = [0u8; 8];
+			file.read_exact(&mut large)?;
+			size = u64::from_be_bytes(large);
+			header_size = 16;
+		} else if size == 0 {
+			size = file_size - position;
+		}
+
+		if size < header_size {
+			return Ok(None);
+		}
+		// Sizes of damaged files must not lead to huge allocations
+		if size > file_size - position {
+			return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Box exceeds the end of the file"));
+		}
+
+		if &header[4..8] == box_type {
+			let mut content = vec![0u8; (size - header_size) as usize];
+			file.read_exact(&mut content)?;
+			return Ok(Some(content));
+		}
+
+		position += size;
+	}
+
+	Ok(None)
+}
+
+/// Iterates over the boxes contained in the given data as (type, content)
+fn boxes(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+	let mut position = 0usize;
+
+	std::iter::from_fn(move || {
+		if position + 8 > data.len() {
+			return None;
+		}
+
+		let size = u32::from_be_bytes([data[position], data[position + 1], data[position + 2], data[position + 3]]) as usize;
+		let box_type = [data[position + 4], data[position + 5], data[position + 6], data[position + 7]];
+		let end = if size == 0 { data.len() } else { position + size };
+
+		if (size != 0 && size < 8) || end > data.len() {
+			return None;
+		}
+
+		let content = &data[position + 8..end];
+		position = end;
+		Some((box_type, content))
+	})
+}
+
+fn find_box<'a>(data: &'a [u8], box_type: &[u8; 4]) -> Option<&'a [u8]> {
+	boxes(data).find(|(t, _)| t == box_type).map(|(_, b)| b)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+	let bytes = data.get(offset..offset + 4)?;
+	Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+	Some(((read_u32(data, offset)? as u64) << 32) | read_u32(data, offset + 4)? as u64)
+}
+
+/// Returns the duration in seconds from a movie header box
+fn parse_mvhd(mvhd: &[u8]) -> Option<f64> {
+	let version = *mvhd.first()?;
+
+	let (timescale, duration) = if version == 1 {
+		(read_u32(mvhd, 20)?, read_u64(mvhd, 24)?)
+	} else {
+		(read_u32(mvhd, 12)?, read_u32(mvhd, 16)? as u64)
+	};
+
+	if timescale == 0 {
+		return None;
+	}
+
+	Some(duration as f64 / timescale as f64)
+}
+
+/// Returns the display size from a track header box, rotated tracks (e.g. portrait videos from phones) are swapped
+fn parse_tkhd(tkhd: &[u8]) -> Option<(u32, u32)> {
+	let version = *tkhd.first()?;
+	let matrix_offset = if version == 1 { 52 } else { 40 };
+
+	// The first two values of the transformation matrix are 0 and +-1 for rotations by 90° or 270°
+	let a = read_u32(tkhd, matrix_offset)?;
+	let b = read_u32(tkhd, matrix_offset + 4)?;
+	let rotated = a == 0 && b != 0;
+
+	// Width and height are 16.16 fixed point values
+	let width = read_u32(tkhd, matrix_offset + 36)? >> 16;
+	let height = read_u32(tkhd, matrix_offset + 40)? >> 16;
+
+	if rotated {
+		Some((height, width))
+	} else {
+		Some((width, height))
+	}
+}